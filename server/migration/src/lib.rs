@@ -1,12 +1,20 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20220101_000001_create_table;
+mod m20261019_000001_create_tus_uploads;
+mod m20261019_000002_create_sessions;
+mod m20261019_000003_create_users;
 
 pub struct Migrator;
 
 #[async_trait::async_trait]
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20220101_000001_create_table::Migration)]
+        vec![
+            Box::new(m20220101_000001_create_table::Migration),
+            Box::new(m20261019_000001_create_tus_uploads::Migration),
+            Box::new(m20261019_000002_create_sessions::Migration),
+            Box::new(m20261019_000003_create_users::Migration),
+        ]
     }
 }
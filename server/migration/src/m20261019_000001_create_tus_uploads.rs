@@ -0,0 +1,52 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(TusUploads::Table)
+                    .if_not_exists()
+                    .col(string(TusUploads::Id).primary_key())
+                    .col(big_integer(TusUploads::Length))
+                    .col(big_integer(TusUploads::UploadOffset))
+                    .col(text(TusUploads::Metadata))
+                    .col(timestamp_with_time_zone(TusUploads::ExpiresAt))
+                    .col(text_null(TusUploads::Stored))
+                    .to_owned(),
+            )
+            .await?;
+
+        // `TusServer::remove_expired` looks uploads up by expiry.
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_tus_uploads_expires_at")
+                    .table(TusUploads::Table)
+                    .col(TusUploads::ExpiresAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(TusUploads::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TusUploads {
+    Table,
+    Id,
+    Length,
+    UploadOffset,
+    Metadata,
+    ExpiresAt,
+    Stored,
+}
@@ -1,24 +1,74 @@
 use std::fmt::Debug;
+use std::sync::LazyLock;
 
-use salvo::oapi::{BasicType, Object, extract::*};
+mod models;
 
-use salvo::routing::delete;
-use salvo::{Extractible, prelude::*};
-use sea_orm::ActiveModelBehavior;
+use models::user;
+
+use salvo::oapi::extract::*;
+
+use salvo::prelude::*;
+use sea_orm::prelude::{Date, DateTimeUtc};
+use sea_orm::{ActiveModelTrait, Database, DatabaseConnection, EntityTrait, ModelTrait, Set};
 use serde::{Deserialize, Serialize};
-use thunder::{FromMultipart, Inertia, MultipartValidated, UploadedFile, Validate};
+use thunder::{
+    Abilities, Ability, Auth, AuthHandler, Authenticatable, Authenticate, Authorized, CurrentUser,
+    Forbidden, FromMultipart, Gate, Guard, ImageConversions, Inertia, JsonValidated, LocalDisk,
+    MessageCatalogue, ModelAbilities, MultipartLimits, MultipartStream, MultipartValidated, Policy,
+    QueryValidated, RedirectIfAuthenticated, SeaOrmSessionStore, SeaOrmTusStore,
+    SeaOrmUserProvider, ServeDisk, Session, SessionHandler, Storage, StoredFile, StoredImage,
+    TusServer, UploadedFile, UrlSigner, Validate, ValidateSignature, ValidationDepot,
+    ValidationError, ValidationErrors, VerifyCsrfToken, hash_api_token, new_api_token,
+};
 
 #[endpoint]
-async fn hello(name: FormBody<String>) -> String {
+async fn hello(_name: FormBody<String>) -> String {
     todo!()
 }
-#[endpoint]
-async fn bye(name: MultipartValidated<UserProfile>) {
+// Generated for every stored avatar.
+static AVATAR_CONVERSIONS: LazyLock<ImageConversions> = LazyLock::new(|| {
+    ImageConversions::parse("thumb: 128x128 fit cover webp; preview: 640x640 fit contain")
+        .expect("invalid avatar conversions")
+});
 
-    // format!("Hello, {}!", name.as_deref().unwrap_or("World"))
+// Files only reachable through the signed links `temporary_url` hands out.
+static PRIVATE_DISK: LazyLock<LocalDisk> =
+    LazyLock::new(|| LocalDisk::new("storage/private", "/private").signed(signer()));
+
+fn signer() -> UrlSigner {
+    UrlSigner::from_env().expect("APP_KEY must be set")
+}
+
+// Reports are for the staff, see `gate`.
+struct ViewReports;
+
+impl Ability for ViewReports {
+    type User = user::Model;
+
+    fn allows(gate: &Gate<user::Model>, user: &user::Model) -> bool {
+        gate.allows(user, "view-reports")
+    }
+}
+
+#[handler]
+async fn report_link(_staff: Authorized<ViewReports>) -> Result<String, StatusError> {
+    PRIVATE_DISK
+        .temporary_url("reports/latest.txt", chrono::Duration::minutes(10))
+        .map_err(|error| StatusError::internal_server_error().brief(error.to_string()))
+}
+
+#[endpoint]
+async fn bye(profile: MultipartValidated<UserProfile>) -> Result<Json<StoredImage>, StatusError> {
+    let disk = LocalDisk::new("storage/public", "/storage");
+    let avatar = profile
+        .avatar
+        .store_image("avatars", &disk, &AVATAR_CONVERSIONS)
+        .await
+        .map_err(|error| StatusError::internal_server_error().brief(error.to_string()))?;
+    Ok(Json(avatar))
 }
 #[endpoint]
-async fn file_t(data: FormBody<Waza>, file_name: FormFile) {
+async fn file_t(_data: FormBody<Waza>, _file_name: FormFile) {
 
     // format!("Hello, {}!", name.as_deref().unwrap_or("World"))
 }
@@ -34,53 +84,478 @@ struct Waza {
     name: String,
 }
 
-#[derive(Debug, ToSchema)]
+#[derive(Debug, ToSchema, Validate, FromMultipart)]
 pub struct UserProfile {
+    #[validate(length(min = 3))]
     pub username: String,
+    #[validate(sanitize(trim, lowercase), email)]
     pub email: String,
+    #[validate(
+        file(max_size = "2MB", mime = ["image/png", "image/jpeg"]),
+        image(min_width = 128, min_height = 128, ratio = "1/1")
+    )]
     pub avatar: UploadedFile, // Para un solo archivo
+    #[multipart(rename = "photos[]")]
+    #[validate(file(max_size = "5MB", extensions = ["png", "jpg", "jpeg", "webp"]))]
+    pub photos: Vec<UploadedFile>,
+    #[validate(min = 13)]
+    pub age: Option<u8>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Validate)]
+struct Name {
+    #[validate(email, min = 34, max = 3, custom = not_reserved)]
+    field: String,
+}
+
+fn not_reserved(value: &str) -> Result<(), ValidationError> {
+    if value == "admin@localhost" {
+        return Err(ValidationError::new(
+            "reserved",
+            "This address is reserved.",
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize, Validate)]
+struct CreatePost {
+    #[validate(sanitize(strip_tags, collapse_whitespace, trim))]
+    #[validate(unique(entity = models::prelude::Post, column = "title"))]
+    title: String,
+    #[validate(required)]
+    #[validate(length(min = 20), groups = ["create"])]
+    text: String,
+    #[validate(nullable, length(max = 40))]
+    subtitle: Option<String>,
+}
+
+thunder::validation_group!(OnCreate = "create");
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, Validate)]
+struct CreatePosts {
+    #[validate(nested)]
+    posts: Vec<CreatePost>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, Validate)]
+#[validate(different(field = "username", other = "password"))]
+struct Register {
+    #[validate(
+        length(min = 3, max = 20),
+        min(value = 3, message = "Pick a longer name.")
+    )]
+    username: String,
+    password: String,
+    #[validate(must_match = "password")]
+    password_confirmation: String,
+    ship: bool,
+    #[validate(required_if(field = "ship", value = true))]
+    address: Option<String>,
+    #[validate(required_with = "address")]
+    zip: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+struct EmailAddress(#[validate(sanitize(trim, lowercase), email)] String);
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, Validate)]
+enum Contact {
+    Email(#[validate(nested)] EmailAddress),
+    Phone {
+        #[validate(length(min = 7, max = 15))]
+        number: String,
+    },
+    Anonymous,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, Validate)]
+struct Subscribe {
+    #[validate(nested)]
+    email: EmailAddress,
+    #[validate(nested)]
+    contacts: Vec<Contact>,
+}
+
+#[allow(dead_code)]
+#[derive(Debug, Deserialize, Validate)]
+struct Booking {
+    #[validate(future)]
+    start_date: Date,
+    #[validate(after = "start_date", before = "2030-01-01")]
+    end_date: Option<Date>,
+    #[validate(past)]
+    requested_at: DateTimeUtc,
+}
+
+#[derive(Debug, Deserialize, ToSchema, Validate)]
+struct PostSearch {
+    #[validate(length(min = 3), regex = "^[\\w ]+$")]
+    title: String,
+    #[validate(min = 1)]
+    page: Option<u32>,
+}
+
+#[endpoint]
+async fn search_posts(search: QueryValidated<PostSearch>) -> String {
+    format!("{} (page {})", search.title, search.page.unwrap_or(1))
+}
+
+#[handler]
+async fn create_post(post: JsonValidated<CreatePost, OnCreate>) -> Json<String> {
+    Json(post.into_inner().text)
+}
+
+// Streams the uploaded videos to disk without buffering them in memory or temp files.
+#[handler]
+async fn upload_videos(req: &mut Request) -> Result<Json<Vec<StoredFile>>, ValidationErrors> {
+    let limits = MultipartLimits::new()
+        .total(2 * 1024 * 1024 * 1024)
+        .per_field(64 * 1024)
+        .field("video", 2 * 1024 * 1024 * 1024);
+    let mut parts = MultipartStream::new(req, limits)?;
+    let disk = LocalDisk::new("storage/public", "/storage");
+
+    let mut stored = Vec::new();
+    while let Some(part) = parts.next_part().await? {
+        if part.is_file() {
+            stored.push(part.store("videos", &disk).await?);
+        }
+    }
+    Ok(Json(stored))
+}
+
+// Counts the requests of the browser in its session; `DELETE` starts over.
+#[handler]
+async fn visits(session: Session) -> Result<String, StatusError> {
+    let count = session.get::<u32>("visits").unwrap_or(0) + 1;
+    session
+        .insert("visits", count)
+        .map_err(|error| StatusError::internal_server_error().brief(error.to_string()))?;
+    Ok(format!("{} visits", count))
+}
+
+#[handler]
+async fn forget_visits(session: Session) {
+    session.invalidate();
+}
+
+impl Authenticatable for user::Model {
+    type Id = i32;
+
+    fn auth_id(&self) -> i32 {
+        self.id
+    }
+
+    fn password_hash(&self) -> &str {
+        &self.password
+    }
+}
+
+#[derive(Debug, Deserialize, ToSchema, Validate)]
+struct SignUp {
+    #[validate(sanitize(trim), length(min = 2, max = 60))]
+    name: String,
+    #[validate(sanitize(trim, lowercase), email)]
+    email: String,
+    #[validate(password(min_len = 10, mixed_case, numbers, symbols, not_compromised_local_list))]
+    password: String,
 }
 
-#[async_trait::async_trait]
-impl FromMultipart for UserProfile {
-    // async fn parse_from_multipart(req: &Request) -> Result<Self, StatusError> {
-    // }
-    //
-    async fn parse_from_multipart(req: &mut Request) -> Result<Self, thunder::ValidationErrors> {
-        // Now you can use `.await` safely here
-        let username: String = req.form("username").await.unwrap();
-        let email: String = req.form("email").await.unwrap();
-        let file = req.file("avatar").await.unwrap().clone();
-
-        Ok(UserProfile {
-            username: username,
-            email: email,
-            avatar: UploadedFile {
-                file_name: todo!(),
-                path: file.path().clone(),
-                content_type: file.content_type(),
-            },
+#[derive(Debug, Serialize, ToSchema)]
+struct NewAccount {
+    // Shown this once; only its hash is stored.
+    api_token: String,
+}
+
+fn internal_error(error: impl std::fmt::Display) -> StatusError {
+    StatusError::internal_server_error().brief(error.to_string())
+}
+
+// Creates an account, logs it in and hands out its API token.
+#[endpoint]
+async fn sign_up(
+    form: JsonValidated<SignUp>,
+    auth: Auth<user::Model>,
+    depot: &mut Depot,
+) -> Result<Json<NewAccount>, StatusError> {
+    let db = depot.obtain::<DatabaseConnection>().map_err(|_| {
+        StatusError::internal_server_error().brief("No database connection in the depot.")
+    })?;
+    let form = form.into_inner();
+    let password = thunder::hash::make(&form.password).map_err(internal_error)?;
+    let api_token = new_api_token();
+    let account = user::ActiveModel {
+        name: Set(form.name),
+        email: Set(form.email),
+        password: Set(password),
+        api_token: Set(Some(hash_api_token(&api_token))),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .map_err(|_| StatusError::conflict().brief("That e-mail address is taken."))?;
+    auth.login(account).map_err(internal_error)?;
+    Ok(Json(NewAccount { api_token }))
+}
+
+#[derive(Debug, Deserialize, ToSchema, Validate)]
+struct Credentials {
+    #[validate(sanitize(trim, lowercase), email)]
+    email: String,
+    #[validate(length(min = 1))]
+    password: String,
+}
+
+#[handler]
+async fn login(
+    credentials: JsonValidated<Credentials>,
+    auth: Auth<user::Model>,
+) -> Result<Redirect, StatusError> {
+    let valid = auth
+        .attempt(&credentials.email, &credentials.password)
+        .await
+        .map_err(internal_error)?;
+    if !valid {
+        return Err(StatusError::unprocessable_entity()
+            .brief("These credentials do not match our records."));
+    }
+    Ok(Redirect::other(auth.intended("/dashboard")))
+}
+
+#[handler]
+async fn logout(auth: Auth<user::Model>) -> Redirect {
+    auth.logout();
+    Redirect::other("/login")
+}
+
+// Answers session and bearer-token requests alike.
+#[handler]
+async fn me(user: CurrentUser<user::Model>) -> Json<serde_json::Value> {
+    Json(serde_json::json!({ "id": user.id, "name": user.name, "email": user.email }))
+}
+
+// Every account is public; only its owner changes or deletes it.
+struct UserPolicy;
+
+impl Policy<user::Model> for UserPolicy {
+    type User = user::Model;
+
+    fn view(&self, _user: &user::Model, _account: &user::Model) -> bool {
+        true
+    }
+
+    fn update(&self, user: &user::Model, account: &user::Model) -> bool {
+        user.id == account.id
+    }
+
+    fn delete(&self, user: &user::Model, account: &user::Model) -> bool {
+        user.id == account.id
+    }
+}
+
+fn gate() -> Gate<user::Model> {
+    Gate::new()
+        .define("view-reports", |user: &user::Model| {
+            user.email.ends_with("@bakery.test")
         })
+        .policy::<user::Model>(UserPolicy)
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct DashboardProps {
+    can: Abilities,
+}
+
+#[endpoint]
+async fn dashboard(
+    user: CurrentUser<user::Model>,
+    gate: Gate<user::Model>,
+) -> Inertia<DashboardProps> {
+    Inertia::new(
+        "Dashboard",
+        DashboardProps {
+            can: gate.abilities(Some(&user)),
+        },
+    )
+}
+
+// How the account handlers fail; a `Forbidden` keeps its Inertia `Error` page.
+#[derive(Debug)]
+enum AccountError {
+    Status(StatusError),
+    Forbidden(Forbidden),
+}
+
+impl From<StatusError> for AccountError {
+    fn from(error: StatusError) -> Self {
+        AccountError::Status(error)
     }
 }
 
-#[derive(Debug, Validate)]
-struct Name {
-    #[validate(email, min = 34, max = 3)]
-    field: String,
+impl From<Forbidden> for AccountError {
+    fn from(forbidden: Forbidden) -> Self {
+        AccountError::Forbidden(forbidden)
+    }
+}
+
+#[async_trait]
+impl Writer for AccountError {
+    async fn write(self, req: &mut Request, depot: &mut Depot, res: &mut Response) {
+        match self {
+            AccountError::Status(error) => res.render(error),
+            AccountError::Forbidden(forbidden) => forbidden.write(req, depot, res).await,
+        }
+    }
+}
+
+impl EndpointOutRegister for AccountError {
+    fn register(components: &mut salvo::oapi::Components, operation: &mut salvo::oapi::Operation) {
+        StatusError::register(components, operation);
+        Forbidden::register(components, operation);
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct AccountProps {
+    id: i32,
+    name: String,
+    can: ModelAbilities,
+}
+
+async fn find_account(depot: &Depot, id: i32) -> Result<user::Model, StatusError> {
+    let db = depot.obtain::<DatabaseConnection>().map_err(|_| {
+        StatusError::internal_server_error().brief("No database connection in the depot.")
+    })?;
+    models::prelude::User::find_by_id(id)
+        .one(db)
+        .await
+        .map_err(internal_error)?
+        .ok_or_else(|| StatusError::not_found().brief("No such account."))
+}
+
+// The page of an account, with the buttons the visitor may use.
+#[endpoint]
+async fn show_account(
+    id: PathParam<i32>,
+    user: CurrentUser<user::Model>,
+    gate: Gate<user::Model>,
+    depot: &mut Depot,
+) -> Result<Inertia<AccountProps>, AccountError> {
+    let account = find_account(depot, id.into_inner()).await?;
+    thunder::authorize!(gate.can_view(&user, &account));
+    let can = gate.model_abilities(Some(&user), &account);
+    Ok(Inertia::new(
+        "Accounts/Show",
+        AccountProps {
+            id: account.id,
+            name: account.name,
+            can,
+        },
+    ))
+}
+
+#[endpoint(status_codes(204, 403, 404))]
+async fn delete_account(
+    id: PathParam<i32>,
+    user: CurrentUser<user::Model>,
+    gate: Gate<user::Model>,
+    depot: &mut Depot,
+) -> Result<StatusCode, AccountError> {
+    let account = find_account(depot, id.into_inner()).await?;
+    thunder::authorize!(
+        gate.can_delete(&user, &account),
+        "Only the owner can delete an account."
+    );
+    let db = depot.obtain::<DatabaseConnection>().map_err(|_| {
+        StatusError::internal_server_error().brief("No database connection in the depot.")
+    })?;
+    account.delete(db).await.map_err(internal_error)?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt().init();
+    MessageCatalogue::init("locales").expect("invalid validation messages in locales/");
 
-    let router = Router::new().push(
-        Router::with_path("hello")
-            .get(hello)
-            .patch(bye)
-            .post(file_t)
-            .delete(inertia_test_endpoint),
-    );
+    let db = Database::connect("sqlite://bakery.db")
+        .await
+        .expect("Failed to connect to the database");
+
+    // Resumable uploads for the large files, e.g. from Uppy or tus-js-client.
+    let tus = TusServer::new(
+        SeaOrmTusStore::new(db.clone()),
+        LocalDisk::new("storage/public", "/storage"),
+        "storage/tus",
+    )
+    .dir("videos")
+    .max_size(2 * 1024 * 1024 * 1024);
+
+    let sessions = SessionHandler::new(SeaOrmSessionStore::new(db.clone()))
+        .idle_timeout(chrono::Duration::minutes(30))
+        .absolute_timeout(chrono::Duration::hours(12));
+
+    let users = SeaOrmUserProvider::<models::prelude::User>::new(db.clone(), user::Column::Email)
+        .password_column(user::Column::Password)
+        .token_column(user::Column::ApiToken);
+
+    let router = Router::new()
+        // For the handlers and the `unique`/`exists` rules of the validated extractors.
+        .hoop(ValidationDepot::new().inject(db))
+        .hoop(sessions)
+        .hoop(AuthHandler::new(users).guards([Guard::Session, Guard::Token]))
+        .hoop(gate())
+        // tus clients resume from other tabs and devices, without the cookie.
+        .hoop(VerifyCsrfToken::new().except("uploads").except("uploads/*"))
+        .push(
+            Router::with_path("hello")
+                .get(hello)
+                .patch(bye)
+                .post(file_t)
+                .delete(inertia_test_endpoint),
+        )
+        .push(
+            Router::with_path("posts")
+                .get(search_posts)
+                .post(create_post),
+        )
+        .push(Router::with_path("videos").post(upload_videos))
+        .push(tus.router("uploads"))
+        .push(Router::with_path("reports/latest").get(report_link))
+        .push(
+            Router::new()
+                .hoop(RedirectIfAuthenticated)
+                .push(Router::with_path("register").post(sign_up))
+                .push(Router::with_path("login").post(login)),
+        )
+        .push(
+            Router::new()
+                .hoop(Authenticate)
+                .push(Router::with_path("logout").post(logout))
+                .push(Router::with_path("me").get(me))
+                .push(Router::with_path("dashboard").get(dashboard))
+                .push(
+                    Router::with_path("accounts/{id}")
+                        .get(show_account)
+                        .delete(delete_account),
+                ),
+        )
+        .push(
+            Router::with_path("visits")
+                .get(visits)
+                .delete(forget_visits),
+        )
+        .push(
+            Router::with_path("private/{**key}")
+                .hoop(ValidateSignature::new(signer()))
+                .get(ServeDisk::new(PRIVATE_DISK.clone())),
+        );
     let doc = OpenApi::new("test api", "0.0.1").merge_router(&router);
 
     let router = router
@@ -1,3 +1,4 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.15
 
 pub use super::post::Entity as Post;
+pub use super::user::Entity as User;
@@ -0,0 +1,217 @@
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+    Data, DeriveInput, Expr, ExprLit, GenericArgument, Lit, MetaNameValue, PathArguments, Token,
+    Type, punctuated::Punctuated,
+};
+
+// How a field is read from the form, decided by its declared type.
+enum Kind<'a> {
+    File,
+    OptionalFile,
+    Files,
+    Text(&'a Type),
+    OptionalText(&'a Type),
+    Texts(&'a Type),
+}
+
+pub fn expand(input: DeriveInput) -> TokenStream {
+    let name = &input.ident;
+
+    let fields = match &input.data {
+        Data::Struct(s) => &s.fields,
+        _ => panic!("FromMultipart can only be derived for structs"),
+    };
+
+    let mut readers = Vec::new();
+    let mut required = Vec::new();
+    let mut assignments = Vec::new();
+
+    for f in fields.iter() {
+        let ident = f.ident.as_ref().expect("Field must have a name");
+        let form_name = form_name(f).unwrap_or_else(|| ident.to_string());
+
+        let missing = quote! {
+            errors.add_error(
+                #form_name,
+                thunder::ValidationError::new("required", "This field is required."),
+            );
+        };
+
+        let reader = match kind(&f.ty) {
+            Kind::File => quote! {
+                let #ident = form.files.get(#form_name).map(thunder::UploadedFile::from);
+                if #ident.is_none() {
+                    #missing
+                }
+            },
+            Kind::OptionalFile => quote! {
+                let #ident = form.files.get(#form_name).map(thunder::UploadedFile::from);
+            },
+            Kind::Files => quote! {
+                let #ident: Vec<thunder::UploadedFile> = form
+                    .files
+                    .get_vec(#form_name)
+                    .map(|files| files.iter().map(thunder::UploadedFile::from).collect())
+                    .unwrap_or_default();
+            },
+            Kind::Text(ty) => quote! {
+                let #ident: Option<#ty> = match form.fields.get(#form_name) {
+                    Some(value) => thunder::parse_multipart_field(#form_name, value, &mut errors),
+                    None => {
+                        #missing
+                        None
+                    }
+                };
+            },
+            Kind::OptionalText(ty) => quote! {
+                let #ident: Option<#ty> = form
+                    .fields
+                    .get(#form_name)
+                    .filter(|value| !value.is_empty())
+                    .and_then(|value| {
+                        thunder::parse_multipart_field(#form_name, value, &mut errors)
+                    });
+            },
+            Kind::Texts(ty) => quote! {
+                let #ident: Vec<#ty> = form
+                    .fields
+                    .get_vec(#form_name)
+                    .map(|values| {
+                        values
+                            .iter()
+                            .filter_map(|value| {
+                                thunder::parse_multipart_field(#form_name, value, &mut errors)
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+            },
+        };
+        readers.push(reader);
+
+        // Required fields are read into an `Option` and unwrapped once every field is known.
+        match kind(&f.ty) {
+            Kind::File | Kind::Text(_) => required.push(ident.clone()),
+            _ => {}
+        }
+        assignments.push(quote! { #ident });
+    }
+
+    let unwrap_required = (!required.is_empty()).then(|| {
+        quote! {
+            let (#(Some(#required),)*) = (#(#required,)*) else {
+                errors.add_status_code(thunder::StatusCode::UNPROCESSABLE_ENTITY);
+                return Err(errors);
+            };
+        }
+    });
+
+    quote! {
+        #[thunder::async_trait]
+        impl thunder::FromMultipart for #name {
+            async fn parse_from_multipart(
+                req: &mut thunder::Request,
+            ) -> Result<Self, thunder::ValidationErrors> {
+                let mut errors = thunder::ValidationErrors::new();
+
+                let form = match req.form_data().await {
+                    Ok(form) => form,
+                    Err(error) => {
+                        errors.add_error(
+                            "body",
+                            thunder::ValidationError::new("parse", error.to_string()),
+                        );
+                        errors.add_status_code(thunder::StatusCode::BAD_REQUEST);
+                        return Err(errors);
+                    }
+                };
+
+                #(#readers)*
+
+                #unwrap_required
+                if !errors.is_empty() {
+                    errors.add_status_code(thunder::StatusCode::UNPROCESSABLE_ENTITY);
+                    return Err(errors);
+                }
+
+                Ok(Self {
+                    #(#assignments),*
+                })
+            }
+        }
+    }
+}
+
+// `#[multipart(rename = "...")]`
+fn form_name(field: &syn::Field) -> Option<String> {
+    let mut rename = None;
+    for attr in &field.attrs {
+        if !attr.path().is_ident("multipart") {
+            continue;
+        }
+        let args = attr
+            .parse_args_with(Punctuated::<MetaNameValue, Token![,]>::parse_terminated)
+            .expect("Failed to parse multipart attribute");
+        for arg in args {
+            match (&arg.path, &arg.value) {
+                (
+                    path,
+                    Expr::Lit(ExprLit {
+                        lit: Lit::Str(lit_str),
+                        ..
+                    }),
+                ) if path.is_ident("rename") => rename = Some(lit_str.value()),
+                _ => panic!("Unknown multipart attribute, expected `rename = \"...\"`"),
+            }
+        }
+    }
+    rename
+}
+
+fn kind(ty: &Type) -> Kind<'_> {
+    match wrapped(ty, "Option") {
+        Some(inner) if is_uploaded_file(inner) => return Kind::OptionalFile,
+        Some(inner) => return Kind::OptionalText(inner),
+        None => {}
+    }
+    match wrapped(ty, "Vec") {
+        Some(inner) if is_uploaded_file(inner) => return Kind::Files,
+        Some(inner) => return Kind::Texts(inner),
+        None => {}
+    }
+    if is_uploaded_file(ty) {
+        Kind::File
+    } else {
+        Kind::Text(ty)
+    }
+}
+
+// The `T` of `Option<T>` or `Vec<T>`.
+pub(crate) fn wrapped<'a>(ty: &'a Type, wrapper: &str) -> Option<&'a Type> {
+    let Type::Path(type_path) = ty else {
+        return None;
+    };
+    let segment = type_path.path.segments.last()?;
+    if segment.ident != wrapper {
+        return None;
+    }
+    let PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.first()? {
+        GenericArgument::Type(inner) => Some(inner),
+        _ => None,
+    }
+}
+
+fn is_uploaded_file(ty: &Type) -> bool {
+    match ty {
+        Type::Path(type_path) => type_path
+            .path
+            .segments
+            .last()
+            .is_some_and(|segment| segment.ident == "UploadedFile"),
+        _ => false,
+    }
+}
@@ -0,0 +1,1388 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use syn::{
+    Data, DeriveInput, Expr, ExprLit, Fields, Ident, Index, Lit, LitStr, Meta, MetaNameValue, Path,
+    Token, Type, punctuated::Punctuated,
+};
+
+use crate::multipart::wrapped;
+
+// A single rule from a `#[validate(...)]` attribute, in any of its forms:
+// `email`, `min = 3`, or `min(value = 3, message = "...", code = "...")`.
+struct Rule {
+    name: Ident,
+    value: Option<Expr>,
+    args: Vec<MetaNameValue>,
+    // Bare arguments such as the `mixed_case` of `password(min_len = 12, mixed_case)`.
+    flags: Vec<Ident>,
+    message: Option<LitStr>,
+    code: Option<LitStr>,
+    // `groups = ["create"]`; a rule without groups always runs.
+    groups: Vec<LitStr>,
+}
+
+impl Rule {
+    fn parse(meta: Meta, defaults: &Overrides) -> Rule {
+        let name = meta
+            .path()
+            .get_ident()
+            .expect("Validation rules must be a single identifier")
+            .clone();
+        let mut rule = Rule {
+            name,
+            value: None,
+            args: Vec::new(),
+            flags: Vec::new(),
+            message: defaults.message.clone(),
+            code: defaults.code.clone(),
+            groups: defaults.groups.clone(),
+        };
+
+        match meta {
+            Meta::Path(_) => {}
+            Meta::NameValue(nv) => rule.value = Some(nv.value),
+            Meta::List(list) => {
+                let args = list
+                    .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                    .unwrap_or_else(|_| panic!("Failed to parse arguments of `{}`", rule.name));
+                for arg in args {
+                    let arg = match arg {
+                        Meta::NameValue(arg) => arg,
+                        Meta::Path(path) => {
+                            rule.flags.push(
+                                path.get_ident()
+                                    .cloned()
+                                    .unwrap_or_else(|| panic!("Unknown flag of `{}`", rule.name)),
+                            );
+                            continue;
+                        }
+                        Meta::List(_) => panic!("Failed to parse arguments of `{}`", rule.name),
+                    };
+                    match &arg.path {
+                        path if path.is_ident("message") => {
+                            rule.message = Some(parse_lit_str(&arg))
+                        }
+                        path if path.is_ident("code") => rule.code = Some(parse_lit_str(&arg)),
+                        path if path.is_ident("value") => rule.value = Some(arg.value),
+                        path if path.is_ident("groups") => rule.groups = parse_groups(&arg),
+                        _ => rule.args.push(arg),
+                    }
+                }
+            }
+        }
+
+        rule
+    }
+
+    fn arg(&self, name: &str) -> Option<&MetaNameValue> {
+        self.args.iter().find(|arg| arg.path.is_ident(name))
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    fn value(&self) -> &Expr {
+        self.value
+            .as_ref()
+            .unwrap_or_else(|| panic!("The `{}` rule needs a value", self.name))
+    }
+
+    // The `code`/`message` overrides, chained onto a `ValidationError` built at runtime.
+    fn overrides(&self) -> TokenStream {
+        let code = self.code.as_ref().map(|code| quote! { .with_code(#code) });
+        let message = self
+            .message
+            .as_ref()
+            .map(|message| quote! { .with_custom_message(#message) });
+        quote! { #code #message }
+    }
+
+    // Wraps `check` so that it only runs when one of the groups of the rule is selected.
+    fn grouped(&self, check: TokenStream) -> TokenStream {
+        if self.groups.is_empty() {
+            return check;
+        }
+        let groups = &self.groups;
+        quote! {
+            if group.is_some_and(|group| [#(#groups),*].contains(&group)) {
+                #check
+            }
+        }
+    }
+
+    // Builds the `errors.add_error(...)` call for this rule. `params` are available to
+    // the message as `{name}` placeholders, both here and in the locale catalogues.
+    fn error(
+        &self,
+        key: &TokenStream,
+        code: &str,
+        message: &str,
+        params: &[(&str, TokenStream)],
+    ) -> TokenStream {
+        let code = self
+            .code
+            .as_ref()
+            .map(LitStr::value)
+            .unwrap_or_else(|| code.to_string());
+        let custom_message = self.message.as_ref().map(|message| {
+            quote! { .with_custom_message(#message) }
+        });
+        let params = params.iter().map(|(name, value)| {
+            quote! { .with_param(#name, #value) }
+        });
+
+        quote! {
+            errors.add_error(
+                #key,
+                thunder::ValidationError::new(#code, #message)
+                    #(#params)*
+                    #custom_message,
+            );
+        }
+    }
+}
+
+// `message`, `code` and `groups` given next to the rules apply to every rule of that
+// attribute.
+#[derive(Default)]
+struct Overrides {
+    message: Option<LitStr>,
+    code: Option<LitStr>,
+    groups: Vec<LitStr>,
+}
+
+// The parsed rules of one `#[validate(...)]` attribute.
+fn parse_rules(metas: Punctuated<Meta, Token![,]>) -> Vec<Rule> {
+    let mut overrides = Overrides::default();
+    let mut rules = Vec::new();
+
+    for meta in metas {
+        match &meta {
+            Meta::NameValue(nv) if nv.path.is_ident("message") => {
+                overrides.message = Some(parse_lit_str(nv))
+            }
+            Meta::NameValue(nv) if nv.path.is_ident("code") => {
+                overrides.code = Some(parse_lit_str(nv))
+            }
+            Meta::NameValue(nv) if nv.path.is_ident("groups") => {
+                overrides.groups = parse_groups(nv)
+            }
+            _ => rules.push(meta),
+        }
+    }
+
+    rules
+        .into_iter()
+        .map(|meta| Rule::parse(meta, &overrides))
+        .collect()
+}
+
+// Where a field lives: how to read it and under which key its errors go.
+struct Field<'a> {
+    name: String,
+    // The property name in the OpenAPI schema, honouring `#[serde(rename = "...")]`.
+    schema_name: String,
+    access: TokenStream,
+    key: TokenStream,
+    ty: &'a Type,
+}
+
+// The generated checks, split between `Validate` and `AsyncValidate`.
+#[derive(Default)]
+struct Checks {
+    checks: Vec<TokenStream>,
+    async_checks: Vec<TokenStream>,
+    constraints: Vec<TokenStream>,
+    sanitizers: Vec<TokenStream>,
+    // The types of the `nested` fields, which get bounds when they are generic.
+    nested: Vec<Type>,
+}
+
+pub fn expand(input: DeriveInput) -> TokenStream {
+    if let Some(attr) = bare_sanitize(&input) {
+        return syn::Error::new_spanned(
+            attr,
+            "write `#[validate(sanitize(...))]`: a bare `#[sanitize]` is the compiler's \
+             built-in attribute",
+        )
+        .to_compile_error();
+    }
+    let name = &input.ident;
+    let (impl_generics, ty_generics, _) = input.generics.split_for_impl();
+
+    let mut all = Checks::default();
+
+    match &input.data {
+        Data::Struct(s) => {
+            struct_rules(&input, &mut all);
+
+            let is_newtype =
+                matches!(&s.fields, Fields::Unnamed(fields) if fields.unnamed.len() == 1);
+            for (index, f) in s.fields.iter().enumerate() {
+                let field = match &f.ident {
+                    Some(field_name) => Field {
+                        name: field_name.to_string(),
+                        schema_name: serde_rename(f).unwrap_or_else(|| field_name.to_string()),
+                        access: quote! { self.#field_name },
+                        key: quote! { stringify!(#field_name) },
+                        ty: &f.ty,
+                    },
+                    None => {
+                        let member = Index::from(index);
+                        Field {
+                            name: index.to_string(),
+                            schema_name: index.to_string(),
+                            access: quote! { self.#member },
+                            // A newtype reports its errors on the field that holds it.
+                            key: if is_newtype {
+                                quote! { "" }
+                            } else {
+                                let key = index.to_string();
+                                quote! { #key }
+                            },
+                            ty: &f.ty,
+                        }
+                    }
+                };
+                field_checks(&field, f, true, &mut all);
+            }
+        }
+        Data::Enum(e) => {
+            if input
+                .attrs
+                .iter()
+                .any(|attr| attr.path().is_ident("validate"))
+            {
+                panic!("Struct-level rules are not supported on enums");
+            }
+
+            // Every variant validates its own fields, bound by reference in a `match`.
+            let mut arms = Vec::new();
+            let mut async_arms = Vec::new();
+            let mut sanitize_arms = Vec::new();
+            let mut sanitizes = false;
+            for variant in &e.variants {
+                let variant_name = &variant.ident;
+                let mut variant_checks = Checks::default();
+                let mut bindings = Vec::new();
+                let is_newtype =
+                    matches!(&variant.fields, Fields::Unnamed(fields) if fields.unnamed.len() == 1);
+
+                for (index, f) in variant.fields.iter().enumerate() {
+                    let binding = match &f.ident {
+                        Some(field_name) => field_name.clone(),
+                        None => format_ident!("__field{}", index),
+                    };
+                    let is_validated = f.attrs.iter().any(|attr| attr.path().is_ident("validate"));
+                    let key = match &f.ident {
+                        Some(field_name) => field_name.to_string(),
+                        None if is_newtype => String::new(),
+                        None => index.to_string(),
+                    };
+                    let field = Field {
+                        name: binding.to_string(),
+                        schema_name: binding.to_string(),
+                        access: quote! { (*#binding) },
+                        key: quote! { #key },
+                        ty: &f.ty,
+                    };
+                    field_checks(&field, f, false, &mut variant_checks);
+                    bindings.push(match (&f.ident, is_validated) {
+                        (_, true) => quote! { #binding },
+                        (Some(field_name), false) => quote! { #field_name: _ },
+                        (None, false) => quote! { _ },
+                    });
+                }
+
+                let pattern = match &variant.fields {
+                    Fields::Named(_) => quote! { Self::#variant_name { #(#bindings),* } },
+                    Fields::Unnamed(_) => quote! { Self::#variant_name(#(#bindings),*) },
+                    Fields::Unit => quote! { Self::#variant_name },
+                };
+                let Checks {
+                    checks,
+                    async_checks,
+                    sanitizers,
+                    nested,
+                    ..
+                } = variant_checks;
+                all.nested.extend(nested);
+                sanitizes |= !sanitizers.is_empty();
+                arms.push(quote! { #pattern => { #(#checks)* } });
+                async_arms.push(quote! { #pattern => { #(#async_checks)* } });
+                sanitize_arms.push(quote! { #pattern => { #(#sanitizers)* } });
+            }
+
+            if sanitizes {
+                all.sanitizers.push(quote! {
+                    #[allow(unused_variables)]
+                    match self {
+                        #(#sanitize_arms)*
+                    }
+                });
+            }
+
+            if !e.variants.is_empty() {
+                all.checks.push(quote! {
+                    #[allow(unused_variables)]
+                    match self {
+                        #(#arms)*
+                    }
+                });
+                all.async_checks.push(quote! {
+                    #[allow(unused_variables)]
+                    match self {
+                        #(#async_arms)*
+                    }
+                });
+            }
+        }
+        Data::Union(_) => panic!("Validate can not be derived for unions"),
+    }
+
+    let Checks {
+        checks,
+        async_checks,
+        constraints,
+        sanitizers,
+        nested,
+    } = all;
+
+    // Generic nested fields need the traits the generated code calls on them. The
+    // async methods borrow `self` across awaits, so `AsyncValidate` needs `Sync`;
+    // the bound is higher-ranked so that a type that is not `Sync` simply goes
+    // without the impl instead of failing to compile.
+    let nested: Vec<&Type> = nested
+        .iter()
+        .filter(|ty| mentions_type_param(ty, &input.generics))
+        .collect();
+    let mut validate_generics = input.generics.clone();
+    let predicates = &mut validate_generics.make_where_clause().predicates;
+    for ty in &nested {
+        predicates.push(syn::parse_quote! { #ty: thunder::Validate });
+    }
+    let mut async_generics = input.generics.clone();
+    let predicates = &mut async_generics.make_where_clause().predicates;
+    predicates.push(syn::parse_quote! { for<'__thunder> Self: Sync });
+    for ty in &nested {
+        predicates.push(syn::parse_quote! { #ty: thunder::AsyncValidate });
+    }
+    let (_, _, validate_where) = validate_generics.split_for_impl();
+    let (_, _, async_where) = async_generics.split_for_impl();
+
+    // The same checks back the plain and the grouped methods; `group` decides which
+    // grouped rules take part.
+    let body = quote! {
+        let mut errors = thunder::ValidationErrors::new();
+
+        #(#checks)*
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    };
+    let async_body = quote! {
+        let mut errors = thunder::ValidationErrors::new();
+
+        #(#async_checks)*
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    };
+
+    quote! {
+        impl #impl_generics thunder::Validate for #name #ty_generics #validate_where {
+            fn validate(&self) -> Result<(), thunder::ValidationErrors> {
+                #[allow(unused_variables)]
+                let group: Option<&str> = None;
+                #body
+            }
+
+            fn validate_group(&self, group: &str) -> Result<(), thunder::ValidationErrors> {
+                #[allow(unused_variables)]
+                let group = Some(group);
+                #body
+            }
+
+            fn schema_constraints() -> Vec<(&'static str, thunder::SchemaConstraint)> {
+                vec![#(#constraints),*]
+            }
+
+            fn sanitize(&mut self) {
+                #(#sanitizers)*
+            }
+        }
+
+        #[thunder::async_trait]
+        impl #impl_generics thunder::AsyncValidate for #name #ty_generics #async_where {
+            async fn validate_async_rules(
+                &self,
+                depot: &thunder::Depot,
+            ) -> Result<(), thunder::ValidationErrors> {
+                #[allow(unused_variables)]
+                let group: Option<&str> = None;
+                #async_body
+            }
+
+            async fn validate_group_async_rules(
+                &self,
+                depot: &thunder::Depot,
+                group: &str,
+            ) -> Result<(), thunder::ValidationErrors> {
+                #[allow(unused_variables)]
+                let group = Some(group);
+                #async_body
+            }
+        }
+    }
+}
+
+// Whether `ty` names one of the type parameters of the input, e.g. `Vec<T>`.
+fn mentions_type_param(ty: &Type, generics: &syn::Generics) -> bool {
+    fn idents(tokens: TokenStream, out: &mut Vec<Ident>) {
+        for token in tokens {
+            match token {
+                proc_macro2::TokenTree::Ident(ident) => out.push(ident),
+                proc_macro2::TokenTree::Group(group) => idents(group.stream(), out),
+                _ => {}
+            }
+        }
+    }
+    let mut found = Vec::new();
+    idents(quote! { #ty }, &mut found);
+    generics
+        .type_params()
+        .any(|param| found.contains(&param.ident))
+}
+
+// Struct-level rules name both fields, e.g.
+// `#[validate(must_match(field = "password_confirmation", other = "password"))]`.
+fn struct_rules(input: &DeriveInput, all: &mut Checks) {
+    for attr in &input.attrs {
+        if !attr.path().is_ident("validate") {
+            continue;
+        }
+
+        let metas = attr
+            .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+            .expect("Failed to parse validation rules");
+
+        for rule in parse_rules(metas) {
+            if !is_cross_field_rule(&rule.name) {
+                panic!("Only cross-field rules can be used on the struct");
+            }
+            let field = parse_ident(
+                rule.arg("field")
+                    .expect("Cross-field rules need a `field = \"...\"` argument"),
+            );
+            let other = parse_ident(
+                rule.arg("other")
+                    .expect("Cross-field rules need an `other = \"...\"` argument"),
+            );
+            all.checks
+                .push(rule.grouped(cross_field_rule(&rule, &field, &other)));
+        }
+    }
+}
+
+// The checks for every `#[validate(...)]` attribute of one field. Cross-field rules read
+// the other field through `self`, so they are only available on structs.
+fn field_checks(field: &Field, f: &syn::Field, on_struct: bool, out: &mut Checks) {
+    let mut rules = Vec::new();
+    for attr in &f.attrs {
+        if !attr.path().is_ident("validate") {
+            continue;
+        }
+
+        let (sanitize, metas): (Vec<_>, Vec<_>) = attr
+            .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+            .expect("Failed to parse validation rules")
+            .into_iter()
+            .partition(|meta| meta.path().is_ident("sanitize"));
+        for meta in sanitize {
+            out.sanitizers.extend(sanitizers(field, meta));
+        }
+        rules.extend(parse_rules(metas.into_iter().collect()));
+    }
+
+    // Like Laravel, `required` and `nullable` fields skip their other rules when the
+    // value is missing: `None`, a blank string or an empty collection.
+    let skips_missing = rules
+        .iter()
+        .any(|rule| rule.name == "required" || rule.name == "nullable");
+    // The rules of an `Option<T>` field look at the `T`, and only when there is one.
+    let inner_ty = wrapped(field.ty, "Option");
+    let inner = Field {
+        name: field.name.clone(),
+        schema_name: field.schema_name.clone(),
+        access: match inner_ty {
+            Some(_) => quote! { (*value) },
+            None => field.access.clone(),
+        },
+        key: field.key.clone(),
+        ty: inner_ty.unwrap_or(field.ty),
+    };
+    let when_present = |check: TokenStream| {
+        let Field { access, .. } = field;
+        let inner_access = &inner.access;
+        let check = if skips_missing {
+            quote! {
+                if thunder::rules::Presence::is_present(&#inner_access) {
+                    #check
+                }
+            }
+        } else {
+            check
+        };
+        match inner_ty {
+            Some(_) => quote! {
+                if let Some(value) = &#access {
+                    #check
+                }
+            },
+            None => check,
+        }
+    };
+
+    for rule in rules {
+        // Grouped rules do not always run, so they are left out of the schema.
+        if f.ident.is_some() && on_struct && rule.groups.is_empty() {
+            out.constraints.extend(schema_constraints(&inner, &rule));
+        }
+        let (checks, async_checks) = (out.checks.len(), out.async_checks.len());
+        match rule.name.to_string().as_str() {
+            "required" => {
+                let Field { access, key, .. } = field;
+                let error = rule.error(key, "required", "This field is required.", &[]);
+                out.checks.push(quote! {
+                    if !thunder::rules::Presence::is_present(&#access) {
+                        #error
+                    }
+                });
+            }
+            "nullable" => {}
+            "unique" | "exists" => out
+                .async_checks
+                .push(when_present(database_rule(&inner, &rule))),
+            "file" | "image" => out
+                .async_checks
+                .push(when_present(file_rule(&inner, &rule))),
+            "custom_async" => out
+                .async_checks
+                .push(when_present(custom_rule(&inner, &rule, true))),
+            "nested" => {
+                out.nested.push(field.ty.clone());
+                let Field { access, key, .. } = field;
+                out.sanitizers.push(quote! {
+                    thunder::Validate::sanitize(&mut #access);
+                });
+                out.checks.push(quote! {
+                    if let Err(nested_errors) = thunder::rules::validate_group(&#access, group) {
+                        errors.merge_nested(#key, nested_errors);
+                    }
+                });
+                out.async_checks.push(quote! {
+                    if let Err(nested_errors) =
+                        thunder::rules::validate_group_async_rules(&#access, depot, group).await
+                    {
+                        errors.merge_nested(#key, nested_errors);
+                    }
+                });
+            }
+            rule_name if is_cross_field_rule(&rule.name) => {
+                let field_name = f
+                    .ident
+                    .as_ref()
+                    .unwrap_or_else(|| panic!("`{}` can only be used on named fields", rule_name));
+                if !on_struct {
+                    panic!("`{}` is not supported on enum variants", rule_name);
+                }
+                let other = if rule_name == "required_if" {
+                    parse_ident(
+                        rule.arg("field")
+                            .expect("required_if needs a `field = \"...\"` argument"),
+                    )
+                } else {
+                    match &rule.value {
+                        Some(_) => parse_ident_expr(rule.value()),
+                        None => parse_ident(rule.arg("other").unwrap_or_else(|| {
+                            panic!("`{}` needs the name of the other field", rule_name)
+                        })),
+                    }
+                };
+                out.checks.push(cross_field_rule(&rule, field_name, &other));
+            }
+            _ => out.checks.push(when_present(field_rule(&inner, &rule))),
+        }
+        for check in &mut out.checks[checks..] {
+            *check = rule.grouped(std::mem::take(check));
+        }
+        for check in &mut out.async_checks[async_checks..] {
+            *check = rule.grouped(std::mem::take(check));
+        }
+    }
+}
+
+// A `#[sanitize(...)]` on a field, meant for us but claimed by the compiler, which
+// only says the attribute is unstable.
+fn bare_sanitize(input: &DeriveInput) -> Option<&syn::Attribute> {
+    let fields: Vec<&syn::Field> = match &input.data {
+        Data::Struct(s) => s.fields.iter().collect(),
+        Data::Enum(e) => e
+            .variants
+            .iter()
+            .flat_map(|variant| &variant.fields)
+            .collect(),
+        Data::Union(_) => Vec::new(),
+    };
+    fields
+        .into_iter()
+        .flat_map(|f| &f.attrs)
+        .find(|attr| attr.path().is_ident("sanitize"))
+}
+
+// `sanitize(trim, lowercase, strip_tags, collapse_whitespace)`, applied in order. It
+// lives inside `#[validate(...)]` because a bare `#[sanitize]` clashes with the
+// compiler's built-in attribute of that name.
+fn sanitizers(field: &Field, meta: Meta) -> Vec<TokenStream> {
+    let access = &field.access;
+    let Meta::List(list) = meta else {
+        panic!("`sanitize` expects a list, e.g. `sanitize(trim, lowercase)`");
+    };
+    list.parse_args_with(Punctuated::<Ident, Token![,]>::parse_terminated)
+        .expect("Failed to parse sanitizers")
+        .into_iter()
+        .map(|sanitizer| {
+            match sanitizer.to_string().as_str() {
+                "trim" | "lowercase" | "strip_tags" | "collapse_whitespace" => {}
+                other => panic!("Unknown sanitizer: {}", other),
+            }
+            quote! {
+                thunder::rules::Text::map_text(&mut #access, thunder::rules::#sanitizer);
+            }
+        })
+        .collect()
+}
+
+// Rules that only look at the field itself.
+fn field_rule(field: &Field, rule: &Rule) -> TokenStream {
+    let Field {
+        access, key, ty, ..
+    } = field;
+
+    match rule.name.to_string().as_str() {
+        "email" => {
+            let error = rule.error(key, "email", "Must be a valid email address.", &[]);
+            quote! {
+                if !#access.contains('@') {
+                    #error
+                }
+            }
+        }
+        "min" | "max" | "equals" => comparison_rule(field, rule),
+        "regex" => {
+            let pattern = rule.value();
+            let error = rule.error(
+                key,
+                "regex",
+                "The format is invalid.",
+                &[("pattern", quote! { #pattern })],
+            );
+            quote! {
+                {
+                    static PATTERN: thunder::rules::Lazy<thunder::rules::Regex> =
+                        thunder::rules::Lazy::new(|| {
+                            thunder::rules::Regex::new(#pattern).expect("Invalid regex in #[validate]")
+                        });
+                    if !PATTERN.is_match(&#access) {
+                        #error
+                    }
+                }
+            }
+        }
+        "length" => {
+            let mut checks = Vec::new();
+            for (bound, code, message, op) in [
+                (
+                    "min",
+                    "length_min",
+                    "Length must be at least {min}.",
+                    quote! { < },
+                ),
+                (
+                    "max",
+                    "length_max",
+                    "Length must be no more than {max}.",
+                    quote! { > },
+                ),
+                (
+                    "equal",
+                    "length_equal",
+                    "Length must be exactly {equal}.",
+                    quote! { != },
+                ),
+            ] {
+                if let Some(arg) = rule.arg(bound) {
+                    let limit = &arg.value;
+                    let error = rule.error(key, code, message, &[(bound, quote! { #limit })]);
+                    checks.push(quote! {
+                        if thunder::rules::Length::length(&#access) #op #limit {
+                            #error
+                        }
+                    });
+                }
+            }
+            quote! { #(#checks)* }
+        }
+        "custom" => custom_rule(field, rule, false),
+        "password" => password_rule(field, rule),
+        "after" | "before" | "after_or_equal" | "before_or_equal" | "future" | "past" => {
+            date_rule(field, rule)
+        }
+        other => panic!(
+            "Unknown validation rule `{}` for a field of type `{}`",
+            other,
+            quote! { #ty }
+        ),
+    }
+}
+
+// The OpenAPI keywords a rule stands for, so the documented contract matches the
+// validated one. Rules without a JSON Schema counterpart document nothing.
+fn schema_constraints(field: &Field, rule: &Rule) -> Vec<TokenStream> {
+    let name = &field.schema_name;
+    let constraint = |constraint: TokenStream| {
+        quote! { (#name, thunder::SchemaConstraint::#constraint) }
+    };
+    let is_string = matches!(field.ty, Type::Path(type_path) if type_path.path.is_ident("String"));
+
+    match rule.name.to_string().as_str() {
+        "required" => vec![constraint(quote! { Required })],
+        "email" => vec![constraint(quote! { Email })],
+        "regex" => {
+            let pattern = rule.value();
+            vec![constraint(quote! { Pattern(#pattern) })]
+        }
+        "min" | "max" | "equals" => {
+            let value = rule.value();
+            let (min, max) = match rule.name.to_string().as_str() {
+                "min" => (true, false),
+                "max" => (false, true),
+                _ => (true, true),
+            };
+            let mut constraints = Vec::new();
+            if is_string {
+                if min {
+                    constraints.push(constraint(quote! { MinLength((#value) as usize) }));
+                }
+                if max {
+                    constraints.push(constraint(quote! { MaxLength((#value) as usize) }));
+                }
+            } else {
+                if min {
+                    constraints.push(constraint(quote! { Minimum((#value) as f64) }));
+                }
+                if max {
+                    constraints.push(constraint(quote! { Maximum((#value) as f64) }));
+                }
+            }
+            constraints
+        }
+        "password" => match rule.arg("min_len") {
+            Some(arg) => {
+                let value = &arg.value;
+                vec![constraint(quote! { MinLength((#value) as usize) })]
+            }
+            None => vec![constraint(quote! { MinLength(8) })],
+        },
+        "length" => {
+            let mut constraints = Vec::new();
+            for (bound, variant) in [("min", "MinLength"), ("max", "MaxLength"), ("equal", "")] {
+                let Some(arg) = rule.arg(bound) else {
+                    continue;
+                };
+                let value = &arg.value;
+                if variant.is_empty() {
+                    constraints.push(constraint(quote! { MinLength((#value) as usize) }));
+                    constraints.push(constraint(quote! { MaxLength((#value) as usize) }));
+                } else {
+                    let variant = Ident::new(variant, Span::call_site());
+                    constraints.push(constraint(quote! { #variant((#value) as usize) }));
+                }
+            }
+            constraints
+        }
+        _ => Vec::new(),
+    }
+}
+
+// `after`, `before`, `after_or_equal`, `before_or_equal`, `future` and `past` on chrono
+// dates. The limit is a date such as `"2025-01-01"`, `"today"`, `"now"`, or the name of
+// another field: `after = "start_date"`.
+fn date_rule(field: &Field, rule: &Rule) -> TokenStream {
+    let Field { access, key, .. } = field;
+    let rule_name = rule.name.to_string();
+
+    let (op, message) = match rule_name.as_str() {
+        "after" => (quote! { > }, "Must be a date after {date}."),
+        "before" => (quote! { < }, "Must be a date before {date}."),
+        "after_or_equal" => (quote! { >= }, "Must be a date after or equal to {date}."),
+        "before_or_equal" => (quote! { <= }, "Must be a date before or equal to {date}."),
+        "future" => (quote! { > }, "Must be a date in the future."),
+        _ => (quote! { < }, "Must be a date in the past."),
+    };
+
+    let (limit, params) = match rule_name.as_str() {
+        "future" | "past" => (
+            quote! { Some(thunder::rules::Moment::now(&#access)) },
+            Vec::new(),
+        ),
+        _ => {
+            let Expr::Lit(ExprLit {
+                lit: Lit::Str(lit_str),
+                ..
+            }) = rule.value()
+            else {
+                panic!("`{}` expects a date or a field name as a string", rule_name)
+            };
+            let date = lit_str.value();
+            let limit = if date == "today" {
+                quote! { Some(thunder::rules::today()) }
+            } else if date == "now" {
+                quote! { Some(thunder::rules::now()) }
+            } else if let Ok(other) = syn::parse_str::<Ident>(&date) {
+                quote! { thunder::rules::Moment::moment(&self.#other) }
+            } else {
+                // Parsed here, so a typo fails the build instead of the request.
+                let Some(moment) = parse_date(&date) else {
+                    return syn::Error::new_spanned(
+                        lit_str,
+                        format!(
+                            "invalid date `{}` for `{}`: expected `YYYY-MM-DD`, \
+                             `YYYY-MM-DD HH:MM:SS`, an RFC 3339 timestamp, `today`, `now` \
+                             or a field name",
+                            date, rule_name
+                        ),
+                    )
+                    .to_compile_error();
+                };
+                let seconds = moment.and_utc().timestamp();
+                let nanoseconds = moment.and_utc().timestamp_subsec_nanos();
+                quote! { thunder::rules::moment_at(#seconds, #nanoseconds) }
+            };
+            (limit, vec![("date", quote! { #date })])
+        }
+    };
+    let error = rule.error(key, &rule_name, message, &params);
+
+    quote! {
+        if let (Some(moment), Some(limit)) = (thunder::rules::Moment::moment(&#access), #limit) {
+            if !(moment #op limit) {
+                #error
+            }
+        }
+    }
+}
+
+// A fixed date of a rule: an RFC 3339 timestamp, a `YYYY-MM-DD HH:MM:SS` date and
+// time or a `YYYY-MM-DD` date, at midnight.
+fn parse_date(value: &str) -> Option<chrono::NaiveDateTime> {
+    if let Ok(date_time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Some(date_time.naive_utc());
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S"] {
+        if let Ok(date_time) = chrono::NaiveDateTime::parse_from_str(value, format) {
+            return Some(date_time);
+        }
+    }
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+}
+
+// `min`, `max` and `equals` compare the length of `String` fields and the value of numbers.
+fn comparison_rule(field: &Field, rule: &Rule) -> TokenStream {
+    let Field {
+        access, key, ty, ..
+    } = field;
+
+    let supported_numeric_types = [
+        "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize",
+        "f32", "f64",
+    ];
+    let supported_string_types = ["String"];
+
+    let type_path = match ty {
+        Type::Path(type_path) => {
+            if !supported_numeric_types
+                .iter()
+                .any(|t| type_path.path.is_ident(t))
+                && !supported_string_types
+                    .iter()
+                    .any(|t| type_path.path.is_ident(t))
+            {
+                panic!(
+                    "The `min` and `max` validators can only be applied to numeric or String fields."
+                );
+            }
+            type_path
+        }
+        _ => {
+            panic!("The `min` and `max` validators are not supported for this complex field type.");
+        }
+    };
+
+    let value = rule.value();
+    let is_string = type_path.path.is_ident("String");
+    let rule_name = rule.name.to_string();
+
+    // On a `String` these are length checks, with the codes and params of `length`,
+    // so the catalogues don't give them the numeric messages.
+    let (op, code, param, message) = match (rule_name.as_str(), is_string) {
+        ("min", true) => (
+            quote! { < },
+            "length_min",
+            "min",
+            "Length must be at least {min}.",
+        ),
+        ("max", true) => (
+            quote! { > },
+            "length_max",
+            "max",
+            "Length must be no more than {max}.",
+        ),
+        ("equals", true) => (
+            quote! { != },
+            "length_equal",
+            "equal",
+            "Length must be exactly {equal}.",
+        ),
+        ("min", false) => (quote! { < }, "min", "min", "Must be at least {min}."),
+        ("max", false) => (quote! { > }, "max", "max", "Must be no more than {max}."),
+        _ => (
+            quote! { != },
+            "equals",
+            "equals",
+            "Must be exactly {equals}.",
+        ),
+    };
+    let error = rule.error(
+        key,
+        code,
+        message,
+        &[(param, quote! { #value }), ("value", quote! { &#access })],
+    );
+
+    if is_string {
+        quote! {
+            if #access.chars().count() #op #value {
+                #error
+            }
+        }
+    } else {
+        quote! {
+            if #access #op #value {
+                #error
+            }
+        }
+    }
+}
+
+// `custom = path::to::fn` calls `fn(&value) -> Result<(), ValidationError>`;
+// `custom_async` additionally passes the `Depot` and awaits the result.
+fn custom_rule(field: &Field, rule: &Rule, is_async: bool) -> TokenStream {
+    let Field { access, key, .. } = field;
+    let function = match (&rule.value, rule.arg("function")) {
+        (Some(value), _) => parse_path(value),
+        (None, Some(arg)) => parse_path(&arg.value),
+        (None, None) => panic!("`{}` needs a path to a function", rule.name),
+    };
+    let call = if is_async {
+        quote! { #function(&#access, depot).await }
+    } else {
+        quote! { #function(&#access) }
+    };
+    let overrides = rule.overrides();
+
+    quote! {
+        if let Err(error) = #call {
+            errors.add_error(#key, error #overrides);
+        }
+    }
+}
+
+// `file(max_size = "5MB", mime = ["image/png"], extensions = ["png"])` and
+// `image(min_width = 100, max_height = 800, ratio = "16/9")`. The MIME type is
+// sniffed from the content at runtime, never taken from the client; reading the
+// file makes these `AsyncValidate` checks.
+fn file_rule(field: &Field, rule: &Rule) -> TokenStream {
+    let Field { access, key, .. } = field;
+    let overrides = rule.overrides();
+
+    let check = if rule.name == "file" {
+        let max_size = match rule.arg("max_size") {
+            Some(arg) => {
+                let bytes = parse_size(&arg.value);
+                quote! { Some(#bytes) }
+            }
+            None => quote! { None },
+        };
+        let mime = rule.arg("mime").map(|arg| &arg.value);
+        let extensions = rule.arg("extensions").map(|arg| &arg.value);
+        let mime = mime.map_or_else(|| quote! { &[] }, |mime| quote! { &#mime });
+        let extensions = extensions.map_or_else(|| quote! { &[] }, |ext| quote! { &#ext });
+        quote! {
+            thunder::rules::check_files(
+                &#access,
+                &thunder::rules::FileRule {
+                    max_size: #max_size,
+                    mime: #mime,
+                    extensions: #extensions,
+                },
+            )
+        }
+    } else {
+        let bounds = ["min_width", "max_width", "min_height", "max_height"]
+            .into_iter()
+            .filter_map(|bound| {
+                let value = &rule.arg(bound)?.value;
+                let bound = Ident::new(bound, Span::call_site());
+                Some(quote! { #bound: Some(#value), })
+            });
+        let ratio = rule.arg("ratio").map(|arg| {
+            let ratio = parse_ratio(&arg.value);
+            quote! { ratio: Some(#ratio), }
+        });
+        quote! {
+            thunder::rules::check_images(
+                &#access,
+                &thunder::rules::ImageRule {
+                    #(#bounds)*
+                    #ratio
+                    ..Default::default()
+                },
+            )
+        }
+    };
+
+    quote! {
+        for error in #check.await {
+            errors.add_error(#key, error #overrides);
+        }
+    }
+}
+
+// `password(min_len = 12, mixed_case, numbers, symbols, not_compromised_local_list)`.
+// Only `min_len` is on by default, at 8 characters.
+fn password_rule(field: &Field, rule: &Rule) -> TokenStream {
+    let Field { access, key, .. } = field;
+    let overrides = rule.overrides();
+
+    for flag in &rule.flags {
+        if ![
+            "mixed_case",
+            "numbers",
+            "symbols",
+            "not_compromised_local_list",
+        ]
+        .iter()
+        .any(|known| flag == known)
+        {
+            panic!("Unknown flag `{}` of the `password` rule", flag);
+        }
+    }
+    let min_len = match rule.arg("min_len") {
+        Some(arg) => {
+            let value = &arg.value;
+            quote! { #value }
+        }
+        None => quote! { 8 },
+    };
+    let mixed_case = rule.flag("mixed_case");
+    let numbers = rule.flag("numbers");
+    let symbols = rule.flag("symbols");
+    let not_compromised = rule.flag("not_compromised_local_list");
+
+    quote! {
+        for error in thunder::rules::check_password(
+            &#access,
+            &thunder::rules::PasswordRule {
+                min_len: #min_len,
+                mixed_case: #mixed_case,
+                numbers: #numbers,
+                symbols: #symbols,
+                not_compromised: #not_compromised,
+            },
+        ) {
+            errors.add_error(#key, error #overrides);
+        }
+    }
+}
+
+// "5MB" -> 5242880. Plain integers are bytes.
+fn parse_size(value: &Expr) -> u64 {
+    let text = match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit_str),
+            ..
+        }) => lit_str.value(),
+        Expr::Lit(ExprLit {
+            lit: Lit::Int(lit_int),
+            ..
+        }) => lit_int.base10_digits().to_string(),
+        _ => panic!("`max_size` must be a size such as \"5MB\""),
+    };
+    let text = text.trim().to_uppercase();
+    let digits_end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let amount: u64 = text[..digits_end]
+        .parse()
+        .unwrap_or_else(|_| panic!("Invalid size `{}`", text));
+    let multiplier = match text[digits_end..].trim() {
+        "" | "B" => 1,
+        "KB" | "K" => 1024,
+        "MB" | "M" => 1024 * 1024,
+        "GB" | "G" => 1024 * 1024 * 1024,
+        unit => panic!("Unknown size unit `{}`, expected B, KB, MB or GB", unit),
+    };
+    amount * multiplier
+}
+
+// "16/9" or a plain number such as `1.5`.
+fn parse_ratio(value: &Expr) -> f64 {
+    let text = match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit_str),
+            ..
+        }) => lit_str.value(),
+        Expr::Lit(ExprLit {
+            lit: Lit::Float(lit_float),
+            ..
+        }) => lit_float.base10_digits().to_string(),
+        Expr::Lit(ExprLit {
+            lit: Lit::Int(lit_int),
+            ..
+        }) => lit_int.base10_digits().to_string(),
+        _ => panic!("`ratio` must be a number or a string such as \"16/9\""),
+    };
+    let parse = |part: &str| -> f64 {
+        part.trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid ratio `{}`", text))
+    };
+    match text.split_once('/') {
+        Some((width, height)) => parse(width) / parse(height),
+        None => parse(&text),
+    }
+}
+
+fn is_cross_field_rule(name: &Ident) -> bool {
+    ["must_match", "different", "required_if", "required_with"]
+        .iter()
+        .any(|rule| name == rule)
+}
+
+// The error always lands on `field`, the one the user has to fix.
+fn cross_field_rule(rule: &Rule, field: &Ident, other: &Ident) -> TokenStream {
+    let key = quote! { stringify!(#field) };
+    let other_param = ("other", quote! { stringify!(#other) });
+
+    match rule.name.to_string().as_str() {
+        "must_match" => {
+            let error = rule.error(&key, "must_match", "Must match {other}.", &[other_param]);
+            quote! {
+                if self.#field != self.#other {
+                    #error
+                }
+            }
+        }
+        "different" => {
+            let error = rule.error(
+                &key,
+                "different",
+                "Must be different from {other}.",
+                &[other_param],
+            );
+            quote! {
+                if self.#field == self.#other {
+                    #error
+                }
+            }
+        }
+        "required_if" => {
+            let value = rule.value();
+            let error = rule.error(
+                &key,
+                "required_if",
+                "Required when {other} is {value}.",
+                &[other_param, ("value", quote! { #value })],
+            );
+            quote! {
+                if self.#other == #value && !thunder::rules::Presence::is_present(&self.#field) {
+                    #error
+                }
+            }
+        }
+        "required_with" => {
+            let error = rule.error(
+                &key,
+                "required_with",
+                "Required when {other} is present.",
+                &[other_param],
+            );
+            quote! {
+                if thunder::rules::Presence::is_present(&self.#other)
+                    && !thunder::rules::Presence::is_present(&self.#field)
+                {
+                    #error
+                }
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+// `unique(entity = post::Entity, column = "title", ignore = "id", ignore_column = "id")`
+// and `exists(entity = post::Entity, column = "id")`.
+fn database_rule(field: &Field, rule: &Rule) -> TokenStream {
+    let Field { access, key, .. } = field;
+    let is_unique = rule.name == "unique";
+
+    let entity = parse_path(
+        &rule
+            .arg("entity")
+            .expect("Database rules need an `entity = path::to::Entity` argument")
+            .value,
+    );
+    let column = match rule.arg("column") {
+        Some(column) => parse_string(column),
+        None => field.name.clone(),
+    };
+
+    let (check, error) = if is_unique {
+        let ignore = match rule.arg("ignore") {
+            Some(ignore) => {
+                let ignore_field = parse_ident(ignore);
+                let ignore_column = rule
+                    .arg("ignore_column")
+                    .map(parse_string)
+                    .unwrap_or_else(|| String::from("id"));
+                quote! {
+                    Some((#ignore_column, thunder::rules::Value::from(self.#ignore_field.clone())))
+                }
+            }
+            None => quote! { None },
+        };
+        (
+            quote! {
+                thunder::rules::unique::<#entity>(depot, #column, #access.clone(), #ignore).await
+            },
+            rule.error(key, "unique", "This value has already been taken.", &[]),
+        )
+    } else {
+        (
+            quote! {
+                thunder::rules::exists::<#entity>(depot, #column, #access.clone()).await
+            },
+            rule.error(key, "exists", "The selected value is invalid.", &[]),
+        )
+    };
+
+    quote! {
+        match #check {
+            Ok(true) => {}
+            Ok(false) => {
+                #error
+            }
+            Err(error) => {
+                errors.add_error(#key, thunder::rules::database_failed(#key, error));
+                errors.add_status_code(thunder::StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+}
+
+// Accepts both `custom = path::to::fn` and `custom = "path::to::fn"`.
+fn parse_path(value: &Expr) -> Path {
+    match value {
+        Expr::Path(expr_path) => expr_path.path.clone(),
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit_str),
+            ..
+        }) => lit_str.parse().expect("Expected a path"),
+        _ => panic!("Expected a path"),
+    }
+}
+
+fn serde_rename(field: &syn::Field) -> Option<String> {
+    let mut rename = None;
+    for attr in &field.attrs {
+        if !attr.path().is_ident("serde") {
+            continue;
+        }
+        // Other serde options are not ours to judge, so parsing failures are ignored.
+        let _ = attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                let value: LitStr = meta.value()?.parse()?;
+                rename = Some(value.value());
+            } else if meta.input.peek(Token![=]) {
+                let _: Expr = meta.value()?.parse()?;
+            }
+            Ok(())
+        });
+    }
+    rename
+}
+
+fn parse_ident(nv: &MetaNameValue) -> Ident {
+    parse_ident_expr(&nv.value)
+}
+
+fn parse_ident_expr(value: &Expr) -> Ident {
+    match value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit_str),
+            ..
+        }) => Ident::new(&lit_str.value(), Span::call_site()),
+        _ => panic!("Expected a field name as a string literal"),
+    }
+}
+
+fn parse_lit_str(nv: &MetaNameValue) -> LitStr {
+    match &nv.value {
+        Expr::Lit(ExprLit {
+            lit: Lit::Str(lit_str),
+            ..
+        }) => lit_str.clone(),
+        _ => panic!("Expected a string literal"),
+    }
+}
+
+// `groups = ["create", "admin"]`
+fn parse_groups(nv: &MetaNameValue) -> Vec<LitStr> {
+    let Expr::Array(array) = &nv.value else {
+        panic!("Expected a list of groups, e.g. `groups = [\"create\"]`");
+    };
+    array
+        .elems
+        .iter()
+        .map(|elem| match elem {
+            Expr::Lit(ExprLit {
+                lit: Lit::Str(lit_str),
+                ..
+            }) => lit_str.clone(),
+            _ => panic!("Groups must be string literals"),
+        })
+        .collect()
+}
+
+fn parse_string(nv: &MetaNameValue) -> String {
+    parse_lit_str(nv).value()
+}
@@ -0,0 +1,647 @@
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Debug, Formatter};
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::Arc;
+
+use salvo::http::{StatusCode, StatusError};
+use salvo::oapi::{Components, EndpointArgRegister, EndpointOutRegister, Operation, ToSchema};
+use salvo::{Depot, FlowCtrl, Handler, Request, Response, Writer, async_trait};
+use salvo_core::extract::{Extractible, Metadata};
+use serde::Serialize;
+
+use super::{Auth, Authenticatable, Rejection, missing_handler, wants_page};
+use crate::Inertia;
+
+// Who may do what with the models of type `M`, e.g. only the author updates a post.
+// Actions a policy does not implement are denied.
+pub trait Policy<M>: Send + Sync + 'static {
+    type User: Authenticatable;
+
+    fn view(&self, user: &Self::User, model: &M) -> bool {
+        let _ = (user, model);
+        false
+    }
+
+    fn create(&self, user: &Self::User) -> bool {
+        let _ = user;
+        false
+    }
+
+    fn update(&self, user: &Self::User, model: &M) -> bool {
+        let _ = (user, model);
+        false
+    }
+
+    fn delete(&self, user: &Self::User, model: &M) -> bool {
+        let _ = (user, model);
+        false
+    }
+}
+
+type AbilityFn<U> = Arc<dyn Fn(&U) -> bool + Send + Sync>;
+type BeforeFn<U> = Arc<dyn Fn(&U, &str) -> Option<bool> + Send + Sync>;
+
+// The abilities of the app and the policies of its models. Used as a hoop after
+// `AuthHandler`, it makes itself available to handlers and `Authorized<A>`:
+//
+//     let gate = Gate::new()
+//         .define("view-reports", |user: &user::Model| user.is_admin)
+//         .policy::<post::Model>(PostPolicy);
+//     Router::new().hoop(AuthHandler::new(users)).hoop(gate)
+//
+// Abilities nobody defined, and models without a policy, are denied.
+pub struct Gate<U: Authenticatable> {
+    before: Arc<Vec<BeforeFn<U>>>,
+    abilities: Arc<BTreeMap<String, AbilityFn<U>>>,
+    // `Arc<dyn Policy<M, User = U>>` by the `TypeId` of `M`.
+    policies: Arc<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>,
+}
+
+impl<U: Authenticatable> Clone for Gate<U> {
+    fn clone(&self) -> Self {
+        Gate {
+            before: self.before.clone(),
+            abilities: self.abilities.clone(),
+            policies: self.policies.clone(),
+        }
+    }
+}
+
+impl<U: Authenticatable> Debug for Gate<U> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Gate")
+            .field("abilities", &self.abilities.keys().collect::<Vec<_>>())
+            .field("policies", &self.policies.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<U: Authenticatable> Default for Gate<U> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<U: Authenticatable> Gate<U> {
+    pub fn new() -> Self {
+        Gate {
+            before: Arc::new(Vec::new()),
+            abilities: Arc::new(BTreeMap::new()),
+            policies: Arc::new(HashMap::new()),
+        }
+    }
+
+    // Runs ahead of every check, with the name of the ability or policy action; an
+    // answer other than `None` is final. E.g. administrators may do everything.
+    pub fn before(
+        mut self,
+        check: impl Fn(&U, &str) -> Option<bool> + Send + Sync + 'static,
+    ) -> Self {
+        Arc::make_mut(&mut self.before).push(Arc::new(check));
+        self
+    }
+
+    // An ability that belongs to no model, e.g. `view-reports`.
+    pub fn define(
+        mut self,
+        ability: impl Into<String>,
+        check: impl Fn(&U) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        Arc::make_mut(&mut self.abilities).insert(ability.into(), Arc::new(check));
+        self
+    }
+
+    // The policy of the models of type `M`, replacing any earlier one.
+    pub fn policy<M: 'static>(mut self, policy: impl Policy<M, User = U>) -> Self {
+        let policy: Arc<dyn Policy<M, User = U>> = Arc::new(policy);
+        Arc::make_mut(&mut self.policies).insert(TypeId::of::<M>(), Arc::new(policy));
+        self
+    }
+
+    fn before_checks(&self, user: &U, ability: &str) -> Option<bool> {
+        self.before.iter().find_map(|check| check(user, ability))
+    }
+
+    fn check_policy<M: 'static>(
+        &self,
+        user: &U,
+        action: &str,
+        check: impl FnOnce(&dyn Policy<M, User = U>) -> bool,
+    ) -> bool {
+        if let Some(allowed) = self.before_checks(user, action) {
+            return allowed;
+        }
+        self.policies
+            .get(&TypeId::of::<M>())
+            .and_then(|policy| policy.downcast_ref::<Arc<dyn Policy<M, User = U>>>())
+            .is_some_and(|policy| check(policy.as_ref()))
+    }
+
+    // Whether `user` has the ability defined as `ability`.
+    pub fn allows(&self, user: &U, ability: &str) -> bool {
+        if let Some(allowed) = self.before_checks(user, ability) {
+            return allowed;
+        }
+        self.abilities.get(ability).is_some_and(|check| check(user))
+    }
+
+    pub fn denies(&self, user: &U, ability: &str) -> bool {
+        !self.allows(user, ability)
+    }
+
+    pub fn can_view<M: 'static>(&self, user: &U, model: &M) -> bool {
+        self.check_policy::<M>(user, "view", |policy| policy.view(user, model))
+    }
+
+    pub fn can_create<M: 'static>(&self, user: &U) -> bool {
+        self.check_policy::<M>(user, "create", |policy| policy.create(user))
+    }
+
+    pub fn can_update<M: 'static>(&self, user: &U, model: &M) -> bool {
+        self.check_policy::<M>(user, "update", |policy| policy.update(user, model))
+    }
+
+    pub fn can_delete<M: 'static>(&self, user: &U, model: &M) -> bool {
+        self.check_policy::<M>(user, "delete", |policy| policy.delete(user, model))
+    }
+
+    // Every defined ability of `user`, for the props of a page; guests have none.
+    pub fn abilities(&self, user: Option<&U>) -> Abilities {
+        let abilities = self
+            .abilities
+            .keys()
+            .map(|ability| {
+                let allowed = user.is_some_and(|user| self.allows(user, ability));
+                (ability.clone(), allowed)
+            })
+            .collect();
+        Abilities(abilities)
+    }
+
+    // What `user` may do with `model`, for the props of a page showing it.
+    pub fn model_abilities<M: 'static>(&self, user: Option<&U>, model: &M) -> ModelAbilities {
+        let Some(user) = user else {
+            return ModelAbilities::default();
+        };
+        ModelAbilities {
+            view: self.can_view(user, model),
+            update: self.can_update(user, model),
+            delete: self.can_delete(user, model),
+        }
+    }
+}
+
+#[async_trait]
+impl<U: Authenticatable> Handler for Gate<U> {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        _res: &mut Response,
+        _ctrl: &mut FlowCtrl,
+    ) {
+        req.extensions_mut().insert(self.clone());
+        depot.inject(self.clone());
+    }
+}
+
+// Ability names and whether the user has them, e.g. `{"view-reports": true}`, so the
+// UI hides what the server would refuse.
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+#[serde(transparent)]
+pub struct Abilities(BTreeMap<String, bool>);
+
+impl Abilities {
+    // Adds an answer the gate does not know by name, e.g. `can_create` of a policy.
+    pub fn with(mut self, ability: impl Into<String>, allowed: bool) -> Self {
+        self.0.insert(ability.into(), allowed);
+        self
+    }
+
+    pub fn get(&self, ability: &str) -> bool {
+        self.0.get(ability).copied().unwrap_or(false)
+    }
+}
+
+// The policy actions on one model; `create` belongs in the page's `Abilities`.
+#[derive(Debug, Clone, Copy, Default, Serialize, ToSchema)]
+pub struct ModelAbilities {
+    pub view: bool,
+    pub update: bool,
+    pub delete: bool,
+}
+
+// A refused action. Pages, Inertia visits included, get the `Error` page with a 403;
+// other clients a JSON 403.
+//
+//     authorize!(gate.can_update(&user, &post));
+#[derive(Debug, Clone)]
+pub struct Forbidden {
+    message: String,
+}
+
+impl Default for Forbidden {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Forbidden {
+    pub fn new() -> Self {
+        Forbidden {
+            message: "This action is unauthorized.".to_string(),
+        }
+    }
+
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+}
+
+#[async_trait]
+impl Writer for Forbidden {
+    async fn write(self, req: &mut Request, depot: &mut Depot, res: &mut Response) {
+        if wants_page(req) {
+            Inertia::error(StatusCode::FORBIDDEN, self.message)
+                .write(req, depot, res)
+                .await;
+        } else {
+            res.render(StatusError::forbidden().brief(self.message));
+        }
+    }
+}
+
+impl EndpointOutRegister for Forbidden {
+    fn register(_components: &mut Components, operation: &mut Operation) {
+        operation.responses.insert(
+            "403",
+            salvo::oapi::Response::new("This action is unauthorized."),
+        );
+    }
+}
+
+// Returns a `Forbidden` from the handler unless `allowed` holds. Handlers that fail
+// in other ways too return an error type with a `From<Forbidden>` that writes it
+// as it is, so pages still get the `Error` page:
+//
+//     authorize!(gate.can_delete(&user, &post));
+//     authorize!(gate.allows(&user, "view-reports"), "Reports are for administrators.");
+#[macro_export]
+macro_rules! authorize {
+    ($allowed:expr) => {
+        if !$allowed {
+            return Err($crate::Forbidden::new().into());
+        }
+    };
+    ($allowed:expr, $message:expr) => {
+        if !$allowed {
+            return Err($crate::Forbidden::new().message($message).into());
+        }
+    };
+}
+
+// An ability `Authorized<A>` checks before the handler runs.
+//
+//     struct ViewReports;
+//
+//     impl Ability for ViewReports {
+//         type User = user::Model;
+//
+//         fn allows(gate: &Gate<user::Model>, user: &user::Model) -> bool {
+//             gate.allows(user, "view-reports")
+//         }
+//     }
+pub trait Ability: Send + Sync + 'static {
+    type User: Authenticatable;
+
+    fn allows(gate: &Gate<Self::User>, user: &Self::User) -> bool;
+}
+
+fn missing_gate() -> StatusError {
+    StatusError::internal_server_error()
+        .brief("No gate: add the Gate as a hoop in front of this route.")
+}
+
+impl<'ex, U: Authenticatable> Extractible<'ex> for Gate<U> {
+    fn metadata() -> &'static Metadata {
+        static METADATA: Metadata = Metadata::new("");
+        &METADATA
+    }
+
+    async fn extract(
+        req: &'ex mut Request,
+    ) -> Result<Self, impl Writer + Send + fmt::Debug + 'static> {
+        req.extensions()
+            .get::<Gate<U>>()
+            .cloned()
+            .ok_or_else(missing_gate)
+    }
+}
+
+impl<U: Authenticatable> EndpointArgRegister for Gate<U> {
+    fn register(_components: &mut Components, _operation: &mut Operation, _arg: &str) {}
+}
+
+// The authenticated user, who has the ability `A`. Guests are sent to log in, or get
+// a 401, like with `CurrentUser`; users without the ability get a `Forbidden`.
+pub struct Authorized<A: Ability> {
+    user: A::User,
+    ability: PhantomData<A>,
+}
+
+impl<A: Ability> Authorized<A> {
+    pub fn into_inner(self) -> A::User {
+        self.user
+    }
+}
+
+impl<A: Ability> Deref for Authorized<A> {
+    type Target = A::User;
+
+    fn deref(&self) -> &Self::Target {
+        &self.user
+    }
+}
+
+impl<A: Ability> Debug for Authorized<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Authorized").finish_non_exhaustive()
+    }
+}
+
+impl<'ex, A: Ability> Extractible<'ex> for Authorized<A> {
+    fn metadata() -> &'static Metadata {
+        static METADATA: Metadata = Metadata::new("");
+        &METADATA
+    }
+
+    async fn extract(
+        req: &'ex mut Request,
+    ) -> Result<Self, impl Writer + Send + fmt::Debug + 'static> {
+        let auth = req
+            .extensions()
+            .get::<Auth<A::User>>()
+            .ok_or_else(|| Rejection::Status(missing_handler()))?;
+        let user = auth
+            .user()
+            .ok_or_else(|| Rejection::Unauthenticated(auth.state.login_url.clone()))?;
+        let gate = req
+            .extensions()
+            .get::<Gate<A::User>>()
+            .ok_or_else(|| Rejection::Status(missing_gate()))?;
+        if !A::allows(gate, &user) {
+            return Err(Rejection::Forbidden(Forbidden::new()));
+        }
+        Ok(Authorized {
+            user,
+            ability: PhantomData,
+        })
+    }
+}
+
+impl<A: Ability> EndpointArgRegister for Authorized<A> {
+    fn register(_components: &mut Components, operation: &mut Operation, _arg: &str) {
+        operation
+            .responses
+            .insert("401", salvo::oapi::Response::new("Unauthenticated."));
+        operation.responses.insert(
+            "403",
+            salvo::oapi::Response::new("This action is unauthorized."),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo::test::{ResponseExt, TestClient};
+    use salvo::{Router, Service, handler};
+
+    use super::*;
+    use crate::{AuthError, AuthHandler, Guard, UserProvider};
+
+    #[derive(Debug, Clone)]
+    struct User {
+        id: i64,
+        admin: bool,
+    }
+
+    impl Authenticatable for User {
+        type Id = i64;
+
+        fn auth_id(&self) -> i64 {
+            self.id
+        }
+
+        fn password_hash(&self) -> &str {
+            ""
+        }
+    }
+
+    const ADMIN: User = User { id: 1, admin: true };
+    const AUTHOR: User = User {
+        id: 2,
+        admin: false,
+    };
+    const READER: User = User {
+        id: 3,
+        admin: false,
+    };
+
+    struct Post {
+        author_id: i64,
+    }
+
+    // A model nobody wrote a policy for.
+    struct Invoice;
+
+    struct PostPolicy;
+
+    impl Policy<Post> for PostPolicy {
+        type User = User;
+
+        fn view(&self, _user: &User, _post: &Post) -> bool {
+            true
+        }
+
+        fn update(&self, user: &User, post: &Post) -> bool {
+            user.id == post.author_id
+        }
+    }
+
+    fn gate() -> Gate<User> {
+        Gate::new()
+            .before(|user: &User, ability| {
+                // Administrators may do everything but delete.
+                (user.admin && ability != "delete").then_some(true)
+            })
+            .define("view-reports", |user: &User| user.id == AUTHOR.id)
+            .policy::<Post>(PostPolicy)
+    }
+
+    #[test]
+    fn checks_the_defined_abilities() {
+        let gate = gate();
+        assert!(gate.allows(&AUTHOR, "view-reports"));
+        assert!(gate.denies(&READER, "view-reports"));
+        // Undefined abilities are denied, but `before` still answers first.
+        assert!(gate.denies(&AUTHOR, "export"));
+        assert!(gate.allows(&ADMIN, "export"));
+    }
+
+    #[test]
+    fn asks_before_ahead_of_the_policies() {
+        let gate = gate();
+        let post = Post {
+            author_id: AUTHOR.id,
+        };
+        assert!(gate.can_update(&AUTHOR, &post));
+        assert!(!gate.can_update(&READER, &post));
+        assert!(gate.can_update(&ADMIN, &post));
+
+        // `None` from `before` leaves it to the policy, which denies what it
+        // does not implement.
+        assert!(!gate.can_delete(&ADMIN, &post));
+        assert!(!gate.can_delete(&AUTHOR, &post));
+        assert!(!gate.can_create::<Post>(&AUTHOR));
+    }
+
+    #[test]
+    fn denies_models_without_a_policy() {
+        let gate = Gate::new().policy::<Post>(PostPolicy);
+        assert!(!gate.can_view(&AUTHOR, &Invoice));
+        assert!(gate.can_view(&AUTHOR, &Post { author_id: 9 }));
+
+        // A later policy replaces the earlier one.
+        struct Closed;
+        impl Policy<Post> for Closed {
+            type User = User;
+        }
+        let gate = gate.policy::<Post>(Closed);
+        assert!(!gate.can_view(&AUTHOR, &Post { author_id: 9 }));
+    }
+
+    #[test]
+    fn lists_the_abilities_for_the_page() {
+        let gate = gate();
+        assert!(gate.abilities(Some(&AUTHOR)).get("view-reports"));
+        assert!(!gate.abilities(None).get("view-reports"));
+
+        let post = Post {
+            author_id: AUTHOR.id,
+        };
+        let abilities = gate.model_abilities(Some(&AUTHOR), &post);
+        assert!(abilities.view && abilities.update && !abilities.delete);
+        let abilities = gate.model_abilities(None, &post);
+        assert!(!abilities.view && !abilities.update);
+    }
+
+    fn update(gate: &Gate<User>, user: &User, post: &Post) -> Result<&'static str, Forbidden> {
+        authorize!(gate.can_update(user, post));
+        authorize!(user.id != 0, "Nobody is user 0.");
+        Ok("updated")
+    }
+
+    #[test]
+    fn returns_forbidden_from_authorize() {
+        let gate = gate();
+        let post = Post {
+            author_id: AUTHOR.id,
+        };
+        assert_eq!(update(&gate, &AUTHOR, &post).unwrap(), "updated");
+        assert_eq!(
+            update(&gate, &READER, &post).unwrap_err().message,
+            "This action is unauthorized."
+        );
+        let nobody = User { id: 0, admin: true };
+        assert_eq!(
+            update(&gate, &nobody, &post).unwrap_err().message,
+            "Nobody is user 0."
+        );
+    }
+
+    // Users by bearer token, their id.
+    struct Users;
+
+    #[async_trait]
+    impl UserProvider for Users {
+        type User = User;
+
+        async fn retrieve_by_id(&self, _id: &i64) -> Result<Option<User>, AuthError> {
+            Ok(None)
+        }
+
+        async fn retrieve_by_login(&self, _login: &str) -> Result<Option<User>, AuthError> {
+            Ok(None)
+        }
+
+        async fn retrieve_by_token(&self, token: &str) -> Result<Option<User>, AuthError> {
+            Ok([ADMIN, AUTHOR, READER]
+                .into_iter()
+                .find(|user| user.id.to_string() == token))
+        }
+    }
+
+    struct ViewReports;
+
+    impl Ability for ViewReports {
+        type User = User;
+
+        fn allows(gate: &Gate<User>, user: &User) -> bool {
+            gate.allows(user, "view-reports")
+        }
+    }
+
+    #[handler]
+    async fn reports(user: Authorized<ViewReports>) -> String {
+        format!("reports for {}", user.id)
+    }
+
+    fn service() -> Service {
+        let router = Router::new()
+            .hoop(AuthHandler::new(Users).guards([Guard::Token]))
+            .hoop(gate())
+            .push(Router::with_path("reports").get(reports));
+        Service::new(router)
+    }
+
+    async fn get_reports(user: Option<&User>, inertia: bool) -> Response {
+        let mut request = TestClient::get("http://localhost/reports");
+        if let Some(user) = user {
+            request = request.add_header("authorization", format!("Bearer {}", user.id), true);
+        }
+        if inertia {
+            request = request.add_header("X-Inertia", "true", true);
+        }
+        request.send(&service()).await
+    }
+
+    #[tokio::test]
+    async fn lets_users_with_the_ability_through() {
+        let mut res = get_reports(Some(&AUTHOR), false).await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        assert_eq!(res.take_string().await.unwrap(), "reports for 2");
+    }
+
+    #[tokio::test]
+    async fn answers_403_to_users_without_the_ability() {
+        let res = get_reports(Some(&READER), false).await;
+        assert_eq!(res.status_code, Some(StatusCode::FORBIDDEN));
+
+        // Inertia visits get the `Error` page.
+        let mut res = get_reports(Some(&READER), true).await;
+        assert_eq!(res.status_code, Some(StatusCode::FORBIDDEN));
+        let page: serde_json::Value = res.take_json().await.unwrap();
+        assert_eq!(page["component"], "Error");
+        assert_eq!(page["props"]["status"], 403);
+        assert_eq!(page["props"]["message"], "This action is unauthorized.");
+    }
+
+    #[tokio::test]
+    async fn answers_401_to_guests() {
+        let res = get_reports(None, false).await;
+        assert_eq!(res.status_code, Some(StatusCode::UNAUTHORIZED));
+    }
+}
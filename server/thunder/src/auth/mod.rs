@@ -0,0 +1,827 @@
+// Authentication: who is making the request. `AuthHandler` finds the user through
+// its guards, the session or a bearer token; handlers get them with `CurrentUser<U>`
+// and log in and out through `Auth<U>`. The session guard needs a `SessionHandler`
+// in front of it.
+//
+//     Router::new()
+//         .hoop(SessionHandler::new(store))
+//         .hoop(AuthHandler::new(SeaOrmUserProvider::<user::Entity>::new(db, user::Column::Email)))
+//         .push(Router::with_path("dashboard").hoop(Authenticate).get(dashboard))
+//         .push(Router::with_path("login").hoop(RedirectIfAuthenticated).post(login))
+//
+// Authorization, what the user may do, is up to the `Gate` and its policies.
+use std::fmt::{self, Debug, Display, Formatter};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use salvo::http::{HeaderValue, Method, StatusCode, StatusError, header};
+use salvo::oapi::{Components, EndpointArgRegister, Operation};
+use salvo::writing::Redirect;
+use salvo::{Depot, FlowCtrl, Handler, Request, Response, Writer, async_trait};
+use salvo_core::extract::{Extractible, Metadata};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use crate::{Hasher, Session, SessionError};
+
+mod gate;
+mod provider;
+
+pub use self::gate::{Abilities, Ability, Authorized, Forbidden, Gate, ModelAbilities, Policy};
+pub use self::provider::{SeaOrmUserProvider, UserProvider, hash_api_token, new_api_token};
+
+// Where the session guard keeps the id of the user.
+const USER_KEY: &str = "_auth_user_id";
+// Where `Authenticate` keeps the page a guest asked for, for `Auth::intended`.
+const INTENDED_KEY: &str = "_auth_intended_url";
+
+#[derive(Debug)]
+pub enum AuthError {
+    // The user provider failed, e.g. the database is down.
+    Provider(String),
+    Session(SessionError),
+    // Logging in needs a session, and there is no `SessionHandler` before `AuthHandler`.
+    NoSession,
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Provider(message) => write!(f, "user provider error: {}", message),
+            AuthError::Session(error) => write!(f, "{}", error),
+            AuthError::NoSession => write!(f, "logging in needs a SessionHandler hoop"),
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+// A user that can log in.
+pub trait Authenticatable: Clone + Send + Sync + 'static {
+    // What the session remembers the user by, usually the primary key.
+    type Id: Serialize + DeserializeOwned + Clone + Send + Sync;
+
+    fn auth_id(&self) -> Self::Id;
+
+    // The stored hash `Auth::attempt` checks passwords against, see `Hasher`.
+    fn password_hash(&self) -> &str;
+}
+
+// How `AuthHandler` recognizes the user of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Guard {
+    // The id `Auth::login` stored in the session.
+    Session,
+    // An `Authorization: Bearer <token>` header, see `UserProvider::retrieve_by_token`.
+    Token,
+}
+
+// The guard that recognized the user of the request, kept in its extensions.
+// `VerifyCsrfToken` lets a request through unchecked only when it is `Guard::Token`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AuthenticatedBy(pub(crate) Guard);
+
+// What the hoops need to know, whatever the type of the user.
+#[derive(Debug, Clone)]
+struct AuthState {
+    authenticated: Arc<AtomicBool>,
+    login_url: Arc<str>,
+    home_url: Arc<str>,
+}
+
+// The authentication of the current request. Clones share the same user.
+pub struct Auth<U: Authenticatable> {
+    provider: Arc<dyn UserProvider<User = U>>,
+    hasher: Hasher,
+    session: Option<Session>,
+    user: Arc<Mutex<Option<U>>>,
+    state: AuthState,
+}
+
+impl<U: Authenticatable> Clone for Auth<U> {
+    fn clone(&self) -> Self {
+        Auth {
+            provider: self.provider.clone(),
+            hasher: self.hasher.clone(),
+            session: self.session.clone(),
+            user: self.user.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<U: Authenticatable> Debug for Auth<U> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Auth")
+            .field("authenticated", &self.check())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<U: Authenticatable> Auth<U> {
+    fn slot(&self) -> MutexGuard<'_, Option<U>> {
+        self.user.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    fn set_user(&self, user: Option<U>) {
+        self.state
+            .authenticated
+            .store(user.is_some(), Ordering::Relaxed);
+        *self.slot() = user;
+    }
+
+    pub fn user(&self) -> Option<U> {
+        self.slot().clone()
+    }
+
+    pub fn id(&self) -> Option<U::Id> {
+        self.slot().as_ref().map(Authenticatable::auth_id)
+    }
+
+    // Whether the request has a user.
+    pub fn check(&self) -> bool {
+        self.slot().is_some()
+    }
+
+    /// Returns the user with `login` if `password` is theirs, without logging them in.
+    /// A hash made with other settings is replaced while the password is at hand.
+    pub async fn validate(&self, login: &str, password: &str) -> Result<Option<U>, AuthError> {
+        let Some(user) = self.provider.retrieve_by_login(login).await? else {
+            // As slow as a wrong password, so the timing does not tell who has an account.
+            let _ = self
+                .hash(password, |hasher, password| hasher.make(&password))
+                .await;
+            return Ok(None);
+        };
+
+        let hash = user.password_hash().to_string();
+        let (valid, rehash) = self
+            .hash(password, move |hasher, password| {
+                if !hasher.check(&password, &hash) {
+                    return (false, None);
+                }
+                let rehash = hasher
+                    .needs_rehash(&hash)
+                    .then(|| hasher.make(&password).ok())
+                    .flatten();
+                (true, rehash)
+            })
+            .await;
+        if !valid {
+            return Ok(None);
+        }
+        if let Some(hash) = rehash {
+            self.provider.update_password_hash(&user, &hash).await?;
+        }
+        Ok(Some(user))
+    }
+
+    // Runs `f` away from the async workers: hashing takes a good fraction of a second.
+    async fn hash<T: Send + 'static>(
+        &self,
+        password: &str,
+        f: impl FnOnce(&Hasher, String) -> T + Send + 'static,
+    ) -> T {
+        let hasher = self.hasher.clone();
+        let password = password.to_string();
+        tokio::task::spawn_blocking(move || f(&hasher, password))
+            .await
+            .expect("password hashing panicked")
+    }
+
+    /// Logs in the user with `login` if `password` is theirs.
+    pub async fn attempt(&self, login: &str, password: &str) -> Result<bool, AuthError> {
+        match self.validate(login, password).await? {
+            Some(user) => self.login(user).map(|()| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Makes `user` the user of this session. The session gets a new id and CSRF
+    /// token, so whatever was planted before the login is worthless.
+    pub fn login(&self, user: U) -> Result<(), AuthError> {
+        let session = self.session.as_ref().ok_or(AuthError::NoSession)?;
+        session.regenerate();
+        session.regenerate_token();
+        session
+            .insert(USER_KEY, user.auth_id())
+            .map_err(AuthError::Session)?;
+        self.set_user(Some(user));
+        Ok(())
+    }
+
+    /// Forgets the user and everything else in the session.
+    pub fn logout(&self) {
+        if let Some(session) = &self.session {
+            session.invalidate();
+        }
+        self.set_user(None);
+    }
+
+    /// The page the user asked for before being sent to log in, else `default`.
+    pub fn intended(&self, default: &str) -> String {
+        self.session
+            .as_ref()
+            .and_then(|session| session.remove::<String>(INTENDED_KEY))
+            .unwrap_or_else(|| default.to_string())
+    }
+}
+
+// Hoop that authenticates every request, trying the guards in order.
+pub struct AuthHandler<U: Authenticatable> {
+    provider: Arc<dyn UserProvider<User = U>>,
+    hasher: Hasher,
+    guards: Vec<Guard>,
+    login_url: Arc<str>,
+    home_url: Arc<str>,
+}
+
+impl<U: Authenticatable> AuthHandler<U> {
+    pub fn new(provider: impl UserProvider<User = U> + 'static) -> Self {
+        AuthHandler {
+            provider: Arc::new(provider),
+            hasher: Hasher::new(),
+            guards: vec![Guard::Session],
+            login_url: "/login".into(),
+            home_url: "/".into(),
+        }
+    }
+
+    // `[Guard::Session]` by default; APIs add or use `Guard::Token`.
+    pub fn guards(mut self, guards: impl IntoIterator<Item = Guard>) -> Self {
+        self.guards = guards.into_iter().collect();
+        self
+    }
+
+    // How passwords are checked, and rehashed when the hasher has other settings.
+    pub fn hasher(mut self, hasher: Hasher) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    // Where guests are sent, `/login` by default.
+    pub fn login_url(mut self, url: impl Into<String>) -> Self {
+        self.login_url = url.into().into();
+        self
+    }
+
+    // Where `RedirectIfAuthenticated` sends users, `/` by default.
+    pub fn home_url(mut self, url: impl Into<String>) -> Self {
+        self.home_url = url.into().into();
+        self
+    }
+
+    async fn authenticate(
+        &self,
+        req: &Request,
+        session: Option<&Session>,
+    ) -> Result<Option<(U, Guard)>, AuthError> {
+        for guard in &self.guards {
+            let user = match guard {
+                Guard::Session => match session.and_then(|session| session.get(USER_KEY)) {
+                    Some(id) => self.provider.retrieve_by_id(&id).await?,
+                    None => None,
+                },
+                Guard::Token => match bearer_token(req) {
+                    Some(token) => self.provider.retrieve_by_token(token).await?,
+                    None => None,
+                },
+            };
+            if let Some(user) = user {
+                return Ok(Some((user, *guard)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn bearer_token(req: &Request) -> Option<&str> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+#[async_trait]
+impl<U: Authenticatable> Handler for AuthHandler<U> {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let session = req.extensions().get::<Session>().cloned();
+        let user = match self.authenticate(req, session.as_ref()).await {
+            Ok(Some((user, guard))) => {
+                req.extensions_mut().insert(AuthenticatedBy(guard));
+                Some(user)
+            }
+            Ok(None) => None,
+            Err(error) => {
+                res.render(StatusError::internal_server_error().brief(error.to_string()));
+                ctrl.skip_rest();
+                return;
+            }
+        };
+        let auth = Auth {
+            provider: self.provider.clone(),
+            hasher: self.hasher.clone(),
+            session,
+            user: Arc::new(Mutex::new(None)),
+            state: AuthState {
+                authenticated: Arc::new(AtomicBool::new(false)),
+                login_url: self.login_url.clone(),
+                home_url: self.home_url.clone(),
+            },
+        };
+        auth.set_user(user);
+        req.extensions_mut().insert(auth.state.clone());
+        req.extensions_mut().insert(auth.clone());
+        depot.inject(auth);
+    }
+}
+
+// Whether the request comes from a browser, Inertia visits included, rather than
+// e.g. a `fetch` call expecting JSON.
+fn wants_page(req: &Request) -> bool {
+    req.headers().contains_key("X-Inertia")
+        || req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"))
+}
+
+// The page a guest asked for, for `Auth::intended` after the login.
+fn remember_intended(req: &Request) {
+    if let Some(session) = req.extensions().get::<Session>() {
+        let url = req
+            .uri()
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+        // A string always serializes.
+        let _ = session.insert(INTENDED_KEY, url);
+    }
+}
+
+// Browsers are sent to log in; other clients get a 401.
+async fn unauthenticated(req: &mut Request, res: &mut Response, login_url: &str) {
+    if !wants_page(req) {
+        res.render(StatusError::unauthorized().brief("Unauthenticated."));
+        return;
+    }
+
+    if *req.method() == Method::GET {
+        remember_intended(req);
+        res.render(Redirect::found(login_url));
+    } else {
+        // Inertia only follows a 303 after a PUT, PATCH or DELETE.
+        res.render(Redirect::other(login_url));
+    }
+}
+
+fn missing_handler() -> StatusError {
+    StatusError::internal_server_error()
+        .brief("No authentication: add an AuthHandler hoop in front of this route.")
+}
+
+// Hoop that lets only authenticated requests through, the `auth` middleware.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Authenticate;
+
+#[async_trait]
+impl Handler for Authenticate {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let Some(state) = req.extensions().get::<AuthState>().cloned() else {
+            res.render(missing_handler());
+            ctrl.skip_rest();
+            return;
+        };
+        if !state.authenticated.load(Ordering::Relaxed) {
+            unauthenticated(req, res, &state.login_url).await;
+            ctrl.skip_rest();
+        }
+    }
+}
+
+// Hoop that sends authenticated users home, e.g. away from the login page: the
+// `guest` middleware.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RedirectIfAuthenticated;
+
+#[async_trait]
+impl Handler for RedirectIfAuthenticated {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let Some(state) = req.extensions().get::<AuthState>().cloned() else {
+            res.render(missing_handler());
+            ctrl.skip_rest();
+            return;
+        };
+        if state.authenticated.load(Ordering::Relaxed) {
+            res.render(Redirect::other(&*state.home_url));
+            ctrl.skip_rest();
+        }
+    }
+}
+
+impl<'ex, U: Authenticatable> Extractible<'ex> for Auth<U> {
+    fn metadata() -> &'static Metadata {
+        static METADATA: Metadata = Metadata::new("");
+        &METADATA
+    }
+
+    async fn extract(
+        req: &'ex mut Request,
+    ) -> Result<Self, impl Writer + Send + fmt::Debug + 'static> {
+        req.extensions()
+            .get::<Auth<U>>()
+            .cloned()
+            .ok_or_else(missing_handler)
+    }
+}
+
+impl<U: Authenticatable> EndpointArgRegister for Auth<U> {
+    fn register(_components: &mut Components, _operation: &mut Operation, _arg: &str) {}
+}
+
+// The authenticated user. Guests on an Inertia visit are sent to log in, others
+// get a 401.
+#[derive(Debug, Clone)]
+pub struct CurrentUser<U>(pub U);
+
+impl<U> CurrentUser<U> {
+    pub fn into_inner(self) -> U {
+        self.0
+    }
+}
+
+impl<U> Deref for CurrentUser<U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+// Why `CurrentUser` or `Authorized` could not be extracted.
+#[derive(Debug)]
+enum Rejection {
+    // A hoop is missing.
+    Status(StatusError),
+    Unauthenticated(Arc<str>),
+    Forbidden(Forbidden),
+}
+
+#[async_trait]
+impl Writer for Rejection {
+    async fn write(self, req: &mut Request, depot: &mut Depot, res: &mut Response) {
+        match self {
+            Rejection::Status(error) => res.render(error),
+            // Salvo turns anything but an error from a failed extractor into a 400,
+            // so there is no redirect here: Inertia visits get the 409 that makes the
+            // client load the login page, everything else a 401. Routes that send
+            // plain browsers to log in put `Authenticate` in front.
+            Rejection::Unauthenticated(login_url) => {
+                if !req.headers().contains_key("X-Inertia") {
+                    res.render(StatusError::unauthorized().brief("Unauthenticated."));
+                    return;
+                }
+                if *req.method() == Method::GET {
+                    remember_intended(req);
+                }
+                res.status_code(StatusCode::CONFLICT);
+                match HeaderValue::from_str(&login_url) {
+                    Ok(location) => {
+                        res.headers_mut().insert("X-Inertia-Location", location);
+                    }
+                    Err(_) => res.render(StatusError::unauthorized().brief("Unauthenticated.")),
+                }
+            }
+            Rejection::Forbidden(forbidden) => forbidden.write(req, depot, res).await,
+        }
+    }
+}
+
+impl<'ex, U: Authenticatable> Extractible<'ex> for CurrentUser<U> {
+    fn metadata() -> &'static Metadata {
+        static METADATA: Metadata = Metadata::new("");
+        &METADATA
+    }
+
+    async fn extract(
+        req: &'ex mut Request,
+    ) -> Result<Self, impl Writer + Send + fmt::Debug + 'static> {
+        let auth = req
+            .extensions()
+            .get::<Auth<U>>()
+            .ok_or_else(|| Rejection::Status(missing_handler()))?;
+        auth.user()
+            .map(CurrentUser)
+            .ok_or_else(|| Rejection::Unauthenticated(auth.state.login_url.clone()))
+    }
+}
+
+impl<U> EndpointArgRegister for CurrentUser<U> {
+    fn register(_components: &mut Components, operation: &mut Operation, _arg: &str) {
+        operation
+            .responses
+            .insert("401", salvo::oapi::Response::new("Unauthenticated."));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo::http::StatusCode;
+    use salvo::test::{ResponseExt, TestClient};
+    use salvo::{Router, Service, handler};
+    use sea_orm::{ConnectionTrait, Database, DatabaseConnection, EntityTrait};
+
+    use super::*;
+    use crate::{MemorySessionStore, SessionHandler};
+
+    mod user {
+        use sea_orm::entity::prelude::*;
+
+        #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+        #[sea_orm(table_name = "users")]
+        pub struct Model {
+            #[sea_orm(primary_key)]
+            pub id: i32,
+            pub email: String,
+            pub password: String,
+            pub api_token: Option<String>,
+        }
+
+        #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+        pub enum Relation {}
+
+        impl ActiveModelBehavior for ActiveModel {}
+    }
+
+    impl Authenticatable for user::Model {
+        type Id = i32;
+
+        fn auth_id(&self) -> i32 {
+            self.id
+        }
+
+        fn password_hash(&self) -> &str {
+            &self.password
+        }
+    }
+
+    // Cheap costs: the tests hash a lot.
+    fn hasher() -> Hasher {
+        Hasher::new().memory_cost(64).time_cost(1).parallelism(1)
+    }
+
+    // Ada, whose password is `secret` and whose API token is `api-token`, with a
+    // hash of `password_hasher`.
+    async fn database(password_hasher: Hasher) -> DatabaseConnection {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        db.execute_unprepared(
+            "CREATE TABLE users (
+                id INTEGER PRIMARY KEY,
+                email TEXT NOT NULL,
+                password TEXT NOT NULL,
+                api_token TEXT
+            )",
+        )
+        .await
+        .unwrap();
+        db.execute_unprepared(&format!(
+            "INSERT INTO users (id, email, password, api_token)
+             VALUES (1, 'ada@example.com', '{}', '{}')",
+            password_hasher.make("secret").unwrap(),
+            hash_api_token("api-token"),
+        ))
+        .await
+        .unwrap();
+        db
+    }
+
+    #[handler]
+    async fn attempt(req: &mut Request, depot: &mut Depot) -> &'static str {
+        let auth = depot.obtain::<Auth<user::Model>>().unwrap();
+        let password = req.query::<String>("password").unwrap_or_default();
+        match auth.attempt("ada@example.com", &password).await.unwrap() {
+            true => "welcome",
+            false => "wrong password",
+        }
+    }
+
+    #[handler]
+    async fn logout(depot: &mut Depot) {
+        depot.obtain::<Auth<user::Model>>().unwrap().logout();
+    }
+
+    #[handler]
+    async fn me(user: CurrentUser<user::Model>) -> String {
+        user.email.clone()
+    }
+
+    #[handler]
+    async fn intended(depot: &mut Depot) -> String {
+        depot
+            .obtain::<Auth<user::Model>>()
+            .unwrap()
+            .intended("/home")
+    }
+
+    #[handler]
+    async fn ok() -> &'static str {
+        "ok"
+    }
+
+    fn service(db: DatabaseConnection) -> Service {
+        let users = SeaOrmUserProvider::<user::Entity>::new(db, user::Column::Email)
+            .password_column(user::Column::Password)
+            .token_column(user::Column::ApiToken);
+        let router = Router::new()
+            .hoop(SessionHandler::new(MemorySessionStore::new()))
+            .hoop(
+                AuthHandler::new(users)
+                    .guards([Guard::Session, Guard::Token])
+                    .hasher(hasher()),
+            )
+            .push(Router::with_path("attempt").get(attempt))
+            .push(Router::with_path("logout").post(logout))
+            .push(Router::with_path("me").get(me))
+            .push(Router::with_path("intended").get(intended))
+            .push(
+                Router::with_path("dashboard")
+                    .hoop(Authenticate)
+                    .get(ok)
+                    .post(ok),
+            )
+            .push(
+                Router::with_path("login")
+                    .hoop(RedirectIfAuthenticated)
+                    .get(ok),
+            );
+        Service::new(router)
+    }
+
+    fn session_cookie(res: &Response) -> Option<String> {
+        res.cookie("thunder_session")
+            .filter(|cookie| !cookie.value().is_empty())
+            .map(|cookie| format!("thunder_session={}", cookie.value()))
+    }
+
+    // Logs Ada in and returns the session cookie.
+    async fn log_in(service: &Service) -> String {
+        let mut res = TestClient::get("http://localhost/attempt?password=secret")
+            .send(service)
+            .await;
+        assert_eq!(res.take_string().await.unwrap(), "welcome");
+        session_cookie(&res).unwrap()
+    }
+
+    async fn get(service: &Service, path: &str, headers: &[(&'static str, &str)]) -> Response {
+        let mut request = TestClient::get(format!("http://localhost/{}", path));
+        for (name, value) in headers {
+            request = request.add_header(*name, value.to_string(), true);
+        }
+        request.send(service).await
+    }
+
+    fn location(res: &Response) -> &str {
+        res.headers()
+            .get(header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn logs_in_with_the_right_password_only() {
+        let service = service(database(hasher()).await);
+
+        let mut res = get(&service, "attempt?password=wrong", &[]).await;
+        assert_eq!(res.take_string().await.unwrap(), "wrong password");
+        assert_eq!(session_cookie(&res), None);
+
+        let cookie = log_in(&service).await;
+        let mut res = get(&service, "me", &[("cookie", &cookie)]).await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        assert_eq!(res.take_string().await.unwrap(), "ada@example.com");
+    }
+
+    #[tokio::test]
+    async fn gives_the_session_a_new_id_on_login() {
+        let service = service(database(hasher()).await);
+
+        // A guest session, e.g. one planted by an attacker.
+        let res = get(&service, "dashboard", &[("accept", "text/html")]).await;
+        let planted = session_cookie(&res).unwrap();
+
+        let mut res = get(&service, "attempt?password=secret", &[("cookie", &planted)]).await;
+        assert_eq!(res.take_string().await.unwrap(), "welcome");
+        let cookie = session_cookie(&res).unwrap();
+        assert_ne!(cookie, planted);
+
+        // The data moved along, the old id is worthless.
+        let mut res = get(&service, "intended", &[("cookie", &cookie)]).await;
+        assert_eq!(res.take_string().await.unwrap(), "/dashboard");
+        let res = get(&service, "me", &[("cookie", &planted)]).await;
+        assert_eq!(res.status_code, Some(StatusCode::UNAUTHORIZED));
+    }
+
+    #[tokio::test]
+    async fn logs_out() {
+        let service = service(database(hasher()).await);
+        let cookie = log_in(&service).await;
+
+        let res = TestClient::post("http://localhost/logout")
+            .add_header("cookie", &cookie, true)
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        let res = get(&service, "me", &[("cookie", &cookie)]).await;
+        assert_eq!(res.status_code, Some(StatusCode::UNAUTHORIZED));
+    }
+
+    #[tokio::test]
+    async fn rehashes_an_outdated_password_on_login() {
+        let db = database(hasher().time_cost(2)).await;
+        let service = service(db.clone());
+        log_in(&service).await;
+
+        let ada = user::Entity::find_by_id(1).one(&db).await.unwrap().unwrap();
+        assert!(!hasher().needs_rehash(&ada.password));
+        assert!(hasher().check("secret", &ada.password));
+    }
+
+    #[tokio::test]
+    async fn authenticates_bearer_tokens_by_their_hash() {
+        let service = service(database(hasher()).await);
+
+        let mut res = get(&service, "me", &[("authorization", "Bearer api-token")]).await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        assert_eq!(res.take_string().await.unwrap(), "ada@example.com");
+
+        // The column holds the hash, which is no token itself.
+        let hash = format!("Bearer {}", hash_api_token("api-token"));
+        for authorization in ["Bearer wrong", "Basic api-token", hash.as_str()] {
+            let res = get(&service, "me", &[("authorization", authorization)]).await;
+            assert_eq!(res.status_code, Some(StatusCode::UNAUTHORIZED));
+        }
+    }
+
+    #[tokio::test]
+    async fn answers_guests_with_401_or_a_redirect() {
+        let service = service(database(hasher()).await);
+
+        let res = get(&service, "dashboard", &[]).await;
+        assert_eq!(res.status_code, Some(StatusCode::UNAUTHORIZED));
+        let res = get(&service, "dashboard", &[("accept", "text/html")]).await;
+        assert_eq!(res.status_code, Some(StatusCode::FOUND));
+        assert_eq!(location(&res), "/login");
+
+        // `CurrentUser` can't redirect, but Inertia visits it the login page.
+        for accept in ["application/json", "text/html"] {
+            let res = get(&service, "me", &[("accept", accept)]).await;
+            assert_eq!(res.status_code, Some(StatusCode::UNAUTHORIZED));
+        }
+        let res = get(&service, "me", &[("X-Inertia", "true")]).await;
+        assert_eq!(res.status_code, Some(StatusCode::CONFLICT));
+        assert_eq!(res.headers().get("X-Inertia-Location").unwrap(), "/login");
+
+        // Inertia only follows a 303 after anything but a GET.
+        let res = TestClient::post("http://localhost/dashboard")
+            .add_header("X-Inertia", "true", true)
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::SEE_OTHER));
+        assert_eq!(location(&res), "/login");
+    }
+
+    #[tokio::test]
+    async fn sends_users_away_from_the_guest_pages() {
+        let service = service(database(hasher()).await);
+
+        let res = get(&service, "login", &[]).await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+
+        let cookie = log_in(&service).await;
+        let res = get(&service, "login", &[("cookie", &cookie)]).await;
+        assert_eq!(res.status_code, Some(StatusCode::SEE_OTHER));
+        assert_eq!(location(&res), "/");
+        let res = get(&service, "dashboard", &[("cookie", &cookie)]).await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+    }
+}
@@ -0,0 +1,154 @@
+use aes_gcm::aead::OsRng;
+use aes_gcm::aead::rand_core::RngCore;
+use salvo::async_trait;
+use sea_orm::sea_query::{Expr, IntoValueTuple, ValueTuple};
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, DbErr, EntityTrait, Iterable, PrimaryKeyToColumn,
+    PrimaryKeyTrait, QueryFilter,
+};
+use sha2::{Digest, Sha256};
+
+use super::{AuthError, Authenticatable};
+
+// Where `AuthHandler` looks users up.
+#[async_trait]
+pub trait UserProvider: Send + Sync {
+    type User: Authenticatable;
+
+    // The user a session points to.
+    async fn retrieve_by_id(
+        &self,
+        id: &<Self::User as Authenticatable>::Id,
+    ) -> Result<Option<Self::User>, AuthError>;
+
+    // The user `Auth::attempt` checks the password of, by e-mail or username.
+    async fn retrieve_by_login(&self, login: &str) -> Result<Option<Self::User>, AuthError>;
+
+    // The user of a bearer token. Providers without tokens know nobody.
+    async fn retrieve_by_token(&self, token: &str) -> Result<Option<Self::User>, AuthError> {
+        let _ = token;
+        Ok(None)
+    }
+
+    // Stores the new hash of `user`, made after a login with an outdated one. Providers
+    // that cannot write keep the old hash, which still verifies.
+    async fn update_password_hash(&self, user: &Self::User, hash: &str) -> Result<(), AuthError> {
+        let _ = (user, hash);
+        Ok(())
+    }
+}
+
+// A new API token to hand out once; keep only `hash_api_token` of it.
+pub fn new_api_token() -> String {
+    let mut bytes = [0; 32];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+// What the token column holds: a leaked table does not leak usable tokens.
+pub fn hash_api_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Users in a sea-orm entity whose `Model` is `Authenticatable`, with the primary key
+// as the id:
+//
+//     SeaOrmUserProvider::<user::Entity>::new(db, user::Column::Email)
+//         .token_column(user::Column::ApiToken)
+#[derive(Debug, Clone)]
+pub struct SeaOrmUserProvider<E: EntityTrait> {
+    db: DatabaseConnection,
+    login_column: E::Column,
+    password_column: Option<E::Column>,
+    token_column: Option<E::Column>,
+}
+
+impl<E: EntityTrait> SeaOrmUserProvider<E> {
+    pub fn new(db: DatabaseConnection, login_column: E::Column) -> Self {
+        SeaOrmUserProvider {
+            db,
+            login_column,
+            password_column: None,
+            token_column: None,
+        }
+    }
+
+    // The column of `Authenticatable::password_hash`, for rehashing on login.
+    pub fn password_column(mut self, column: E::Column) -> Self {
+        self.password_column = Some(column);
+        self
+    }
+
+    // The column with the `hash_api_token` of every user's token, for `Guard::Token`.
+    pub fn token_column(mut self, column: E::Column) -> Self {
+        self.token_column = Some(column);
+        self
+    }
+}
+
+fn database_failed(error: DbErr) -> AuthError {
+    AuthError::Provider(error.to_string())
+}
+
+#[async_trait]
+impl<E> UserProvider for SeaOrmUserProvider<E>
+where
+    E: EntityTrait,
+    E::Model: Authenticatable<Id = <E::PrimaryKey as PrimaryKeyTrait>::ValueType>,
+    <E::PrimaryKey as PrimaryKeyTrait>::ValueType: Clone + Sync,
+{
+    type User = E::Model;
+
+    async fn retrieve_by_id(
+        &self,
+        id: &<E::Model as Authenticatable>::Id,
+    ) -> Result<Option<E::Model>, AuthError> {
+        E::find_by_id(id.clone())
+            .one(&self.db)
+            .await
+            .map_err(database_failed)
+    }
+
+    async fn retrieve_by_login(&self, login: &str) -> Result<Option<E::Model>, AuthError> {
+        E::find()
+            .filter(self.login_column.eq(login))
+            .one(&self.db)
+            .await
+            .map_err(database_failed)
+    }
+
+    async fn retrieve_by_token(&self, token: &str) -> Result<Option<E::Model>, AuthError> {
+        let Some(column) = self.token_column else {
+            return Ok(None);
+        };
+        E::find()
+            .filter(column.eq(hash_api_token(token)))
+            .one(&self.db)
+            .await
+            .map_err(database_failed)
+    }
+
+    async fn update_password_hash(&self, user: &E::Model, hash: &str) -> Result<(), AuthError> {
+        let Some(column) = self.password_column else {
+            return Ok(());
+        };
+        // Composite keys are left alone.
+        let key = {
+            let mut keys = E::PrimaryKey::iter();
+            match (keys.next(), keys.next()) {
+                (Some(key), None) => key,
+                _ => return Ok(()),
+            }
+        };
+        let ValueTuple::One(id) = user.auth_id().into_value_tuple() else {
+            return Ok(());
+        };
+        E::update_many()
+            .col_expr(column, Expr::value(hash))
+            .filter(key.into_column().eq(id))
+            .exec(&self.db)
+            .await
+            .map_err(database_failed)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,373 @@
+// Protection against cross-site request forgery. `VerifyCsrfToken` hands the token
+// of the session to the browser in the `XSRF-TOKEN` cookie, which Inertia's axios
+// sends back as `X-XSRF-TOKEN` on its own; plain forms post it as `_token`. Needs a
+// `SessionHandler` in front of it. API clients authenticated by `Guard::Token` are let
+// through, so it goes after the `AuthHandler`.
+//
+//     Router::new()
+//         .hoop(SessionHandler::new(store))
+//         .hoop(AuthHandler::new(users).guards([Guard::Session, Guard::Token]))
+//         .hoop(VerifyCsrfToken::new().except("webhooks/*"))
+use salvo::http::cookie::{Cookie, SameSite};
+use salvo::http::{Method, StatusCode, StatusError, mime};
+use salvo::{Depot, FlowCtrl, Handler, Request, Response, Writer, async_trait};
+
+use crate::auth::AuthenticatedBy;
+use crate::hash::constant_time_eq;
+use crate::{Guard, Inertia, Session};
+
+// Rejects state-changing requests whose token is not the one of the session.
+#[derive(Debug, Clone)]
+pub struct VerifyCsrfToken {
+    except: Vec<String>,
+    secure: bool,
+}
+
+impl Default for VerifyCsrfToken {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VerifyCsrfToken {
+    pub fn new() -> Self {
+        VerifyCsrfToken {
+            except: Vec::new(),
+            secure: false,
+        }
+    }
+
+    // Leaves the paths matching `pattern` unchecked, e.g. `webhooks/*` for callbacks
+    // from other services. `*` stands for any run of characters.
+    pub fn except(mut self, pattern: impl Into<String>) -> Self {
+        self.except
+            .push(pattern.into().trim_start_matches('/').to_string());
+        self
+    }
+
+    // Sends the cookie over HTTPS only; turn it on in production.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    fn is_excluded(&self, path: &str) -> bool {
+        let path = path.trim_start_matches('/');
+        self.except
+            .iter()
+            .any(|pattern| wildcard_match(pattern, path))
+    }
+
+    fn cookie(&self, token: String) -> Cookie<'static> {
+        // Not http-only: the JavaScript of the page reads it to fill the header.
+        Cookie::build(("XSRF-TOKEN", token))
+            .path("/")
+            .secure(self.secure)
+            .same_site(SameSite::Lax)
+            .build()
+    }
+}
+
+fn is_reading(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+// A page on another site can make the browser send the session cookie, but not an
+// `Authorization` header, so a request the token guard authenticated cannot be forged.
+// A mere bearer header is not enough: with a bogus token and the session cookie, the
+// session guard would still log the request in.
+fn is_token_authenticated(req: &Request) -> bool {
+    matches!(
+        req.extensions().get::<AuthenticatedBy>(),
+        Some(AuthenticatedBy(Guard::Token))
+    )
+}
+
+// `*` matches any run of characters, everything else itself.
+fn wildcard_match(pattern: &str, path: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+// The token sent with the request: the header, else the `_token` field of a form.
+// The form is parsed once and kept by the request, so `FormValidated` and
+// `MultipartValidated` still find it; uploads read with `MultipartStream` need the header.
+async fn submitted_token(req: &mut Request) -> Option<String> {
+    if let Some(token) = req
+        .headers()
+        .get("X-XSRF-TOKEN")
+        .and_then(|value| value.to_str().ok())
+    {
+        return Some(token.to_string());
+    }
+    let is_form = req.content_type().is_some_and(|mime| {
+        mime.subtype() == mime::WWW_FORM_URLENCODED || mime.subtype() == mime::FORM_DATA
+    });
+    if !is_form {
+        return None;
+    }
+    let form = req.form_data().await.ok()?;
+    form.fields.get("_token").cloned()
+}
+
+#[async_trait]
+impl Handler for VerifyCsrfToken {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let Ok(session) = depot.obtain::<Session>().cloned() else {
+            res.render(
+                StatusError::internal_server_error()
+                    .brief("No session: add a SessionHandler hoop in front of VerifyCsrfToken."),
+            );
+            ctrl.skip_rest();
+            return;
+        };
+
+        if !is_reading(req.method())
+            && !self.is_excluded(req.uri().path())
+            && !is_token_authenticated(req)
+        {
+            let expected = session.token();
+            let valid = submitted_token(req)
+                .await
+                .is_some_and(|token| constant_time_eq(token.as_bytes(), expected.as_bytes()));
+            if !valid {
+                res.add_cookie(self.cookie(expected));
+                let status_code = StatusCode::from_u16(419).expect("419 is a valid status code");
+                Inertia::error(status_code, "Page Expired")
+                    .write(req, depot, res)
+                    .await;
+                ctrl.skip_rest();
+                return;
+            }
+        }
+
+        ctrl.call_next(req, depot, res).await;
+
+        // After the handlers, which may have regenerated the token, e.g. on login.
+        res.add_cookie(self.cookie(session.token()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo::test::{RequestBuilder, ResponseExt, TestClient};
+    use salvo::{Router, Service, handler};
+
+    use super::*;
+    use crate::{
+        Auth, AuthError, AuthHandler, Authenticatable, MemorySessionStore, SessionHandler,
+        UserProvider,
+    };
+
+    #[derive(Debug, Clone)]
+    struct User;
+
+    impl Authenticatable for User {
+        type Id = i64;
+
+        fn auth_id(&self) -> i64 {
+            1
+        }
+
+        fn password_hash(&self) -> &str {
+            ""
+        }
+    }
+
+    // One user, who logs in with the `api-token` bearer token.
+    struct Users;
+
+    #[async_trait]
+    impl UserProvider for Users {
+        type User = User;
+
+        async fn retrieve_by_id(&self, id: &i64) -> Result<Option<User>, AuthError> {
+            Ok((*id == 1).then_some(User))
+        }
+
+        async fn retrieve_by_login(&self, _login: &str) -> Result<Option<User>, AuthError> {
+            Ok(None)
+        }
+
+        async fn retrieve_by_token(&self, token: &str) -> Result<Option<User>, AuthError> {
+            Ok((token == "api-token").then_some(User))
+        }
+    }
+
+    #[handler]
+    async fn ok() -> &'static str {
+        "ok"
+    }
+
+    #[handler]
+    async fn login(depot: &mut Depot) -> &'static str {
+        depot.obtain::<Auth<User>>().unwrap().login(User).unwrap();
+        "logged in"
+    }
+
+    fn service() -> Service {
+        let router = Router::new()
+            .hoop(SessionHandler::new(MemorySessionStore::new()))
+            .hoop(AuthHandler::new(Users).guards([Guard::Session, Guard::Token]))
+            .hoop(VerifyCsrfToken::new().except("webhooks/*"))
+            .push(Router::with_path("login").get(login))
+            .push(Router::with_path("posts").get(ok).post(ok))
+            .push(Router::with_path("webhooks/{**rest}").post(ok));
+        Service::new(router)
+    }
+
+    // The session cookie and the CSRF token of a first visit.
+    async fn visit(service: &Service, path: &str) -> (String, String) {
+        let res = TestClient::get(format!("http://localhost/{}", path))
+            .send(service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        let session = res.cookie("thunder_session").unwrap();
+        let token = res.cookie("XSRF-TOKEN").unwrap();
+        assert!(!token.http_only().unwrap_or(false));
+        (
+            format!("thunder_session={}", session.value()),
+            token.value().to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn issues_the_token_cookie() {
+        let service = service();
+        let (cookie, token) = visit(&service, "posts").await;
+
+        // The same session keeps the same token.
+        let res = TestClient::get("http://localhost/posts")
+            .add_header("cookie", &cookie, true)
+            .send(&service)
+            .await;
+        assert_eq!(res.cookie("XSRF-TOKEN").unwrap().value(), token);
+    }
+
+    #[tokio::test]
+    async fn accepts_the_token_in_the_header() {
+        let service = service();
+        let (cookie, token) = visit(&service, "posts").await;
+
+        let mut res = TestClient::post("http://localhost/posts")
+            .add_header("cookie", &cookie, true)
+            .add_header("X-XSRF-TOKEN", &token, true)
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        assert_eq!(res.take_string().await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn accepts_the_token_in_a_form() {
+        let service = service();
+        let (cookie, token) = visit(&service, "posts").await;
+
+        let res = TestClient::post("http://localhost/posts")
+            .add_header("cookie", &cookie, true)
+            .raw_form(format!("title=Hello&_token={}", token))
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+    }
+
+    #[tokio::test]
+    async fn answers_419_on_a_wrong_or_missing_token() {
+        let service = service();
+        let (cookie, token) = visit(&service, "posts").await;
+
+        let mut res = TestClient::post("http://localhost/posts")
+            .add_header("cookie", &cookie, true)
+            .add_header("X-XSRF-TOKEN", "forged", true)
+            .add_header("X-Inertia", "true", true)
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code.map(|code| code.as_u16()), Some(419));
+        // The page gets the right token back, to retry with.
+        assert_eq!(res.cookie("XSRF-TOKEN").unwrap().value(), token);
+        let page: serde_json::Value = res.take_json().await.unwrap();
+        assert_eq!(page["props"]["message"], "Page Expired");
+
+        let res = TestClient::post("http://localhost/posts")
+            .add_header("cookie", &cookie, true)
+            .add_header("X-Inertia", "true", true)
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code.map(|code| code.as_u16()), Some(419));
+    }
+
+    #[tokio::test]
+    async fn lets_reading_requests_and_excepted_paths_through() {
+        let service = service();
+        for method in [Method::GET, Method::HEAD, Method::OPTIONS] {
+            let res = RequestBuilder::new("http://localhost/posts", method)
+                .send(&service)
+                .await;
+            assert_ne!(res.status_code.map(|code| code.as_u16()), Some(419));
+        }
+
+        let res = TestClient::post("http://localhost/webhooks/stripe/events")
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(wildcard_match("webhooks/*", "webhooks/stripe"));
+        assert!(wildcard_match("webhooks/*", "webhooks/"));
+        assert!(wildcard_match("api/*/callback", "api/v1/payments/callback"));
+        assert!(wildcard_match("uploads", "uploads"));
+        assert!(!wildcard_match("uploads", "uploads/1"));
+        assert!(!wildcard_match("webhooks/*", "hooks/stripe"));
+        assert!(!wildcard_match("api/*/callback", "api/v1/callbacks"));
+    }
+
+    #[tokio::test]
+    async fn lets_token_authenticated_requests_through() {
+        let service = service();
+        let res = TestClient::post("http://localhost/posts")
+            .add_header("authorization", "Bearer api-token", true)
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+    }
+
+    #[tokio::test]
+    async fn checks_a_bogus_bearer_token_sent_with_the_session() {
+        let service = service();
+        let (cookie, _) = visit(&service, "login").await;
+
+        // The session guard logs the request in, so the header proves nothing.
+        let res = TestClient::post("http://localhost/posts")
+            .add_header("cookie", &cookie, true)
+            .add_header("authorization", "Bearer forged", true)
+            .add_header("X-Inertia", "true", true)
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code.map(|code| code.as_u16()), Some(419));
+    }
+}
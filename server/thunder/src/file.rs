@@ -1,9 +1,27 @@
+use futures_util::TryStreamExt;
 use salvo::{
-    http::Mime,
-    oapi::{
-        Array, BasicType, EndpointArgRegister, KnownFormat, Object, Schema, SchemaFormat, ToSchema,
-    },
+    http::{Mime, form::FilePart},
+    oapi::{BasicType, KnownFormat, Object, Schema, SchemaFormat, ToSchema},
 };
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use crate::storage::{Storage, StorageError, StoredFile, file_stream, join_key};
+
+// The extension of a client-side file name, as `UploadedFile::extension` and
+// `StreamPart::store` put it in storage keys.
+pub(crate) fn file_name_extension(file_name: &str) -> Option<String> {
+    std::path::Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase())
+        .filter(|extension| {
+            (1..=10).contains(&extension.len())
+                && extension
+                    .bytes()
+                    .all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit())
+        })
+}
 
 #[derive(Debug)]
 pub struct UploadedFile {
@@ -12,6 +30,127 @@ pub struct UploadedFile {
     pub content_type: Option<Mime>,
 }
 
+impl UploadedFile {
+    /// Size of the file on disk, in bytes.
+    pub async fn size(&self) -> std::io::Result<u64> {
+        Ok(tokio::fs::metadata(&self.path).await?.len())
+    }
+
+    /// Lowercased extension of the client-side file name. Anything but 1 to 10
+    /// ASCII letters and digits is no extension, since it ends up in storage keys.
+    pub fn extension(&self) -> Option<String> {
+        file_name_extension(self.file_name.as_deref()?)
+    }
+
+    /// MIME type detected from the magic bytes of the content, ignoring whatever
+    /// `Content-Type` the client sent.
+    pub async fn sniff_mime(&self) -> std::io::Result<Option<&'static str>> {
+        Ok(self.sniff_kind().await?.map(|kind| kind.mime_type()))
+    }
+
+    async fn sniff_kind(&self) -> std::io::Result<Option<infer::Type>> {
+        let mut buffer = Vec::with_capacity(8192);
+        tokio::fs::File::open(&self.path)
+            .await?
+            .take(8192)
+            .read_to_end(&mut buffer)
+            .await?;
+        Ok(infer::get(&buffer))
+    }
+
+    /// Width and height in pixels, read from the image header.
+    pub async fn dimensions(&self) -> Option<(usize, usize)> {
+        // `imagesize` only has a blocking reader.
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || imagesize::size(path))
+            .await
+            .ok()?
+            .ok()
+            .map(|size| (size.width, size.height))
+    }
+
+    /// Copies the file into `dir` of `disk`, named after the SHA-256 of its contents,
+    /// so storing the same upload twice gives the same key.
+    pub async fn store(
+        &self,
+        dir: &str,
+        disk: &(impl Storage + ?Sized),
+    ) -> Result<StoredFile, StorageError> {
+        // The name needs the hash up front, so the file is read twice rather than
+        // held in memory.
+        let mut hasher = Sha256::new();
+        let mut chunks = file_stream(tokio::fs::File::open(&self.path).await?);
+        while let Some(chunk) = chunks.try_next().await? {
+            hasher.update(&chunk);
+        }
+        let hash = hex::encode(hasher.finalize());
+
+        let extension = match self.extension() {
+            Some(extension) => Some(extension),
+            None => self
+                .sniff_kind()
+                .await?
+                .map(|kind| kind.extension().to_string()),
+        };
+        let name = match extension {
+            Some(extension) => format!("{}.{}", hash, extension),
+            None => hash,
+        };
+        self.put(join_key(dir, &name), disk).await
+    }
+
+    /// Copies the file into `dir` of `disk` under the given `name`, replacing whatever
+    /// was stored there.
+    pub async fn store_as(
+        &self,
+        dir: &str,
+        name: &str,
+        disk: &(impl Storage + ?Sized),
+    ) -> Result<StoredFile, StorageError> {
+        self.put(join_key(dir, name), disk).await
+    }
+
+    // Streams the file into `disk`, working out its hash and size on the way.
+    async fn put(
+        &self,
+        key: String,
+        disk: &(impl Storage + ?Sized),
+    ) -> Result<StoredFile, StorageError> {
+        // The sniffed type wins over the one the client claimed.
+        let content_type = match self.sniff_mime().await? {
+            Some(mime) => Some(mime.to_string()),
+            None => self.content_type.as_ref().map(Mime::to_string),
+        };
+
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        let chunks = file_stream(tokio::fs::File::open(&self.path).await?).inspect_ok(|chunk| {
+            hasher.update(chunk);
+            size += chunk.len() as u64;
+        });
+        disk.put_stream(&key, Box::pin(chunks), content_type.as_deref())
+            .await?;
+
+        Ok(StoredFile {
+            url: disk.url(&key),
+            key,
+            hash: hex::encode(hasher.finalize()),
+            size,
+            content_type,
+        })
+    }
+}
+
+impl From<&FilePart> for UploadedFile {
+    fn from(file: &FilePart) -> Self {
+        UploadedFile {
+            path: file.path().clone(),
+            file_name: file.name().map(|name| name.to_string()),
+            content_type: file.content_type(),
+        }
+    }
+}
+
 impl ToSchema for UploadedFile {
     fn to_schema(_components: &mut salvo::oapi::Components) -> salvo::oapi::RefOr<Schema> {
         // For OpenAPI, a file upload is represented as a string in binary format.
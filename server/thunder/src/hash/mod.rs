@@ -0,0 +1,224 @@
+// Password hashing. New hashes are Argon2id; bcrypt hashes from an older system still
+// verify, and `needs_rehash` tells when a hash should be replaced on the next login.
+//
+//     let hash = hash::make(&password)?;
+//     if hash::check(&attempt, &hash) { ... }
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use once_cell::sync::Lazy;
+use subtle::ConstantTimeEq;
+
+#[derive(Debug)]
+pub enum HashError {
+    // The Argon2 parameters are out of range, or hashing failed.
+    Hash(String),
+}
+
+impl Display for HashError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            HashError::Hash(message) => write!(f, "could not hash the password: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for HashError {}
+
+// Argon2id with the costs new hashes are made with. The defaults are the OWASP
+// recommendation: 19 MiB of memory, 2 passes, 1 lane.
+#[derive(Debug, Clone)]
+pub struct Hasher {
+    memory_cost: u32,
+    time_cost: u32,
+    parallelism: u32,
+}
+
+impl Default for Hasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hasher {
+    pub fn new() -> Self {
+        Hasher {
+            memory_cost: Params::DEFAULT_M_COST,
+            time_cost: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+
+    // In KiB.
+    pub fn memory_cost(mut self, kib: u32) -> Self {
+        self.memory_cost = kib;
+        self
+    }
+
+    pub fn time_cost(mut self, passes: u32) -> Self {
+        self.time_cost = passes;
+        self
+    }
+
+    pub fn parallelism(mut self, lanes: u32) -> Self {
+        self.parallelism = lanes;
+        self
+    }
+
+    fn argon2(&self) -> Result<Argon2<'static>, HashError> {
+        let params = Params::new(self.memory_cost, self.time_cost, self.parallelism, None)
+            .map_err(|error| HashError::Hash(error.to_string()))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    /// Hashes `password` with a fresh salt, as a PHC string.
+    pub fn make(&self, password: &str) -> Result<String, HashError> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = self
+            .argon2()?
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|error| HashError::Hash(error.to_string()))?;
+        Ok(hash.to_string())
+    }
+
+    /// Whether `password` matches `hash`, an Argon2 hash with any costs or a bcrypt
+    /// one. Unknown formats never match.
+    pub fn check(&self, password: &str, hash: &str) -> bool {
+        if is_bcrypt(hash) {
+            return bcrypt::verify(password, hash).unwrap_or(false);
+        }
+        let Ok(hash) = PasswordHash::new(hash) else {
+            return false;
+        };
+        // The algorithm and costs are read from the hash itself.
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok()
+    }
+
+    /// Whether `hash` was made by something else than this hasher: bcrypt, another
+    /// Argon2 variant or other costs.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let Ok(hash) = PasswordHash::new(hash) else {
+            return true;
+        };
+        if hash.algorithm != argon2::ARGON2ID_IDENT || hash.version != Some(Version::V0x13 as u32) {
+            return true;
+        }
+        Params::try_from(&hash).map_or(true, |params| {
+            params.m_cost() != self.memory_cost
+                || params.t_cost() != self.time_cost
+                || params.p_cost() != self.parallelism
+        })
+    }
+}
+
+fn is_bcrypt(hash: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"]
+        .iter()
+        .any(|prefix| hash.starts_with(prefix))
+}
+
+/// `Hasher::make` with the default costs.
+pub fn make(password: &str) -> Result<String, HashError> {
+    Hasher::new().make(password)
+}
+
+/// `Hasher::check`.
+pub fn check(password: &str, hash: &str) -> bool {
+    Hasher::new().check(password, hash)
+}
+
+/// `Hasher::needs_rehash` against the default costs.
+pub fn needs_rehash(hash: &str) -> bool {
+    Hasher::new().needs_rehash(hash)
+}
+
+/// Compares secrets, e.g. tokens, in a time that only depends on their length.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+static COMPROMISED: Lazy<HashSet<&'static str>> = Lazy::new(|| {
+    include_str!("compromised.txt")
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+});
+
+/// Whether `password` is on the bundled list of the most common leaked passwords,
+/// whatever its case. Nothing leaves the machine.
+pub fn is_compromised(password: &str) -> bool {
+    COMPROMISED.contains(password.to_lowercase().as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Low costs keep the tests fast; the behaviour doesn't depend on them.
+    fn hasher() -> Hasher {
+        Hasher::new().memory_cost(1024).time_cost(1).parallelism(1)
+    }
+
+    #[test]
+    fn checks_its_own_hashes() {
+        let hash = hasher().make("correct horse").unwrap();
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(hasher().check("correct horse", &hash));
+        assert!(!hasher().check("correct horsf", &hash));
+        assert_ne!(hasher().make("correct horse").unwrap(), hash);
+    }
+
+    #[test]
+    fn checks_hashes_made_with_other_costs() {
+        let hash = hasher().time_cost(2).make("correct horse").unwrap();
+        assert!(hasher().check("correct horse", &hash));
+    }
+
+    #[test]
+    fn checks_bcrypt_hashes() {
+        let hash = bcrypt::hash("correct horse", 4).unwrap();
+        assert!(hasher().check("correct horse", &hash));
+        assert!(!hasher().check("correct horsf", &hash));
+    }
+
+    #[test]
+    fn never_matches_an_unknown_format() {
+        assert!(!hasher().check("correct horse", "correct horse"));
+        assert!(!hasher().check("", ""));
+        assert!(!hasher().check("correct horse", "$2y$04$not-a-hash"));
+    }
+
+    #[test]
+    fn rehashes_anything_it_did_not_make() {
+        let hash = hasher().make("correct horse").unwrap();
+        assert!(!hasher().needs_rehash(&hash));
+
+        assert!(hasher().memory_cost(2048).needs_rehash(&hash));
+        assert!(hasher().time_cost(2).needs_rehash(&hash));
+        assert!(hasher().parallelism(2).needs_rehash(&hash));
+
+        let bcrypt = bcrypt::hash("correct horse", 4).unwrap();
+        assert!(hasher().needs_rehash(&bcrypt));
+
+        let params = Params::new(1024, 1, 1, None).unwrap();
+        let argon2i = Argon2::new(Algorithm::Argon2i, Version::V0x13, params)
+            .hash_password(b"correct horse", &SaltString::generate(&mut OsRng))
+            .unwrap()
+            .to_string();
+        assert!(hasher().needs_rehash(&argon2i));
+
+        assert!(hasher().needs_rehash("not a hash"));
+    }
+
+    #[test]
+    fn finds_compromised_passwords_whatever_their_case() {
+        assert!(is_compromised("Password"));
+        assert!(!is_compromised("correct horse battery staple 42"));
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use once_cell::sync::Lazy;
+
+// Validation messages per locale, loaded from `locales/<locale>.toml` at startup with
+// `MessageCatalogue::init`. Every file maps an error code to a message template:
+//
+//     min = "Debe tener al menos {min} caracteres."
+static MESSAGE_CATALOGUE: OnceLock<MessageCatalogue> = OnceLock::new();
+
+// What `global` hands out before `init`: no translations, the messages stay English.
+static EMPTY: Lazy<MessageCatalogue> = Lazy::new(MessageCatalogue::default);
+
+#[derive(Debug)]
+pub enum CatalogueError {
+    Read(PathBuf, std::io::Error),
+    Parse(PathBuf, toml::de::Error),
+}
+
+impl Display for CatalogueError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CatalogueError::Read(path, error) => {
+                write!(f, "Error reading {}: {}", path.display(), error)
+            }
+            CatalogueError::Parse(path, error) => {
+                write!(f, "Error parsing {}: {}", path.display(), error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CatalogueError {}
+
+#[derive(Debug, Default)]
+pub struct MessageCatalogue {
+    locales: HashMap<String, HashMap<String, String>>,
+}
+
+impl MessageCatalogue {
+    pub fn load(directory: impl AsRef<Path>) -> Result<MessageCatalogue, CatalogueError> {
+        let mut catalogue = MessageCatalogue::default();
+
+        // A missing directory just means no translations: the default messages are English.
+        let Ok(entries) = fs::read_dir(directory) else {
+            return Ok(catalogue);
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|extension| extension != "toml") {
+                continue;
+            }
+            let Some(locale) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+
+            let content = match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(error) => return Err(CatalogueError::Read(path, error)),
+            };
+            let messages: HashMap<String, String> = match toml::from_str(&content) {
+                Ok(messages) => messages,
+                Err(error) => return Err(CatalogueError::Parse(path, error)),
+            };
+
+            catalogue.locales.insert(locale.to_lowercase(), messages);
+        }
+
+        Ok(catalogue)
+    }
+
+    /// Loads `directory` as the catalogue `ValidationErrors::translate` and the
+    /// validated extractors use. Meant for startup, so a broken file stops the app
+    /// before it serves anything; only the first call has an effect.
+    pub fn init(directory: impl AsRef<Path>) -> Result<(), CatalogueError> {
+        let catalogue = MessageCatalogue::load(directory)?;
+        let _ = MESSAGE_CATALOGUE.set(catalogue);
+        Ok(())
+    }
+
+    // The catalogue from `init`, or an empty one.
+    pub fn global() -> &'static MessageCatalogue {
+        MESSAGE_CATALOGUE.get().unwrap_or(&EMPTY)
+    }
+
+    pub fn message(&self, locale: &str, code: &str) -> Option<&str> {
+        self.locales
+            .get(&locale.to_lowercase())
+            .and_then(|messages| messages.get(code))
+            .map(String::as_str)
+    }
+
+    // Picks the best available locale for an `Accept-Language` header such as
+    // `es-CL,es;q=0.9,en;q=0.8`, falling back from `es-cl` to `es`.
+    pub fn negotiate(&self, accept_language: &str) -> Option<&str> {
+        let mut ranges: Vec<(String, f32)> = accept_language
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.trim().split(';');
+                let tag = parts.next()?.trim().to_lowercase();
+                let quality = parts
+                    .find_map(|param| param.trim().strip_prefix("q="))
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                (!tag.is_empty()).then_some((tag, quality))
+            })
+            .collect();
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        ranges.iter().find_map(|(tag, _)| {
+            let primary = tag.split('-').next().unwrap_or(tag);
+            [tag.as_str(), primary].into_iter().find_map(|candidate| {
+                self.locales
+                    .get_key_value(candidate)
+                    .map(|(locale, _)| locale.as_str())
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_a_broken_locale_file() {
+        let directory =
+            std::env::temp_dir().join(format!("thunder-locales-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("es.toml"),
+            "min = \"Debe tener al menos {min}.\"\n",
+        )
+        .unwrap();
+
+        let catalogue = MessageCatalogue::load(&directory).unwrap();
+        assert_eq!(
+            catalogue.message("es", "min"),
+            Some("Debe tener al menos {min}.")
+        );
+
+        fs::write(directory.join("fr.toml"), "min = ").unwrap();
+        let error = MessageCatalogue::load(&directory).unwrap_err();
+        assert!(matches!(error, CatalogueError::Parse(ref path, _) if path.ends_with("fr.toml")));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn a_missing_directory_is_an_empty_catalogue() {
+        let catalogue = MessageCatalogue::load("does-not-exist").unwrap();
+        assert_eq!(catalogue.message("es", "min"), None);
+    }
+}
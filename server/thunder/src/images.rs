@@ -0,0 +1,656 @@
+// Image processing for uploads, behind the `images` feature. An upload is decoded
+// once, turned upright following its EXIF orientation and re-encoded, which also
+// drops the EXIF block (camera, GPS position...). The named conversions declared
+// in `ImageConversions` are generated from it and stored next to the original.
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::Cursor;
+use std::path::Path;
+use std::str::FromStr;
+
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageReader};
+use salvo::oapi::ToSchema;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::UploadedFile;
+use crate::storage::{Storage, StorageError, StoredFile, join_key};
+
+#[derive(Debug)]
+pub enum ImageError {
+    // The file is not an image in one of the enabled formats, or is corrupt.
+    Decode(String),
+    Encode(String),
+    // A conversion spec that does not parse, e.g. `128 fit cover`.
+    InvalidConversion(String),
+    Storage(StorageError),
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ImageError::Decode(message) => write!(f, "could not decode the image: {}", message),
+            ImageError::Encode(message) => write!(f, "could not encode the image: {}", message),
+            ImageError::InvalidConversion(spec) => write!(f, "invalid image conversion `{}`", spec),
+            ImageError::Storage(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<StorageError> for ImageError {
+    fn from(error: StorageError) -> Self {
+        ImageError::Storage(error)
+    }
+}
+
+impl From<std::io::Error> for ImageError {
+    fn from(error: std::io::Error) -> Self {
+        ImageError::Storage(StorageError::Io(error))
+    }
+}
+
+// How an image is fitted into the width and height of a conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fit {
+    // Fills the whole box, cropping whatever sticks out around the centre.
+    Cover,
+    // Fits inside the box keeping the aspect ratio; one side may come out smaller.
+    Contain,
+    // Stretched to exactly the box.
+    Fill,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    // Always lossless; the quality is ignored.
+    WebP,
+    Avif,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Png => "png",
+            OutputFormat::WebP => "webp",
+            OutputFormat::Avif => "avif",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Png => "image/png",
+            OutputFormat::WebP => "image/webp",
+            OutputFormat::Avif => "image/avif",
+        }
+    }
+
+    // The format an upload keeps when it is not converted. Formats that cannot be
+    // written, such as GIF, become PNG.
+    fn of(format: Option<image::ImageFormat>) -> Self {
+        match format {
+            Some(image::ImageFormat::Jpeg) => OutputFormat::Jpeg,
+            Some(image::ImageFormat::WebP) => OutputFormat::WebP,
+            Some(image::ImageFormat::Avif) => OutputFormat::Avif,
+            _ => OutputFormat::Png,
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = ImageError;
+
+    fn from_str(name: &str) -> Result<Self, ImageError> {
+        match name.to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Ok(OutputFormat::Jpeg),
+            "png" => Ok(OutputFormat::Png),
+            "webp" => Ok(OutputFormat::WebP),
+            "avif" => Ok(OutputFormat::Avif),
+            _ => Err(ImageError::InvalidConversion(name.to_string())),
+        }
+    }
+}
+
+// A decoded image, upright.
+#[derive(Debug, Clone)]
+pub struct Image {
+    image: DynamicImage,
+    format: OutputFormat,
+}
+
+impl Image {
+    // Decodes the file at `path` and applies its EXIF orientation.
+    pub fn open(path: impl AsRef<Path>) -> Result<Image, ImageError> {
+        let reader = ImageReader::open(path)?
+            .with_guessed_format()
+            .map_err(|error| ImageError::Decode(error.to_string()))?;
+        let format = OutputFormat::of(reader.format());
+        let mut decoder = reader
+            .into_decoder()
+            .map_err(|error| ImageError::Decode(error.to_string()))?;
+        let orientation = decoder
+            .orientation()
+            .map_err(|error| ImageError::Decode(error.to_string()))?;
+        let mut image = DynamicImage::from_decoder(decoder)
+            .map_err(|error| ImageError::Decode(error.to_string()))?;
+        image.apply_orientation(orientation);
+        Ok(Image { image, format })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.image.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.image.height()
+    }
+
+    // The format the image was decoded from, or PNG when it cannot be written back.
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    pub fn resize(&self, width: u32, height: u32, fit: Fit) -> Image {
+        let image = match fit {
+            Fit::Cover => self
+                .image
+                .resize_to_fill(width, height, FilterType::Lanczos3),
+            Fit::Contain => self.image.resize(width, height, FilterType::Lanczos3),
+            Fit::Fill => self.image.resize_exact(width, height, FilterType::Lanczos3),
+        };
+        Image {
+            image,
+            format: self.format,
+        }
+    }
+
+    // The `width` x `height` area whose top left corner is at `x`, `y`, clamped to
+    // the image.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Image {
+        Image {
+            image: self.image.crop_imm(x, y, width, height),
+            format: self.format,
+        }
+    }
+
+    // Encodes without any metadata. `quality` goes from 1 to 100 and only applies
+    // to JPEG and AVIF.
+    pub fn encode(&self, format: OutputFormat, quality: u8) -> Result<Vec<u8>, ImageError> {
+        let quality = quality.clamp(1, 100);
+        let mut bytes = Vec::new();
+        let written = match format {
+            // JPEG has no alpha channel.
+            OutputFormat::Jpeg => DynamicImage::ImageRgb8(self.image.to_rgb8())
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, quality)),
+            OutputFormat::Png => self.image.write_with_encoder(PngEncoder::new(&mut bytes)),
+            OutputFormat::WebP => DynamicImage::ImageRgba8(self.image.to_rgba8())
+                .write_with_encoder(WebPEncoder::new_lossless(&mut bytes)),
+            OutputFormat::Avif => {
+                DynamicImage::ImageRgba8(self.image.to_rgba8()).write_with_encoder(
+                    AvifEncoder::new_with_speed_quality(Cursor::new(&mut bytes), 8, quality),
+                )
+            }
+        };
+        written.map_err(|error| ImageError::Encode(error.to_string()))?;
+        Ok(bytes)
+    }
+}
+
+// One named variant of an upload, e.g. `128x128 fit cover webp quality 80`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conversion {
+    pub width: u32,
+    pub height: u32,
+    pub fit: Fit,
+    // Keeps the format of the original when `None`.
+    pub format: Option<OutputFormat>,
+    pub quality: u8,
+}
+
+impl Conversion {
+    pub fn new(width: u32, height: u32) -> Self {
+        Conversion {
+            width,
+            height,
+            fit: Fit::Contain,
+            format: None,
+            quality: 85,
+        }
+    }
+
+    pub fn fit(mut self, fit: Fit) -> Self {
+        self.fit = fit;
+        self
+    }
+
+    pub fn format(mut self, format: OutputFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.quality = quality;
+        self
+    }
+}
+
+// `<width>x<height>`, then in any order `fit <cover|contain|fill>`, a format
+// (`jpeg`, `png`, `webp`, `avif`) and `quality <1-100>`.
+impl FromStr for Conversion {
+    type Err = ImageError;
+
+    fn from_str(spec: &str) -> Result<Self, ImageError> {
+        let invalid = || ImageError::InvalidConversion(spec.to_string());
+        let mut words = spec.split_whitespace();
+        let (width, height) = words
+            .next()
+            .and_then(|size| size.split_once('x'))
+            .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+            .filter(|&(width, height)| width > 0 && height > 0)
+            .ok_or_else(invalid)?;
+
+        let mut conversion = Conversion::new(width, height);
+        while let Some(word) = words.next() {
+            match word {
+                "fit" => {
+                    conversion.fit = match words.next() {
+                        Some("cover") => Fit::Cover,
+                        Some("contain") => Fit::Contain,
+                        Some("fill") => Fit::Fill,
+                        _ => return Err(invalid()),
+                    }
+                }
+                "quality" => {
+                    conversion.quality = words
+                        .next()
+                        .and_then(|quality| quality.parse().ok())
+                        .filter(|quality| (1..=100).contains(quality))
+                        .ok_or_else(invalid)?;
+                }
+                format => conversion.format = Some(format.parse().map_err(|_| invalid())?),
+            }
+        }
+        Ok(conversion)
+    }
+}
+
+// The conversions generated for every image stored with them, declared once, e.g.
+// in a `Lazy` next to the model:
+//
+//     ImageConversions::parse("thumb: 128x128 fit cover; preview: 800x600 webp")
+#[derive(Debug, Clone, Default)]
+pub struct ImageConversions {
+    conversions: BTreeMap<String, Conversion>,
+    original: Option<OutputFormat>,
+}
+
+impl ImageConversions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `name: spec` pairs separated by `;` or new lines, see `Conversion`.
+    pub fn parse(specs: &str) -> Result<Self, ImageError> {
+        let mut conversions = ImageConversions::new();
+        for spec in specs
+            .split([';', '\n'])
+            .map(str::trim)
+            .filter(|spec| !spec.is_empty())
+        {
+            let (name, conversion) = spec
+                .split_once(':')
+                .filter(|(name, _)| !name.trim().is_empty())
+                .ok_or_else(|| ImageError::InvalidConversion(spec.to_string()))?;
+            conversions = conversions.add(name.trim(), conversion.parse()?);
+        }
+        Ok(conversions)
+    }
+
+    pub fn add(mut self, name: impl Into<String>, conversion: Conversion) -> Self {
+        self.conversions.insert(name.into(), conversion);
+        self
+    }
+
+    // Converts the original too instead of keeping its format.
+    pub fn original_format(mut self, format: OutputFormat) -> Self {
+        self.original = Some(format);
+        self
+    }
+}
+
+// A stored image and its conversions. Serializes straight into Inertia props;
+// `urls` gives the flat `{"original": ..., "thumb": ...}` map.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct StoredImage {
+    pub original: StoredFile,
+    pub width: u32,
+    pub height: u32,
+    pub conversions: BTreeMap<String, StoredFile>,
+}
+
+impl StoredImage {
+    // The URL of the conversion `name`, or of the original for `"original"`.
+    pub fn url(&self, name: &str) -> Option<&str> {
+        match name {
+            "original" => Some(&self.original.url),
+            name => self.conversions.get(name).map(|file| file.url.as_str()),
+        }
+    }
+
+    pub fn urls(&self) -> BTreeMap<String, String> {
+        let mut urls = BTreeMap::from([("original".to_string(), self.original.url.clone())]);
+        for (name, file) in &self.conversions {
+            urls.insert(name.clone(), file.url.clone());
+        }
+        urls
+    }
+}
+
+// An encoded image waiting to be written.
+struct Encoded {
+    bytes: Vec<u8>,
+    format: OutputFormat,
+}
+
+impl UploadedFile {
+    /// Stores the image in `dir` of `disk` without its metadata, along with every
+    /// conversion in `conversions`. The original is named after the SHA-256 of
+    /// what is stored and the conversions after it, e.g. `<hash>-thumb.webp`.
+    pub async fn store_image(
+        &self,
+        dir: &str,
+        disk: &(impl Storage + ?Sized),
+        conversions: &ImageConversions,
+    ) -> Result<StoredImage, ImageError> {
+        let path = self.path.clone();
+        let conversions = conversions.clone();
+        // Decoding and encoding are CPU-bound; they are kept off the runtime threads.
+        let (width, height, original, converted) = tokio::task::spawn_blocking(move || {
+            let image = Image::open(&path)?;
+            let format = conversions.original.unwrap_or(image.format());
+            let original = Encoded {
+                bytes: image.encode(format, 90)?,
+                format,
+            };
+            let mut converted = BTreeMap::new();
+            for (name, conversion) in &conversions.conversions {
+                let format = conversion.format.unwrap_or(image.format());
+                let bytes = image
+                    .resize(conversion.width, conversion.height, conversion.fit)
+                    .encode(format, conversion.quality)?;
+                converted.insert(name.clone(), Encoded { bytes, format });
+            }
+            Ok::<_, ImageError>((image.width(), image.height(), original, converted))
+        })
+        .await
+        .map_err(|error| ImageError::Encode(error.to_string()))??;
+
+        let hash = hex::encode(Sha256::digest(&original.bytes));
+        let mut stored = StoredImage {
+            original: put(
+                disk,
+                join_key(dir, &format!("{}.{}", hash, original.format.extension())),
+                original,
+            )
+            .await?,
+            width,
+            height,
+            conversions: BTreeMap::new(),
+        };
+        for (name, encoded) in converted {
+            let key = join_key(
+                dir,
+                &format!("{}-{}.{}", hash, name, encoded.format.extension()),
+            );
+            stored
+                .conversions
+                .insert(name, put(disk, key, encoded).await?);
+        }
+        Ok(stored)
+    }
+}
+
+async fn put(
+    disk: &(impl Storage + ?Sized),
+    key: String,
+    encoded: Encoded,
+) -> Result<StoredFile, StorageError> {
+    let hash = hex::encode(Sha256::digest(&encoded.bytes));
+    let size = encoded.bytes.len() as u64;
+    let content_type = encoded.format.mime_type();
+    disk.put(&key, encoded.bytes, Some(content_type)).await?;
+    Ok(StoredFile {
+        url: disk.url(&key),
+        key,
+        hash,
+        size,
+        content_type: Some(content_type.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+    use crate::LocalDisk;
+
+    // A 4x2 JPEG, red on the left half and blue on the right, with an EXIF block
+    // giving `orientation` and a camera make.
+    fn jpeg(orientation: u16) -> Vec<u8> {
+        let image = RgbImage::from_fn(4, 2, |x, _| {
+            if x < 2 {
+                Rgb([255, 0, 0])
+            } else {
+                Rgb([0, 0, 255])
+            }
+        });
+        let mut bytes = Vec::new();
+        DynamicImage::ImageRgb8(image)
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut bytes, 100))
+            .unwrap();
+
+        // Big-endian TIFF with one IFD: the orientation and the make, stored after it.
+        let mut exif = b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x02".to_vec();
+        exif.extend([0x01, 0x12, 0, 3, 0, 0, 0, 1]);
+        exif.extend(orientation.to_be_bytes());
+        exif.extend([0, 0]);
+        exif.extend([0x01, 0x0f, 0, 2, 0, 0, 0, 6, 0, 0, 0, 38]);
+        exif.extend([0, 0, 0, 0]);
+        exif.extend(b"Canon\0");
+        let mut segment = vec![0xff, 0xe1];
+        segment.extend((exif.len() as u16 + 2).to_be_bytes());
+        segment.extend(exif);
+        // Right after the start of image marker.
+        bytes.splice(2..2, segment);
+        bytes
+    }
+
+    fn upload(name: &str, contents: &[u8]) -> UploadedFile {
+        let path = std::env::temp_dir().join(format!("thunder-image-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        UploadedFile {
+            path,
+            file_name: Some(name.to_string()),
+            content_type: None,
+        }
+    }
+
+    fn pixel(image: &Image, x: u32, y: u32) -> [u8; 3] {
+        image.image.to_rgb8().get_pixel(x, y).0
+    }
+
+    fn is_red([red, green, blue]: [u8; 3]) -> bool {
+        red > 200 && green < 60 && blue < 60
+    }
+
+    #[test]
+    fn parses_conversions() {
+        assert_eq!(
+            "128x64".parse::<Conversion>().unwrap(),
+            Conversion::new(128, 64)
+        );
+        assert_eq!(
+            "128x128 webp quality 80 fit cover"
+                .parse::<Conversion>()
+                .unwrap(),
+            Conversion::new(128, 128)
+                .fit(Fit::Cover)
+                .format(OutputFormat::WebP)
+                .quality(80)
+        );
+        assert_eq!(
+            "10x10 JPG fit fill".parse::<Conversion>().unwrap(),
+            Conversion::new(10, 10)
+                .fit(Fit::Fill)
+                .format(OutputFormat::Jpeg)
+        );
+
+        for spec in [
+            "",
+            "128",
+            "0x128",
+            "128x",
+            "128 fit cover",
+            "128x128 fit",
+            "128x128 fit stretch",
+            "128x128 gif",
+            "128x128 quality",
+            "128x128 quality 0",
+            "128x128 quality 101",
+        ] {
+            assert!(
+                matches!(
+                    spec.parse::<Conversion>(),
+                    Err(ImageError::InvalidConversion(_))
+                ),
+                "{:?} parsed",
+                spec
+            );
+        }
+    }
+
+    #[test]
+    fn parses_named_conversions() {
+        let conversions =
+            ImageConversions::parse("thumb: 128x128 fit cover;\n preview : 800x600 webp;").unwrap();
+        assert_eq!(
+            conversions.conversions.keys().collect::<Vec<_>>(),
+            ["preview", "thumb"]
+        );
+        assert_eq!(
+            conversions.conversions["preview"],
+            Conversion::new(800, 600).format(OutputFormat::WebP)
+        );
+
+        for specs in ["thumb 128x128", ": 128x128", "  : 128x128", "thumb: 128"] {
+            assert!(
+                matches!(
+                    ImageConversions::parse(specs),
+                    Err(ImageError::InvalidConversion(_))
+                ),
+                "{:?} parsed",
+                specs
+            );
+        }
+    }
+
+    #[test]
+    fn fits_the_box() {
+        let image = Image {
+            image: DynamicImage::new_rgb8(400, 200),
+            format: OutputFormat::Png,
+        };
+
+        let cover = image.resize(100, 100, Fit::Cover);
+        assert_eq!((cover.width(), cover.height()), (100, 100));
+        let contain = image.resize(100, 100, Fit::Contain);
+        assert_eq!((contain.width(), contain.height()), (100, 50));
+        let fill = image.resize(100, 100, Fit::Fill);
+        assert_eq!((fill.width(), fill.height()), (100, 100));
+
+        let crop = image.crop(350, 150, 100, 100);
+        assert_eq!((crop.width(), crop.height()), (50, 50));
+    }
+
+    #[test]
+    fn turns_the_image_upright() {
+        let file = upload("photo.jpg", &jpeg(1));
+        let image = Image::open(&file.path).unwrap();
+        assert_eq!((image.width(), image.height()), (4, 2));
+        assert!(is_red(pixel(&image, 0, 0)));
+
+        // 6: taken with the camera turned, to be rotated 90° clockwise.
+        let file = upload("photo.jpg", &jpeg(6));
+        let image = Image::open(&file.path).unwrap();
+        assert_eq!(image.format(), OutputFormat::Jpeg);
+        assert_eq!((image.width(), image.height()), (2, 4));
+        assert!(is_red(pixel(&image, 0, 0)));
+        assert!(is_red(pixel(&image, 1, 0)));
+        assert!(!is_red(pixel(&image, 0, 3)));
+    }
+
+    #[test]
+    fn refuses_what_is_not_an_image() {
+        let file = upload("notes.png", b"not an image");
+        assert!(matches!(
+            Image::open(&file.path),
+            Err(ImageError::Decode(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn stores_the_original_and_its_conversions() {
+        let root = std::env::temp_dir().join(format!("thunder-images-{}", uuid::Uuid::new_v4()));
+        let disk = LocalDisk::new(&root, "/storage");
+        let conversions =
+            ImageConversions::parse("thumb: 1x1 fit cover; wide: 8x8 fit contain png").unwrap();
+        let file = upload("photo.jpg", &jpeg(6));
+
+        let stored = file
+            .store_image("avatars", &disk, &conversions)
+            .await
+            .unwrap();
+        assert_eq!((stored.width, stored.height), (2, 4));
+
+        let hash = &stored.original.hash;
+        assert_eq!(stored.original.key, format!("avatars/{}.jpg", hash));
+        assert_eq!(stored.original.content_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(
+            stored.conversions["thumb"].key,
+            format!("avatars/{}-thumb.jpg", hash)
+        );
+        assert_eq!(
+            stored.conversions["wide"].key,
+            format!("avatars/{}-wide.png", hash)
+        );
+        assert_eq!(
+            stored.url("wide"),
+            Some(format!("/storage/avatars/{}-wide.png", hash).as_str())
+        );
+        assert_eq!(stored.url("original"), Some(stored.original.url.as_str()));
+        assert_eq!(stored.url("missing"), None);
+        assert_eq!(stored.urls().len(), 3);
+
+        // The EXIF block is gone, and with it the orientation, now applied.
+        let original = disk.get(&stored.original.key).await.unwrap();
+        assert_eq!(stored.original.size, original.len() as u64);
+        assert!(!original.windows(4).any(|bytes| bytes == b"Exif"));
+        assert!(!original.windows(5).any(|bytes| bytes == b"Canon"));
+        let wide = Image::open(disk.path(&stored.conversions["wide"].key).unwrap()).unwrap();
+        assert_eq!((wide.width(), wide.height()), (4, 8));
+
+        let _ = std::fs::remove_dir_all(&root);
+        let _ = std::fs::remove_file(&file.path);
+    }
+}
@@ -4,13 +4,13 @@ use once_cell::sync::Lazy;
 //use salvo::http::{header, Mime};
 use salvo::{
     Depot, Request, Response, Writer, async_trait,
-    http::{HeaderValue, header},
+    http::{HeaderValue, StatusCode, header},
     oapi::{Components, EndpointOutRegister, Operation, ToSchema},
     writing::Text,
 };
 use serde::Serialize;
 use serde_json::json;
-use tera::{Context, Tera};
+use tera::Context;
 
 use sha2::{Digest, Sha256};
 
@@ -19,10 +19,13 @@ use crate::tera_helpers; // Import hashing tools
 // This is where the magic happens. We teach Salvo how to render our `Inertia` struct.
 #[async_trait]
 impl<T: Serialize + Send + salvo::prelude::ToSchema> Writer for Inertia<T> {
-    async fn write(mut self, req: &mut Request, depot: &mut Depot, res: &mut Response) {
+    async fn write(mut self, req: &mut Request, _depot: &mut Depot, res: &mut Response) {
         let tera: &tera::Tera = &tera_helpers::TERA_ENGINE;
         // 2. Define a static asset version. In a real app, this might come from a file hash or env var.
         let version = ASSET_VERSION.to_string();
+        if let Some(status_code) = self.status_code {
+            res.status_code(status_code);
+        }
 
         // 3. Construct the full Page object.
         let page = Page {
@@ -122,9 +125,7 @@ pub static ASSET_VERSION: Lazy<String> = Lazy::new(|| {
     }
 });
 
-pub static IS_DEVELOPMENT: Lazy<bool> = Lazy::new(|| {
-    return cfg!(debug_assertions);
-});
+pub static IS_DEVELOPMENT: Lazy<bool> = Lazy::new(|| cfg!(debug_assertions));
 
 // pub fn init_thunder(){
 //
@@ -145,6 +146,7 @@ pub struct Page<T: Serialize + Send> {
 pub struct Inertia<T: Serialize + ToSchema + Send> {
     component: String,
     props: Option<T>,
+    status_code: Option<StatusCode>,
 }
 
 impl<T: Serialize> Inertia<T>
@@ -155,6 +157,7 @@ where
         Self {
             component: component.into(),
             props: Some(props),
+            status_code: None,
         }
     }
 
@@ -162,8 +165,37 @@ where
         Self {
             component: component.into(),
             props: None,
+            status_code: None,
         }
     }
+
+    // Answers with `status_code` instead of 200, e.g. for error pages.
+    pub fn status(mut self, status_code: StatusCode) -> Self {
+        self.status_code = Some(status_code);
+        self
+    }
+}
+
+// Props of the `Error` page component, so failures look like the rest of the app.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorPage {
+    pub status: u16,
+    pub message: String,
+}
+
+impl Inertia<ErrorPage> {
+    // The `Error` page for `status_code`. Inertia shows it in place of the current
+    // page instead of the modal it uses for responses that are not Inertia's.
+    pub fn error(status_code: StatusCode, message: impl Into<String>) -> Self {
+        Inertia::new(
+            "Error",
+            ErrorPage {
+                status: status_code.as_u16(),
+                message: message.into(),
+            },
+        )
+        .status(status_code)
+    }
 }
 
 // impl<T: salvo::oapi::EndpointOutRegister> for Inertia<T>
@@ -178,7 +210,7 @@ impl<T> EndpointOutRegister for Inertia<T>
 where
     T: Serialize + ToSchema + Send,
 {
-    fn register(components: &mut Components, operation: &mut Operation) {
+    fn register(_components: &mut Components, operation: &mut Operation) {
         let response = salvo::oapi::Response::new("desc");
         // T::register(components);
         // operation.responses.insert(
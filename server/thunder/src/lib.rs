@@ -1,8 +1,21 @@
 // In my-validator/src/lib.rs
 use std::collections::HashMap;
+use std::fmt::Display;
+mod auth;
+mod csrf;
 mod file;
+mod i18n;
+#[cfg(feature = "images")]
+mod images;
 mod inertia;
 mod salvo_extractor;
+mod schema;
+mod session;
+mod signing;
+mod storage;
+mod streaming;
+mod tus;
+mod validated;
 // mod tera;
 
 // Represents a single validation error
@@ -10,57 +23,433 @@ mod salvo_extractor;
 pub struct ValidationError {
     pub code: String,
     pub message: String,
+    // Values for the `{name}` placeholders of the message. Never serialized: they
+    // can hold the submitted value, e.g. a password.
+    #[serde(skip_serializing)]
+    pub params: HashMap<String, serde_json::Value>,
+    // Set by `message = "..."` overrides, which the locale catalogues leave alone.
+    #[serde(skip_serializing)]
+    custom_message: bool,
+}
+
+impl ValidationError {
+    pub fn new(code: impl Into<String>, message: impl Into<String>) -> ValidationError {
+        ValidationError {
+            code: code.into(),
+            message: message.into(),
+            params: HashMap::new(),
+            custom_message: false,
+        }
+    }
+
+    pub fn with_param(mut self, name: &str, value: impl Serialize) -> ValidationError {
+        self.params.insert(
+            name.to_string(),
+            serde_json::to_value(value).unwrap_or_default(),
+        );
+        self
+    }
+
+    pub fn with_code(mut self, code: impl Into<String>) -> ValidationError {
+        self.code = code.into();
+        self
+    }
+
+    pub fn with_custom_message(mut self, message: impl Into<String>) -> ValidationError {
+        self.message = message.into();
+        self.custom_message = true;
+        self
+    }
+
+    // Replaces the `{name}` placeholders of `template` with the params of this error.
+    pub fn interpolate(&self, template: &str) -> String {
+        let mut message = template.to_string();
+        for (name, value) in &self.params {
+            let value = match value {
+                serde_json::Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            message = message.replace(&format!("{{{}}}", name), &value);
+        }
+        message
+    }
+}
+
+// How `ValidationErrors` are serialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    // `{"errors": {"email": [{"code": "email", "message": "..."}]}}`
+    Full,
+    // `{"errors": {"email": "..."}}`, the first message of every field, the way
+    // Laravel hands errors to an Inertia page.
+    Flat,
 }
 
 // A collection of all errors for a given struct validation
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
 pub struct ValidationErrors {
     // We can store errors per field if we want
-    #[serde(skip_serializing)]
     status_code: Option<StatusCode>,
+    // Unset means `Flat` for Inertia requests and `Full` otherwise.
+    format: Option<ErrorFormat>,
 
     errors: std::collections::HashMap<String, Vec<ValidationError>>,
 }
 
+impl Serialize for ValidationErrors {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("ValidationErrors", 1)?;
+        match self.format.unwrap_or(ErrorFormat::Full) {
+            ErrorFormat::Full => state.serialize_field("errors", &self.errors)?,
+            ErrorFormat::Flat => state.serialize_field("errors", &self.to_flat())?,
+        }
+        state.end()
+    }
+}
+
+impl Default for ValidationErrors {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ValidationErrors {
     pub fn new() -> ValidationErrors {
-        return ValidationErrors {
+        ValidationErrors {
             errors: HashMap::new(),
             status_code: None,
-        };
+            format: None,
+        }
     }
     pub fn is_empty(&self) -> bool {
-        return self.errors.is_empty();
+        self.errors.is_empty()
     }
     pub fn add_status_code(&mut self, status_code: StatusCode) {
         self.status_code = Some(status_code);
     }
+    pub fn status_code(&self) -> Option<StatusCode> {
+        self.status_code
+    }
+
+    pub fn with_format(mut self, format: ErrorFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+    pub fn set_format(&mut self, format: ErrorFormat) {
+        self.format = Some(format);
+    }
+    pub fn format(&self) -> Option<ErrorFormat> {
+        self.format
+    }
 
     pub fn add(&mut self, field_name: &str, error_type: &str, error_message: &str) {
-        match self.errors.get_mut(field_name) {
-            Some(value) => {
-                value.push({
-                    ValidationError {
-                        code: error_type.to_string(),
-                        message: error_message.to_string(),
-                    }
-                });
+        self.add_error(field_name, ValidationError::new(error_type, error_message));
+    }
+
+    pub fn add_error(&mut self, field_name: &str, mut error: ValidationError) {
+        error.message = error.interpolate(&error.message);
+        self.errors
+            .entry(field_name.to_string())
+            .or_default()
+            .push(error);
+    }
+
+    pub fn has(&self, field_name: &str) -> bool {
+        self.errors.contains_key(field_name)
+    }
+
+    // Every error of `field_name`, in the order the rules ran.
+    pub fn get(&self, field_name: &str) -> Option<&[ValidationError]> {
+        self.errors.get(field_name).map(Vec::as_slice)
+    }
+
+    pub fn first(&self, field_name: &str) -> Option<&ValidationError> {
+        self.get(field_name)?.first()
+    }
+
+    pub fn len(&self) -> usize {
+        self.errors.values().map(Vec::len).sum()
+    }
+
+    // The fields and their errors, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[ValidationError])> {
+        self.errors
+            .iter()
+            .map(|(field_name, errors)| (field_name.as_str(), errors.as_slice()))
+    }
+
+    // The first message of every field.
+    pub fn to_flat(&self) -> HashMap<String, String> {
+        self.errors
+            .iter()
+            .filter_map(|(field_name, errors)| {
+                Some((field_name.clone(), errors.first()?.message.clone()))
+            })
+            .collect()
+    }
+
+    // Adds every error of `other` under the same field names.
+    pub fn merge(&mut self, other: ValidationErrors) {
+        self.merge_nested("", other);
+    }
+
+    // Rewrites every message in `locale` using the catalogue loaded from `locales/`.
+    // Errors without a translation, and custom messages, are kept as they are.
+    pub fn translate(&mut self, locale: &str) {
+        let catalogue = MessageCatalogue::global();
+        for error in self.errors.values_mut().flatten() {
+            if error.custom_message {
+                continue;
+            }
+            if let Some(template) = catalogue.message(locale, &error.code) {
+                error.message = error.interpolate(template);
             }
-            None => todo!(),
         }
     }
+
+    // Moves every error of `nested` under `prefix`, so a failing `zip` of the
+    // `address` field ends up as `address.zip` and item 3 of `items` as `items.3.quantity`.
+    pub fn merge_nested(&mut self, prefix: &str, nested: ValidationErrors) {
+        if self.status_code.is_none() {
+            self.status_code = nested.status_code;
+        }
+        if self.format.is_none() {
+            self.format = nested.format;
+        }
+        for (field_name, errors) in nested.errors {
+            let key = if field_name.is_empty() {
+                prefix.to_string()
+            } else if prefix.is_empty() {
+                field_name
+            } else {
+                format!("{}.{}", prefix, field_name)
+            };
+            self.errors.entry(key).or_default().extend(errors);
+        }
+    }
+}
+
+impl IntoIterator for ValidationErrors {
+    type Item = (String, Vec<ValidationError>);
+    type IntoIter = std::collections::hash_map::IntoIter<String, Vec<ValidationError>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.errors.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a ValidationErrors {
+    type Item = (&'a String, &'a Vec<ValidationError>);
+    type IntoIter = std::collections::hash_map::Iter<'a, String, Vec<ValidationError>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.errors.iter()
+    }
 }
 
 pub trait Validate {
+    // The rules that belong to no group.
     fn validate(&self) -> Result<(), ValidationErrors>;
+
+    // The rules of `group`, e.g. `#[validate(required, groups = ["create"])]`, together
+    // with the ones that belong to no group.
+    fn validate_group(&self, group: &str) -> Result<(), ValidationErrors> {
+        let _ = group;
+        self.validate()
+    }
+
+    // Applies the `#[validate(sanitize(...))]` rules; the validated extractors call it
+    // right before `validate`.
+    fn sanitize(&mut self) {}
+
+    // The OpenAPI keywords implied by the rules, keyed by property name.
+    fn schema_constraints() -> Vec<(&'static str, SchemaConstraint)>
+    where
+        Self: Sized,
+    {
+        Vec::new()
+    }
+}
+
+// Validation that needs request state, e.g. the sea-orm `DatabaseConnection`
+// injected into the `Depot`. The derive implements `validate_async_rules` for every
+// `Validate` type with its `custom_async`, `unique` and `exists` rules.
+#[async_trait]
+pub trait AsyncValidate: Validate + Sync {
+    // Only the rules that need the depot.
+    async fn validate_async_rules(&self, depot: &Depot) -> Result<(), ValidationErrors>;
+
+    // Only the rules that need the depot, for `group` and no group.
+    async fn validate_group_async_rules(
+        &self,
+        depot: &Depot,
+        group: &str,
+    ) -> Result<(), ValidationErrors> {
+        let _ = group;
+        self.validate_async_rules(depot).await
+    }
+
+    // The synchronous rules followed by the async ones, all in one collection.
+    async fn validate_async(&self, depot: &Depot) -> Result<(), ValidationErrors> {
+        let mut errors = self.validate().err().unwrap_or_default();
+        if let Err(async_errors) = self.validate_async_rules(depot).await {
+            errors.merge(async_errors);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    // `validate_async` for `group`.
+    async fn validate_group_async(
+        &self,
+        depot: &Depot,
+        group: &str,
+    ) -> Result<(), ValidationErrors> {
+        let mut errors = self.validate_group(group).err().unwrap_or_default();
+        if let Err(async_errors) = self.validate_group_async_rules(depot, group).await {
+            errors.merge(async_errors);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+// Collections validate every element; used by `#[validate(nested)]`.
+impl<T: Validate> Validate for Option<T> {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        rules::validate_in(self.iter().map(|value| ("", value)), None)
+    }
+
+    fn validate_group(&self, group: &str) -> Result<(), ValidationErrors> {
+        rules::validate_in(self.iter().map(|value| ("", value)), Some(group))
+    }
+
+    fn sanitize(&mut self) {
+        if let Some(value) = self {
+            value.sanitize();
+        }
+    }
+}
+
+impl<T: Validate> Validate for Vec<T> {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        rules::validate_in(self.iter().enumerate(), None)
+    }
+
+    fn validate_group(&self, group: &str) -> Result<(), ValidationErrors> {
+        rules::validate_in(self.iter().enumerate(), Some(group))
+    }
+
+    fn sanitize(&mut self) {
+        self.iter_mut().for_each(Validate::sanitize);
+    }
+}
+
+impl<K: Display, T: Validate, S> Validate for HashMap<K, T, S> {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        rules::validate_in(self.iter(), None)
+    }
+
+    fn validate_group(&self, group: &str) -> Result<(), ValidationErrors> {
+        rules::validate_in(self.iter(), Some(group))
+    }
+
+    fn sanitize(&mut self) {
+        self.values_mut().for_each(Validate::sanitize);
+    }
+}
+
+#[async_trait]
+impl<T: AsyncValidate> AsyncValidate for Option<T> {
+    async fn validate_async_rules(&self, depot: &Depot) -> Result<(), ValidationErrors> {
+        rules::validate_async_in(self.iter().map(|value| ("", value)), depot, None).await
+    }
+
+    async fn validate_group_async_rules(
+        &self,
+        depot: &Depot,
+        group: &str,
+    ) -> Result<(), ValidationErrors> {
+        rules::validate_async_in(self.iter().map(|value| ("", value)), depot, Some(group)).await
+    }
+}
+
+#[async_trait]
+impl<T: AsyncValidate> AsyncValidate for Vec<T> {
+    async fn validate_async_rules(&self, depot: &Depot) -> Result<(), ValidationErrors> {
+        rules::validate_async_in(self.iter().enumerate(), depot, None).await
+    }
+
+    async fn validate_group_async_rules(
+        &self,
+        depot: &Depot,
+        group: &str,
+    ) -> Result<(), ValidationErrors> {
+        rules::validate_async_in(self.iter().enumerate(), depot, Some(group)).await
+    }
+}
+
+#[async_trait]
+impl<K: Display + Sync, T: AsyncValidate, S: Sync> AsyncValidate for HashMap<K, T, S> {
+    async fn validate_async_rules(&self, depot: &Depot) -> Result<(), ValidationErrors> {
+        rules::validate_async_in(self.iter(), depot, None).await
+    }
+
+    async fn validate_group_async_rules(
+        &self,
+        depot: &Depot,
+        group: &str,
+    ) -> Result<(), ValidationErrors> {
+        rules::validate_async_in(self.iter(), depot, Some(group)).await
+    }
 }
 
 // Re-export the derive macro from the other crate
+pub use auth::{
+    Abilities, Ability, Auth, AuthError, AuthHandler, Authenticatable, Authenticate, Authorized,
+    CurrentUser, Forbidden, Gate, Guard, ModelAbilities, Policy, RedirectIfAuthenticated,
+    SeaOrmUserProvider, UserProvider, hash_api_token, new_api_token,
+};
+pub use csrf::VerifyCsrfToken;
 pub use file::*;
+pub use hash::{HashError, Hasher};
+pub use i18n::{CatalogueError, MessageCatalogue};
+#[cfg(feature = "images")]
+pub use images::{Conversion, Fit, Image, ImageConversions, ImageError, OutputFormat, StoredImage};
 pub use inertia::*;
-use salvo::http::StatusCode;
+pub use salvo::http::StatusCode;
+pub use salvo::{Depot, Request, async_trait};
 pub use salvo_extractor::FromMultipart;
 pub use salvo_extractor::MultipartValidated;
+pub use salvo_extractor::parse_multipart_field;
+pub use schema::{SchemaConstraint, apply_constraints, document_constraints};
 use serde::Serialize;
+pub use session::{
+    CookieSessionStore, FileSessionStore, MemorySessionStore, RedisSessionStore,
+    SeaOrmSessionStore, Session, SessionError, SessionHandler, SessionRecord, SessionStore,
+    stored_session,
+};
+pub use signing::{AppKeyError, SignatureError, UrlSigner, ValidateSignature};
+pub use storage::{
+    ByteStream, LocalDisk, S3Config, S3Disk, ServeDisk, Storage, StorageError, StoredFile,
+};
+pub use streaming::{MultipartLimits, MultipartStream, StreamPart};
+pub use tus::{FileTusStore, SeaOrmTusStore, TusServer, TusStore, TusUpload, tus_upload};
+pub use validated::{
+    FormValidated, JsonValidated, NoGroup, PathValidated, QueryValidated, ValidationDepot,
+    ValidationGroup,
+};
+pub mod hash;
+pub mod rules;
 pub mod tera_helpers;
-pub use thunder_macros::Validate;
+pub use thunder_macros::{FromMultipart, Validate};
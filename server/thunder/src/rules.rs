@@ -0,0 +1,516 @@
+// Runtime helpers called by the code that `#[derive(Validate)]` generates.
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use salvo::Depot;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait, QueryFilter};
+
+pub use once_cell::sync::Lazy;
+pub use regex::Regex;
+pub use sea_orm::Value;
+
+use crate::{AsyncValidate, UploadedFile, Validate, ValidationError, ValidationErrors};
+
+// Validates `value` for `group`, or for no group when it is `None`.
+pub fn validate_group<T: Validate + ?Sized>(
+    value: &T,
+    group: Option<&str>,
+) -> Result<(), ValidationErrors> {
+    match group {
+        Some(group) => value.validate_group(group),
+        None => value.validate(),
+    }
+}
+
+// `validate_group` for the rules that need the depot.
+pub async fn validate_group_async_rules<T: AsyncValidate + ?Sized>(
+    value: &T,
+    depot: &Depot,
+    group: Option<&str>,
+) -> Result<(), ValidationErrors> {
+    match group {
+        Some(group) => value.validate_group_async_rules(depot, group).await,
+        None => value.validate_async_rules(depot).await,
+    }
+}
+
+// Validates every item of a collection, with its errors under the key of the item.
+pub(crate) fn validate_in<'a, K: Display, T: Validate + 'a>(
+    items: impl Iterator<Item = (K, &'a T)>,
+    group: Option<&str>,
+) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::new();
+    for (key, item) in items {
+        if let Err(item_errors) = validate_group(item, group) {
+            errors.merge_nested(&key.to_string(), item_errors);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+pub(crate) async fn validate_async_in<'a, K: Display, T: AsyncValidate + 'a>(
+    items: impl Iterator<Item = (K, &'a T)>,
+    depot: &Depot,
+    group: Option<&str>,
+) -> Result<(), ValidationErrors> {
+    let mut errors = ValidationErrors::new();
+    for (key, item) in items {
+        if let Err(item_errors) = validate_group_async_rules(item, depot, group).await {
+            errors.merge_nested(&key.to_string(), item_errors);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+fn connection(depot: &Depot) -> Result<&DatabaseConnection, DbErr> {
+    depot.obtain::<DatabaseConnection>().map_err(|_| {
+        DbErr::Custom("No DatabaseConnection found in the Depot. Inject one in a hoop.".to_string())
+    })
+}
+
+fn column<E: EntityTrait>(column: &str) -> Result<E::Column, DbErr> {
+    E::Column::from_str(column).map_err(|_| {
+        DbErr::Custom(format!(
+            "Unknown column `{}` on entity `{}`.",
+            column,
+            E::default().table_name()
+        ))
+    })
+}
+
+// Counts the rows of `E` whose `column` equals `value`. When `ignore` holds a
+// non-null value, the row whose `ignore_column` equals it is left out, which is
+// how an update form skips the record being edited.
+async fn count_matching<E>(
+    depot: &Depot,
+    column_name: &str,
+    value: Value,
+    ignore: Option<(&str, Value)>,
+) -> Result<u64, DbErr>
+where
+    E: EntityTrait,
+    E::Model: Sync,
+{
+    let db = connection(depot)?;
+    let mut query = E::find().filter(column::<E>(column_name)?.eq(value));
+
+    if let Some((ignore_column, ignore_value)) = ignore
+        && ignore_value != ignore_value.as_null()
+    {
+        query = query.filter(column::<E>(ignore_column)?.ne(ignore_value));
+    }
+
+    query.count(db).await
+}
+
+/// Returns `true` when no row of `E` has `value` in `column`.
+pub async fn unique<E>(
+    depot: &Depot,
+    column: &str,
+    value: impl Into<Value>,
+    ignore: Option<(&str, Value)>,
+) -> Result<bool, DbErr>
+where
+    E: EntityTrait,
+    E::Model: Sync,
+{
+    Ok(count_matching::<E>(depot, column, value.into(), ignore).await? == 0)
+}
+
+/// Returns `true` when at least one row of `E` has `value` in `column`.
+pub async fn exists<E>(depot: &Depot, column: &str, value: impl Into<Value>) -> Result<bool, DbErr>
+where
+    E: EntityTrait,
+    E::Model: Sync,
+{
+    Ok(count_matching::<E>(depot, column, value.into(), None).await? > 0)
+}
+
+/// The error `unique` and `exists` report when the query itself fails. The details
+/// go to the log, not to the client, which gets a generic message with the 500.
+pub fn database_failed(field: &str, error: DbErr) -> ValidationError {
+    tracing::error!("database check of `{}` failed: {}", field, error);
+    ValidationError::new("database", "The value could not be checked.")
+}
+
+// `file(max_size = "5MB", mime = [...], extensions = [...])`. The size is already
+// in bytes: the derive converts the unit at compile time.
+pub struct FileRule<'a> {
+    pub max_size: Option<u64>,
+    pub mime: &'a [&'a str],
+    pub extensions: &'a [&'a str],
+}
+
+// `image(min_width = .., max_width = .., min_height = .., max_height = .., ratio = ..)`.
+#[derive(Default)]
+pub struct ImageRule {
+    pub min_width: Option<usize>,
+    pub max_width: Option<usize>,
+    pub min_height: Option<usize>,
+    pub max_height: Option<usize>,
+    pub ratio: Option<f64>,
+}
+
+// The files of an `UploadedFile`, `Option<UploadedFile>` or `Vec<UploadedFile>` field.
+pub trait Files {
+    fn files(&self) -> Vec<&UploadedFile>;
+}
+
+impl Files for UploadedFile {
+    fn files(&self) -> Vec<&UploadedFile> {
+        vec![self]
+    }
+}
+
+impl Files for Option<UploadedFile> {
+    fn files(&self) -> Vec<&UploadedFile> {
+        self.iter().collect()
+    }
+}
+
+impl Files for Vec<UploadedFile> {
+    fn files(&self) -> Vec<&UploadedFile> {
+        self.iter().collect()
+    }
+}
+
+// The file rules read the uploads, so they run with `unique` and `exists` in
+// `AsyncValidate` rather than blocking the executor in `Validate::validate`.
+pub async fn check_files(field: &impl Files, rule: &FileRule<'_>) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+
+    for file in field.files() {
+        if let Some(max_size) = rule.max_size {
+            match file.size().await {
+                Ok(size) if size <= max_size => {}
+                _ => errors.push(
+                    ValidationError::new(
+                        "file_max_size",
+                        "The file may not be greater than {max_size} bytes.",
+                    )
+                    .with_param("max_size", max_size),
+                ),
+            }
+        }
+
+        if !rule.mime.is_empty() {
+            let sniffed = file.sniff_mime().await.ok().flatten();
+            if !sniffed.is_some_and(|mime| rule.mime.contains(&mime)) {
+                errors.push(
+                    ValidationError::new("file_mime", "The file must be of type: {mime}.")
+                        .with_param("mime", rule.mime.join(", ")),
+                );
+            }
+        }
+
+        if !rule.extensions.is_empty() {
+            let extension = file.extension();
+            if !extension.is_some_and(|extension| rule.extensions.contains(&extension.as_str())) {
+                errors.push(
+                    ValidationError::new(
+                        "file_extension",
+                        "The file must have one of the extensions: {extensions}.",
+                    )
+                    .with_param("extensions", rule.extensions.join(", ")),
+                );
+            }
+        }
+    }
+
+    errors
+}
+
+pub async fn check_images(field: &impl Files, rule: &ImageRule) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+
+    for file in field.files() {
+        let is_image = file
+            .sniff_mime()
+            .await
+            .ok()
+            .flatten()
+            .is_some_and(|mime| mime.starts_with("image/"));
+        let dimensions = if is_image {
+            file.dimensions().await
+        } else {
+            None
+        };
+        let Some((width, height)) = dimensions else {
+            errors.push(ValidationError::new("image", "The file must be an image."));
+            continue;
+        };
+
+        let fits = rule.min_width.is_none_or(|min| width >= min)
+            && rule.max_width.is_none_or(|max| width <= max)
+            && rule.min_height.is_none_or(|min| height >= min)
+            && rule.max_height.is_none_or(|max| height <= max)
+            && rule.ratio.is_none_or(|ratio| {
+                height > 0 && (width as f64 / height as f64 - ratio).abs() < 0.01
+            });
+        if !fits {
+            errors.push(
+                ValidationError::new(
+                    "image_dimensions",
+                    "The image has invalid dimensions ({width}x{height}).",
+                )
+                .with_param("width", width)
+                .with_param("height", height),
+            );
+        }
+    }
+
+    errors
+}
+
+// `password(min_len = .., mixed_case, numbers, symbols, not_compromised_local_list)`.
+pub struct PasswordRule {
+    pub min_len: usize,
+    pub mixed_case: bool,
+    pub numbers: bool,
+    pub symbols: bool,
+    pub not_compromised: bool,
+}
+
+pub fn check_password(password: &str, rule: &PasswordRule) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+
+    if password.chars().count() < rule.min_len {
+        errors.push(
+            ValidationError::new("password_min_len", "Must be at least {min} characters.")
+                .with_param("min", rule.min_len),
+        );
+    }
+    if rule.mixed_case
+        && !(password.chars().any(char::is_uppercase) && password.chars().any(char::is_lowercase))
+    {
+        errors.push(ValidationError::new(
+            "password_mixed_case",
+            "Must contain at least one uppercase and one lowercase letter.",
+        ));
+    }
+    if rule.numbers && !password.chars().any(char::is_numeric) {
+        errors.push(ValidationError::new(
+            "password_numbers",
+            "Must contain at least one number.",
+        ));
+    }
+    if rule.symbols
+        && !password
+            .chars()
+            .any(|c| !c.is_alphanumeric() && !c.is_whitespace())
+    {
+        errors.push(ValidationError::new(
+            "password_symbols",
+            "Must contain at least one symbol.",
+        ));
+    }
+    if rule.not_compromised && crate::hash::is_compromised(password) {
+        errors.push(ValidationError::new(
+            "password_compromised",
+            "This password has appeared in a data leak. Please choose a different one.",
+        ));
+    }
+
+    errors
+}
+
+// What `length(min, max, equal)` measures: characters for strings, items for collections.
+pub trait Length {
+    fn length(&self) -> usize;
+}
+
+impl Length for String {
+    fn length(&self) -> usize {
+        self.chars().count()
+    }
+}
+
+impl Length for &str {
+    fn length(&self) -> usize {
+        self.chars().count()
+    }
+}
+
+impl<T> Length for Vec<T> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+impl<K, V, S> Length for HashMap<K, V, S> {
+    fn length(&self) -> usize {
+        self.len()
+    }
+}
+
+// Whether a field counts as filled in for `required`, `required_if` and `required_with`:
+// `None`, empty collections and blank strings are missing.
+pub trait Presence {
+    fn is_present(&self) -> bool;
+}
+
+impl Presence for String {
+    fn is_present(&self) -> bool {
+        !self.trim().is_empty()
+    }
+}
+
+impl Presence for &str {
+    fn is_present(&self) -> bool {
+        !self.trim().is_empty()
+    }
+}
+
+impl<T: Presence> Presence for Option<T> {
+    fn is_present(&self) -> bool {
+        self.as_ref().is_some_and(Presence::is_present)
+    }
+}
+
+impl<T> Presence for Vec<T> {
+    fn is_present(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl<K, V, S> Presence for HashMap<K, V, S> {
+    fn is_present(&self) -> bool {
+        !self.is_empty()
+    }
+}
+
+impl Presence for UploadedFile {
+    fn is_present(&self) -> bool {
+        true
+    }
+}
+
+macro_rules! always_present {
+    ($($ty:ty),*) => {
+        $(
+            impl Presence for $ty {
+                fn is_present(&self) -> bool {
+                    true
+                }
+            }
+        )*
+    };
+}
+
+always_present!(
+    bool, char, u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64
+);
+
+// The text of a field that `sanitize(...)` rewrites: a `String`, or every string
+// inside an `Option` or `Vec`.
+pub trait Text {
+    fn map_text(&mut self, f: fn(&str) -> String);
+}
+
+impl Text for String {
+    fn map_text(&mut self, f: fn(&str) -> String) {
+        *self = f(self);
+    }
+}
+
+impl<T: Text> Text for Option<T> {
+    fn map_text(&mut self, f: fn(&str) -> String) {
+        if let Some(value) = self {
+            value.map_text(f);
+        }
+    }
+}
+
+impl<T: Text> Text for Vec<T> {
+    fn map_text(&mut self, f: fn(&str) -> String) {
+        self.iter_mut().for_each(|value| value.map_text(f));
+    }
+}
+
+/// `#[validate(sanitize(trim))]`
+pub fn trim(value: &str) -> String {
+    value.trim().to_string()
+}
+
+/// `#[validate(sanitize(lowercase))]`
+pub fn lowercase(value: &str) -> String {
+    value.to_lowercase()
+}
+
+/// `#[validate(sanitize(strip_tags))]` removes anything that looks like an HTML tag.
+pub fn strip_tags(value: &str) -> String {
+    static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"<[^>]*>").unwrap());
+    TAG.replace_all(value, "").into_owned()
+}
+
+/// `#[validate(sanitize(collapse_whitespace))]` turns every run of whitespace into one space.
+pub fn collapse_whitespace(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// The point in time of a `NaiveDate`, `NaiveDateTime` or `DateTime<Tz>` field for
+// `after`, `before`, `future` and `past`. Dates count from midnight and `DateTime`s
+// are compared in UTC; `None` has no moment, so the rule is skipped.
+pub trait Moment {
+    fn moment(&self) -> Option<NaiveDateTime>;
+
+    // What "now" means for the type: today at midnight for dates.
+    fn now(&self) -> NaiveDateTime {
+        now()
+    }
+}
+
+impl Moment for NaiveDate {
+    fn moment(&self) -> Option<NaiveDateTime> {
+        Some(self.and_time(Default::default()))
+    }
+
+    fn now(&self) -> NaiveDateTime {
+        today()
+    }
+}
+
+impl Moment for NaiveDateTime {
+    fn moment(&self) -> Option<NaiveDateTime> {
+        Some(*self)
+    }
+}
+
+impl<Tz: TimeZone> Moment for DateTime<Tz> {
+    fn moment(&self) -> Option<NaiveDateTime> {
+        Some(self.naive_utc())
+    }
+}
+
+impl<T: Moment> Moment for Option<T> {
+    fn moment(&self) -> Option<NaiveDateTime> {
+        self.as_ref()?.moment()
+    }
+}
+
+// `today` in a rule: midnight of the current UTC date.
+pub fn today() -> NaiveDateTime {
+    Utc::now().date_naive().and_time(Default::default())
+}
+
+// `now` in a rule.
+pub fn now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+// A fixed date of a rule, which the derive parsed into a UTC timestamp.
+pub fn moment_at(seconds: i64, nanoseconds: u32) -> Option<NaiveDateTime> {
+    DateTime::from_timestamp(seconds, nanoseconds).map(|date_time| date_time.naive_utc())
+}
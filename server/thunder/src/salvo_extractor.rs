@@ -1,7 +1,8 @@
 use std::fmt::{self, Debug, Display, Formatter};
+use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
 
-use salvo::http::{StatusCode, StatusError};
+use salvo::http::{StatusCode, header};
 use salvo::oapi::{
     Components, Content, EndpointArgRegister, Operation, RequestBody, ToRequestBody, ToSchema,
 };
@@ -9,26 +10,53 @@ use salvo::writing::Json;
 use salvo::{Depot, Response};
 use salvo_core::extract::{Extractible, Metadata};
 use salvo_core::{Request, Writer, async_trait};
+use serde::de::DeserializeOwned;
 
-use crate::ValidationErrors;
+use crate::validated::{NoGroup, ValidationGroup, validated};
+use crate::{
+    AsyncValidate, ErrorFormat, MessageCatalogue, Validate, ValidationError, ValidationErrors,
+    document_constraints,
+};
 
 #[async_trait]
 pub trait FromMultipart: Sized {
-    /// The error type returned when parsing fails.
-
     /// The method that performs the custom parsing logic.
     async fn parse_from_multipart(req: &mut Request) -> Result<Self, ValidationErrors>;
 }
 
-pub struct MultipartValidated<T>(pub T);
-impl<T> MultipartValidated<T> {
+// Deserializes one text part for `#[derive(FromMultipart)]`, recording an `invalid`
+// error under `name` when the value does not fit the field type.
+pub fn parse_multipart_field<T: DeserializeOwned>(
+    name: &str,
+    value: &str,
+    errors: &mut ValidationErrors,
+) -> Option<T> {
+    match salvo_core::serde::from_str_val(value) {
+        Ok(parsed) => Some(parsed),
+        Err(error) => {
+            errors.add_error(
+                name,
+                ValidationError::new("invalid", "The value is invalid: {reason}.")
+                    .with_param("reason", error.to_string()),
+            );
+            None
+        }
+    }
+}
+
+pub struct MultipartValidated<T, G = NoGroup>(pub T, PhantomData<G>);
+impl<T, G> MultipartValidated<T, G> {
+    pub fn new(value: T) -> Self {
+        Self(value, PhantomData)
+    }
+
     /// Consumes self and returns the value of the parameter.
     pub fn into_inner(self) -> T {
         self.0
     }
 }
 
-impl<T> Deref for MultipartValidated<T> {
+impl<T, G> Deref for MultipartValidated<T, G> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -36,31 +64,30 @@ impl<T> Deref for MultipartValidated<T> {
     }
 }
 
-impl<T> DerefMut for MultipartValidated<T> {
+impl<T, G> DerefMut for MultipartValidated<T, G> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.0
     }
 }
 
-impl<'de, T> ToRequestBody for MultipartValidated<T>
+impl<T, G> ToRequestBody for MultipartValidated<T, G>
 where
-    T: ToSchema,
+    T: ToSchema + Validate,
 {
     fn to_request_body(components: &mut Components) -> RequestBody {
+        let schema = T::to_schema(components);
+        let schema = document_constraints::<T>(components, schema);
         RequestBody::new()
             .description("Extract form format data from request.")
             // .add_content(
             //     "application/x-www-form-urlencoded",
             //     Content::new(T::to_schema(components)),
             // )
-            .add_content(
-                "multipart/form-data",
-                Content::new(T::to_schema(components)),
-            )
+            .add_content("multipart/form-data", Content::new(schema))
     }
 }
 
-impl<T> fmt::Debug for MultipartValidated<T>
+impl<T, G> fmt::Debug for MultipartValidated<T, G>
 where
     T: Debug,
 {
@@ -69,15 +96,16 @@ where
     }
 }
 
-impl<T: Display> Display for MultipartValidated<T> {
+impl<T: Display, G> Display for MultipartValidated<T, G> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         self.0.fmt(f)
     }
 }
 
-impl<'ex, T> Extractible<'ex> for MultipartValidated<T>
+impl<'ex, T, G> Extractible<'ex> for MultipartValidated<T, G>
 where
-    T: Send + FromMultipart + Debug,
+    T: Send + FromMultipart + AsyncValidate + Debug,
+    G: ValidationGroup,
 {
     fn metadata() -> &'static Metadata {
         static METADATA: Metadata = Metadata::new("");
@@ -86,32 +114,38 @@ where
     async fn extract(
         req: &'ex mut Request,
     ) -> Result<Self, impl Writer + Send + fmt::Debug + 'static> {
-        let value = match T::parse_from_multipart(req).await {
-            Ok(v) => v,
-            Err(e) => {
-                //TODO: verify if its ok
-                return Err(e);
-            }
-        };
-        return Ok(MultipartValidated(value));
+        let value = T::parse_from_multipart(req).await?;
+        validated(value, G::NAME, req)
+            .await
+            .map(MultipartValidated::new)
     }
 }
 
 #[async_trait]
 impl Writer for ValidationErrors {
-    async fn write(mut self, _: &mut Request, _: &mut Depot, res: &mut Response) {
-        res.status_code(
-            self.status_code
-                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
-        );
+    async fn write(mut self, req: &mut Request, _: &mut Depot, res: &mut Response) {
+        let locale = req
+            .headers()
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| MessageCatalogue::global().negotiate(value));
+        if let Some(locale) = locale {
+            self.translate(locale);
+        }
+
+        if self.format.is_none() && req.headers().contains_key("X-Inertia") {
+            self.format = Some(ErrorFormat::Flat);
+        }
+
+        res.status_code(self.status_code.unwrap_or(StatusCode::UNPROCESSABLE_ENTITY));
         res.render(Json(self));
     }
 }
 
 #[async_trait]
-impl<'de, T> EndpointArgRegister for MultipartValidated<T>
+impl<T, G> EndpointArgRegister for MultipartValidated<T, G>
 where
-    T: ToSchema,
+    T: ToSchema + Validate,
 {
     fn register(components: &mut Components, operation: &mut Operation, _arg: &str) {
         let request_body = Self::to_request_body(components);
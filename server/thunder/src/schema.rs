@@ -0,0 +1,82 @@
+use salvo::oapi::{Components, KnownFormat, Object, RefOr, Schema, SchemaFormat};
+
+use crate::Validate;
+
+// A JSON Schema keyword implied by a `#[validate]` rule. The derive lists them per
+// field in `Validate::schema_constraints`; the validated extractors write them into
+// the schema that `ToSchema` produced.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SchemaConstraint {
+    // `minLength` on strings, `minItems` on arrays.
+    MinLength(usize),
+    // `maxLength` on strings, `maxItems` on arrays.
+    MaxLength(usize),
+    Minimum(f64),
+    Maximum(f64),
+    Pattern(&'static str),
+    Email,
+    // Lists the property in the `required` array of the object.
+    Required,
+}
+
+// Adds the constraints of `T` to its schema. A `$ref` is patched in place inside
+// `components`, an inline schema is returned patched.
+pub fn document_constraints<T: Validate>(
+    components: &mut Components,
+    schema: RefOr<Schema>,
+) -> RefOr<Schema> {
+    let constraints = T::schema_constraints();
+    if constraints.is_empty() {
+        return schema;
+    }
+
+    match schema {
+        RefOr::Ref(reference) => {
+            let referenced = reference
+                .ref_location
+                .strip_prefix("#/components/schemas/")
+                .and_then(|name| components.schemas.0.get_mut(name));
+            if let Some(RefOr::Type(Schema::Object(object))) = referenced {
+                apply_constraints(object, &constraints);
+            }
+            RefOr::Ref(reference)
+        }
+        RefOr::Type(Schema::Object(mut object)) => {
+            apply_constraints(&mut object, &constraints);
+            RefOr::Type(Schema::Object(object))
+        }
+        schema => schema,
+    }
+}
+
+pub fn apply_constraints(object: &mut Object, constraints: &[(&'static str, SchemaConstraint)]) {
+    for (field, constraint) in constraints {
+        if *constraint == SchemaConstraint::Required {
+            object.required.insert(field.to_string());
+            continue;
+        }
+        match object.properties.get_mut(*field) {
+            Some(RefOr::Type(Schema::Object(property))) => apply_to_property(property, constraint),
+            Some(RefOr::Type(Schema::Array(array))) => match constraint {
+                SchemaConstraint::MinLength(min) => array.min_items = Some(*min),
+                SchemaConstraint::MaxLength(max) => array.max_items = Some(*max),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+fn apply_to_property(property: &mut Object, constraint: &SchemaConstraint) {
+    match constraint {
+        SchemaConstraint::MinLength(min) => property.min_length = Some(*min),
+        SchemaConstraint::MaxLength(max) => property.max_length = Some(*max),
+        SchemaConstraint::Minimum(min) => property.minimum = Some(*min),
+        SchemaConstraint::Maximum(max) => property.maximum = Some(*max),
+        SchemaConstraint::Pattern(pattern) => property.pattern = Some(pattern.to_string()),
+        SchemaConstraint::Email => {
+            property.format = Some(SchemaFormat::KnownFormat(KnownFormat::Email))
+        }
+        SchemaConstraint::Required => {}
+    }
+}
@@ -0,0 +1,191 @@
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use salvo::async_trait;
+use sha2::Sha256;
+
+use super::store::{Entry, SessionStore};
+use super::{SessionError, SessionRecord};
+
+// Browsers drop cookies bigger than this, name and attributes included.
+const MAX_COOKIE_SIZE: usize = 4000;
+
+// Keeps the whole session in the cookie, encrypted and authenticated with
+// AES-256-GCM: the client can neither read nor alter it. Nothing is stored on the
+// server, so a session cannot be revoked before it expires, and it must stay
+// well under 4 KB.
+#[derive(Clone)]
+pub struct CookieSessionStore {
+    cipher: Aes256Gcm,
+}
+
+impl std::fmt::Debug for CookieSessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CookieSessionStore").finish_non_exhaustive()
+    }
+}
+
+impl CookieSessionStore {
+    // `key` is the app key; the encryption key is derived from it, so it can be
+    // shared with `UrlSigner`.
+    pub fn new(key: impl AsRef<[u8]>) -> Self {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.as_ref())
+            .expect("HMAC accepts keys of any size");
+        mac.update(b"thunder session cookie");
+        let key = mac.finalize().into_bytes();
+        CookieSessionStore {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for CookieSessionStore {
+    async fn load(&self, cookie: &str) -> Result<Option<SessionRecord>, SessionError> {
+        // Anything that does not decrypt was not made with this key: no session.
+        let Ok(sealed) = URL_SAFE_NO_PAD.decode(cookie) else {
+            return Ok(None);
+        };
+        if sealed.len() < 12 {
+            return Ok(None);
+        }
+        let (nonce, ciphertext) = sealed.split_at(12);
+        let Ok(plaintext) = self.cipher.decrypt(Nonce::from_slice(nonce), ciphertext) else {
+            return Ok(None);
+        };
+        // The expiry is inside, since the client could keep the cookie past it.
+        Ok(serde_json::from_slice::<Entry>(&plaintext)
+            .ok()
+            .filter(|entry| entry.expires_at > Utc::now())
+            .map(|entry| entry.record))
+    }
+
+    async fn save(
+        &self,
+        record: &SessionRecord,
+        expires_at: DateTime<Utc>,
+    ) -> Result<String, SessionError> {
+        let entry = Entry {
+            record: record.clone(),
+            expires_at,
+        };
+        let plaintext =
+            serde_json::to_vec(&entry).map_err(|error| SessionError::Encode(error.to_string()))?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_slice())
+            .map_err(|error| SessionError::Encode(error.to_string()))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&ciphertext);
+        let cookie = URL_SAFE_NO_PAD.encode(sealed);
+        if cookie.len() > MAX_COOKIE_SIZE {
+            return Err(SessionError::Encode(format!(
+                "the session takes {} bytes, more than a cookie can hold",
+                cookie.len()
+            )));
+        }
+        Ok(cookie)
+    }
+
+    // The browser drops the cookie; there is nothing on the server.
+    async fn destroy(&self, _id: &str) -> Result<(), SessionError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn record() -> SessionRecord {
+        let mut record = SessionRecord::new();
+        record
+            .data
+            .insert("user_id".to_string(), serde_json::json!(42));
+        record
+    }
+
+    #[tokio::test]
+    async fn reads_back_what_it_saved() {
+        let store = CookieSessionStore::new("test-key");
+        let record = record();
+        let cookie = store
+            .save(&record, Utc::now() + Duration::hours(1))
+            .await
+            .unwrap();
+        assert!(!cookie.contains("user_id"));
+
+        let loaded = store.load(&cookie).await.unwrap().unwrap();
+        assert_eq!(loaded.id, record.id);
+        assert_eq!(loaded.data, record.data);
+    }
+
+    #[tokio::test]
+    async fn seals_each_save_with_a_fresh_nonce() {
+        let store = CookieSessionStore::new("test-key");
+        let expires_at = Utc::now() + Duration::hours(1);
+        let record = record();
+        assert_ne!(
+            store.save(&record, expires_at).await.unwrap(),
+            store.save(&record, expires_at).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_a_tampered_cookie() {
+        let store = CookieSessionStore::new("test-key");
+        let cookie = store
+            .save(&record(), Utc::now() + Duration::hours(1))
+            .await
+            .unwrap();
+
+        let mut sealed = URL_SAFE_NO_PAD.decode(&cookie).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 1;
+        let tampered = URL_SAFE_NO_PAD.encode(sealed);
+        assert!(store.load(&tampered).await.unwrap().is_none());
+
+        assert!(store.load("not a cookie").await.unwrap().is_none());
+        assert!(store.load("").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn ignores_a_cookie_made_with_another_key() {
+        let cookie = CookieSessionStore::new("other-key")
+            .save(&record(), Utc::now() + Duration::hours(1))
+            .await
+            .unwrap();
+        let store = CookieSessionStore::new("test-key");
+        assert!(store.load(&cookie).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn ignores_an_expired_cookie() {
+        let store = CookieSessionStore::new("test-key");
+        let cookie = store
+            .save(&record(), Utc::now() - Duration::seconds(1))
+            .await
+            .unwrap();
+        assert!(store.load(&cookie).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn refuses_a_session_too_big_for_a_cookie() {
+        let store = CookieSessionStore::new("test-key");
+        let mut record = record();
+        record
+            .data
+            .insert("notes".to_string(), serde_json::json!("x".repeat(4000)));
+        let error = store
+            .save(&record, Utc::now() + Duration::hours(1))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, SessionError::Encode(_)));
+    }
+}
@@ -0,0 +1,115 @@
+// Sessions in the application database. The table comes from the `create_sessions`
+// migration.
+use chrono::{DateTime, Utc};
+use salvo::async_trait;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set};
+
+use super::store::SessionStore;
+use super::{SessionError, SessionRecord, is_valid_id};
+
+pub mod stored_session {
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "sessions")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub id: String,
+        // The data as a JSON object.
+        #[sea_orm(column_type = "Text")]
+        pub data: String,
+        pub created_at: DateTimeUtc,
+        pub last_activity: DateTimeUtc,
+        pub expires_at: DateTimeUtc,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+#[derive(Debug, Clone)]
+pub struct SeaOrmSessionStore {
+    db: DatabaseConnection,
+}
+
+impl SeaOrmSessionStore {
+    pub fn new(db: DatabaseConnection) -> Self {
+        SeaOrmSessionStore { db }
+    }
+}
+
+fn database_failed(error: DbErr) -> SessionError {
+    SessionError::Store(error.to_string())
+}
+
+#[async_trait]
+impl SessionStore for SeaOrmSessionStore {
+    async fn load(&self, cookie: &str) -> Result<Option<SessionRecord>, SessionError> {
+        if !is_valid_id(cookie) {
+            return Ok(None);
+        }
+        let model = stored_session::Entity::find_by_id(cookie.to_string())
+            .filter(stored_session::Column::ExpiresAt.gt(Utc::now()))
+            .one(&self.db)
+            .await
+            .map_err(database_failed)?;
+        Ok(model.and_then(|model| {
+            Some(SessionRecord {
+                data: serde_json::from_str(&model.data).ok()?,
+                id: model.id,
+                created_at: model.created_at,
+                last_activity: model.last_activity,
+            })
+        }))
+    }
+
+    async fn save(
+        &self,
+        record: &SessionRecord,
+        expires_at: DateTime<Utc>,
+    ) -> Result<String, SessionError> {
+        let data = serde_json::to_string(&record.data)
+            .map_err(|error| SessionError::Encode(error.to_string()))?;
+        let model = stored_session::ActiveModel {
+            id: Set(record.id.clone()),
+            data: Set(data),
+            created_at: Set(record.created_at),
+            last_activity: Set(record.last_activity),
+            expires_at: Set(expires_at),
+        };
+        stored_session::Entity::insert(model)
+            .on_conflict(
+                OnConflict::column(stored_session::Column::Id)
+                    .update_columns([
+                        stored_session::Column::Data,
+                        stored_session::Column::LastActivity,
+                        stored_session::Column::ExpiresAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec(&self.db)
+            .await
+            .map_err(database_failed)?;
+        Ok(record.id.clone())
+    }
+
+    async fn destroy(&self, id: &str) -> Result<(), SessionError> {
+        stored_session::Entity::delete_by_id(id.to_string())
+            .exec(&self.db)
+            .await
+            .map_err(database_failed)?;
+        Ok(())
+    }
+
+    async fn gc(&self, now: DateTime<Utc>) -> Result<(), SessionError> {
+        stored_session::Entity::delete_many()
+            .filter(stored_session::Column::ExpiresAt.lte(now))
+            .exec(&self.db)
+            .await
+            .map_err(database_failed)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,389 @@
+// Sessions: data kept for a browser between requests, found again through a cookie.
+// `SessionHandler` loads the session before the handlers run and saves it after;
+// handlers reach it with the `Session` extractor, hoops through the `Depot`.
+//
+//     Router::new()
+//         .hoop(SessionHandler::new(CookieSessionStore::new(app_key)))
+//         .push(Router::with_path("cart").post(add_to_cart))
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use aes_gcm::aead::OsRng;
+use aes_gcm::aead::rand_core::RngCore;
+use chrono::{DateTime, Duration, Utc};
+use salvo::http::StatusError;
+use salvo::http::cookie::{Cookie, SameSite};
+use salvo::oapi::{Components, EndpointArgRegister, Operation};
+use salvo::{Depot, FlowCtrl, Handler, Request, Response, Writer, async_trait};
+use salvo_core::extract::{Extractible, Metadata};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+mod cookie;
+mod database;
+mod redis;
+mod store;
+
+pub use self::cookie::CookieSessionStore;
+pub use self::database::{SeaOrmSessionStore, stored_session};
+pub use self::redis::RedisSessionStore;
+pub use self::store::{FileSessionStore, MemorySessionStore, SessionStore};
+
+#[derive(Debug)]
+pub enum SessionError {
+    // The store could not be reached or answered with an error.
+    Store(String),
+    // A value could not be turned into JSON, or the session is too big for its store.
+    Encode(String),
+}
+
+impl Display for SessionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Store(message) => write!(f, "session store error: {}", message),
+            SessionError::Encode(message) => write!(f, "could not encode the session: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+// What a store saves for one session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionRecord {
+    pub id: String,
+    pub data: HashMap<String, serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+    pub last_activity: DateTime<Utc>,
+}
+
+impl SessionRecord {
+    fn new() -> Self {
+        let now = Utc::now();
+        SessionRecord {
+            id: new_id(),
+            data: HashMap::new(),
+            created_at: now,
+            last_activity: now,
+        }
+    }
+}
+
+// 160 random bits, hex-encoded.
+fn new_id() -> String {
+    let mut bytes = [0; 20];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+// Where `Session::token` keeps the CSRF token.
+const TOKEN_KEY: &str = "_token";
+
+pub(crate) fn is_valid_id(id: &str) -> bool {
+    id.len() == 40 && id.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+#[derive(Debug)]
+struct State {
+    record: SessionRecord,
+    // The id the session was loaded under, to be destroyed when it changes.
+    stored_id: Option<String>,
+}
+
+// The session of the current request. Clones share the same data, and changes are
+// saved once the response is ready.
+#[derive(Debug, Clone)]
+pub struct Session {
+    state: Arc<Mutex<State>>,
+}
+
+impl Session {
+    fn new(record: SessionRecord, stored_id: Option<String>) -> Self {
+        Session {
+            state: Arc::new(Mutex::new(State { record, stored_id })),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|error| error.into_inner())
+    }
+
+    pub fn id(&self) -> String {
+        self.state().record.id.clone()
+    }
+
+    // The value under `key`, or `None` when it is missing or not a `T`.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let state = self.state();
+        let value = state.record.data.get(key)?;
+        serde_json::from_value(value.clone()).ok()
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.state().record.data.contains_key(key)
+    }
+
+    pub fn insert<T: Serialize>(
+        &self,
+        key: impl Into<String>,
+        value: T,
+    ) -> Result<(), SessionError> {
+        let value =
+            serde_json::to_value(value).map_err(|error| SessionError::Encode(error.to_string()))?;
+        self.state().record.data.insert(key.into(), value);
+        Ok(())
+    }
+
+    // Removes `key` and returns its value, if it was a `T`.
+    pub fn remove<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.state().record.data.remove(key)?;
+        serde_json::from_value(value).ok()
+    }
+
+    pub fn clear(&self) {
+        self.state().record.data.clear();
+    }
+
+    // Moves the data to a new id, e.g. right after a login, so that an id planted
+    // before it is worthless.
+    pub fn regenerate(&self) {
+        self.state().record.id = new_id();
+    }
+
+    // The CSRF token of the session, made on first use. `VerifyCsrfToken` checks the
+    // state-changing requests against it.
+    pub fn token(&self) -> String {
+        let mut state = self.state();
+        if let Some(serde_json::Value::String(token)) = state.record.data.get(TOKEN_KEY) {
+            return token.clone();
+        }
+        let token = new_id();
+        state
+            .record
+            .data
+            .insert(TOKEN_KEY.to_string(), token.clone().into());
+        token
+    }
+
+    // Replaces the CSRF token, e.g. on login.
+    pub fn regenerate_token(&self) {
+        self.state()
+            .record
+            .data
+            .insert(TOKEN_KEY.to_string(), new_id().into());
+    }
+
+    // Drops everything and starts a new session, e.g. on logout.
+    pub fn invalidate(&self) {
+        let mut state = self.state();
+        state.record = SessionRecord::new();
+    }
+}
+
+impl<'ex> Extractible<'ex> for Session {
+    fn metadata() -> &'static Metadata {
+        static METADATA: Metadata = Metadata::new("");
+        &METADATA
+    }
+
+    async fn extract(
+        req: &'ex mut Request,
+    ) -> Result<Self, impl Writer + Send + fmt::Debug + 'static> {
+        req.extensions().get::<Session>().cloned().ok_or_else(|| {
+            StatusError::internal_server_error()
+                .brief("No session: add a SessionHandler hoop in front of this route.")
+        })
+    }
+}
+
+// Nothing to document: the session comes from a cookie the browser handles.
+impl EndpointArgRegister for Session {
+    fn register(_components: &mut Components, _operation: &mut Operation, _arg: &str) {}
+}
+
+// Hoop that loads the session from the cookie and saves it after the handlers.
+#[derive(Clone)]
+pub struct SessionHandler {
+    store: Arc<dyn SessionStore>,
+    cookie_name: String,
+    idle_timeout: Duration,
+    absolute_timeout: Option<Duration>,
+    secure: bool,
+    same_site: SameSite,
+    // `chance` in `out_of` requests also collect the expired sessions.
+    gc_lottery: (u32, u32),
+}
+
+impl SessionHandler {
+    pub fn new(store: impl SessionStore + 'static) -> Self {
+        SessionHandler {
+            store: Arc::new(store),
+            cookie_name: "thunder_session".to_string(),
+            idle_timeout: Duration::hours(2),
+            absolute_timeout: None,
+            secure: false,
+            same_site: SameSite::Lax,
+            gc_lottery: (2, 100),
+        }
+    }
+
+    pub fn cookie_name(mut self, name: impl Into<String>) -> Self {
+        self.cookie_name = name.into();
+        self
+    }
+
+    // How long a session lasts without requests, 2 hours by default.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    // How long a session lasts at most, however active. Unlimited by default.
+    pub fn absolute_timeout(mut self, timeout: Duration) -> Self {
+        self.absolute_timeout = Some(timeout);
+        self
+    }
+
+    // Sends the cookie over HTTPS only; turn it on in production.
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    // Collects expired sessions on `chance` in `out_of` requests, 2 in 100 by
+    // default. `(0, 1)` leaves it to a scheduled `gc` call.
+    pub fn gc_lottery(mut self, chance: u32, out_of: u32) -> Self {
+        self.gc_lottery = (chance, out_of.max(1));
+        self
+    }
+
+    pub async fn gc(&self) -> Result<(), SessionError> {
+        self.store.gc(Utc::now()).await
+    }
+
+    fn is_expired(&self, record: &SessionRecord, now: DateTime<Utc>) -> bool {
+        now - record.last_activity > self.idle_timeout
+            || self
+                .absolute_timeout
+                .is_some_and(|timeout| now - record.created_at > timeout)
+    }
+
+    // When a session saved now stops being valid.
+    fn expires_at(&self, record: &SessionRecord, now: DateTime<Utc>) -> DateTime<Utc> {
+        let idle = now + self.idle_timeout;
+        match self.absolute_timeout {
+            Some(timeout) => idle.min(record.created_at + timeout),
+            None => idle,
+        }
+    }
+
+    async fn load(&self, req: &Request) -> Result<Session, SessionError> {
+        let now = Utc::now();
+        let cookie = req
+            .cookie(&self.cookie_name)
+            .map(|cookie| cookie.value().to_string());
+        let record = match &cookie {
+            Some(cookie) => self.store.load(cookie).await?,
+            None => None,
+        };
+        Ok(match record {
+            Some(record) if !self.is_expired(&record, now) => {
+                let id = record.id.clone();
+                Session::new(record, Some(id))
+            }
+            Some(record) => {
+                self.store.destroy(&record.id).await?;
+                Session::new(SessionRecord::new(), None)
+            }
+            None => Session::new(SessionRecord::new(), None),
+        })
+    }
+
+    async fn save(&self, session: &Session, res: &mut Response) -> Result<(), SessionError> {
+        let now = Utc::now();
+        let (mut record, stored_id) = {
+            let state = session.state();
+            (state.record.clone(), state.stored_id.clone())
+        };
+        // A regenerated or invalidated session leaves its old id behind.
+        if let Some(stored_id) = &stored_id
+            && *stored_id != record.id
+        {
+            self.store.destroy(stored_id).await?;
+        }
+
+        if record.data.is_empty() {
+            // Nothing worth a cookie; one left from before is removed.
+            if stored_id.is_some() {
+                self.store.destroy(&record.id).await?;
+                let mut cookie = self.cookie(String::new(), Duration::zero());
+                cookie.make_removal();
+                res.add_cookie(cookie);
+            }
+        } else {
+            record.last_activity = now;
+            let expires_at = self.expires_at(&record, now);
+            let value = self.store.save(&record, expires_at).await?;
+            res.add_cookie(self.cookie(value, expires_at - now));
+        }
+
+        let (chance, out_of) = self.gc_lottery;
+        // Swept in the background so the winner's response doesn't wait for it; a
+        // failed sweep is left to the next winner.
+        if OsRng.next_u32() % out_of < chance {
+            let store = self.store.clone();
+            tokio::spawn(async move {
+                if let Err(error) = store.gc(now).await {
+                    tracing::warn!("session garbage collection failed: {}", error);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn cookie(&self, value: String, max_age: Duration) -> Cookie<'static> {
+        Cookie::build((self.cookie_name.clone(), value))
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .max_age(salvo::http::cookie::time::Duration::seconds(
+                max_age.num_seconds(),
+            ))
+            .build()
+    }
+}
+
+#[async_trait]
+impl Handler for SessionHandler {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let session = match self.load(req).await {
+            Ok(session) => session,
+            Err(error) => {
+                res.render(StatusError::internal_server_error().brief(error.to_string()));
+                ctrl.skip_rest();
+                return;
+            }
+        };
+        req.extensions_mut().insert(session.clone());
+        depot.inject(session.clone());
+
+        ctrl.call_next(req, depot, res).await;
+
+        if let Err(error) = self.save(&session, res).await {
+            res.render(StatusError::internal_server_error().brief(error.to_string()));
+        }
+    }
+}
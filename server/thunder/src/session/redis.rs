@@ -0,0 +1,91 @@
+use chrono::{DateTime, Utc};
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use salvo::async_trait;
+
+use super::store::SessionStore;
+use super::{SessionError, SessionRecord, is_valid_id};
+
+// Sessions in Redis or anything that speaks its protocol (Valkey, KeyDB, Dragonfly).
+// Every session is a key that expires on its own, so there is no collection to run.
+#[derive(Clone)]
+pub struct RedisSessionStore {
+    connection: ConnectionManager,
+    prefix: String,
+}
+
+impl std::fmt::Debug for RedisSessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisSessionStore")
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisSessionStore {
+    // Connects to e.g. `redis://127.0.0.1:6379/0`; the connection is re-established
+    // when it drops.
+    pub async fn connect(url: &str) -> Result<Self, SessionError> {
+        let client = redis::Client::open(url).map_err(redis_failed)?;
+        let connection = ConnectionManager::new(client).await.map_err(redis_failed)?;
+        Ok(RedisSessionStore {
+            connection,
+            prefix: "thunder:session:".to_string(),
+        })
+    }
+
+    // Prepended to the session ids to make the keys, `thunder:session:` by default.
+    pub fn prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}{}", self.prefix, id)
+    }
+}
+
+fn redis_failed(error: redis::RedisError) -> SessionError {
+    SessionError::Store(error.to_string())
+}
+
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn load(&self, cookie: &str) -> Result<Option<SessionRecord>, SessionError> {
+        if !is_valid_id(cookie) {
+            return Ok(None);
+        }
+        let mut connection = self.connection.clone();
+        let value: Option<String> = connection
+            .get(self.key(cookie))
+            .await
+            .map_err(redis_failed)?;
+        Ok(value.and_then(|value| serde_json::from_str(&value).ok()))
+    }
+
+    async fn save(
+        &self,
+        record: &SessionRecord,
+        expires_at: DateTime<Utc>,
+    ) -> Result<String, SessionError> {
+        let value = serde_json::to_string(record)
+            .map_err(|error| SessionError::Encode(error.to_string()))?;
+        let ttl = (expires_at - Utc::now()).num_milliseconds().max(1) as u64;
+        let mut connection = self.connection.clone();
+        let _: () = redis::cmd("SET")
+            .arg(self.key(&record.id))
+            .arg(value)
+            .arg("PX")
+            .arg(ttl)
+            .query_async(&mut connection)
+            .await
+            .map_err(redis_failed)?;
+        Ok(record.id.clone())
+    }
+
+    async fn destroy(&self, id: &str) -> Result<(), SessionError> {
+        let mut connection = self.connection.clone();
+        let _: () = connection.del(self.key(id)).await.map_err(redis_failed)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use salvo::async_trait;
+use serde::{Deserialize, Serialize};
+
+use super::{SessionError, SessionRecord, is_valid_id};
+
+// Where sessions live between requests. `save` returns what goes in the cookie:
+// the id for the server-side stores, the whole session for `CookieSessionStore`.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    // The session a cookie points to, unless it is unknown or expired.
+    async fn load(&self, cookie: &str) -> Result<Option<SessionRecord>, SessionError>;
+
+    // Keeps `record` until `expires_at` and returns the cookie value.
+    async fn save(
+        &self,
+        record: &SessionRecord,
+        expires_at: DateTime<Utc>,
+    ) -> Result<String, SessionError>;
+
+    // Forgets the session `id`; a missing one is not an error.
+    async fn destroy(&self, id: &str) -> Result<(), SessionError>;
+
+    // Drops the sessions that expired before `now`. Stores that expire entries on
+    // their own have nothing to do.
+    async fn gc(&self, now: DateTime<Utc>) -> Result<(), SessionError> {
+        let _ = now;
+        Ok(())
+    }
+}
+
+// A session as the server-side stores keep it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Entry {
+    pub record: SessionRecord,
+    pub expires_at: DateTime<Utc>,
+}
+
+// Sessions in the memory of the process: lost on restart and not shared between
+// instances, which is fine for development and tests.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySessionStore {
+    entries: Arc<RwLock<HashMap<String, Entry>>>,
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn load(&self, cookie: &str) -> Result<Option<SessionRecord>, SessionError> {
+        let entries = self
+            .entries
+            .read()
+            .unwrap_or_else(|error| error.into_inner());
+        Ok(entries
+            .get(cookie)
+            .filter(|entry| entry.expires_at > Utc::now())
+            .map(|entry| entry.record.clone()))
+    }
+
+    async fn save(
+        &self,
+        record: &SessionRecord,
+        expires_at: DateTime<Utc>,
+    ) -> Result<String, SessionError> {
+        let mut entries = self
+            .entries
+            .write()
+            .unwrap_or_else(|error| error.into_inner());
+        entries.insert(
+            record.id.clone(),
+            Entry {
+                record: record.clone(),
+                expires_at,
+            },
+        );
+        Ok(record.id.clone())
+    }
+
+    async fn destroy(&self, id: &str) -> Result<(), SessionError> {
+        let mut entries = self
+            .entries
+            .write()
+            .unwrap_or_else(|error| error.into_inner());
+        entries.remove(id);
+        Ok(())
+    }
+
+    async fn gc(&self, now: DateTime<Utc>) -> Result<(), SessionError> {
+        let mut entries = self
+            .entries
+            .write()
+            .unwrap_or_else(|error| error.into_inner());
+        entries.retain(|_, entry| entry.expires_at > now);
+        Ok(())
+    }
+}
+
+// Every session as a `<id>.json` file in a directory.
+#[derive(Debug, Clone)]
+pub struct FileSessionStore {
+    dir: PathBuf,
+}
+
+impl FileSessionStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileSessionStore { dir: dir.into() }
+    }
+
+    async fn read(&self, id: &str) -> Result<Option<Entry>, SessionError> {
+        match tokio::fs::read(self.dir.join(format!("{}.json", id))).await {
+            Ok(contents) => Ok(serde_json::from_slice(&contents).ok()),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(SessionError::Store(error.to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for FileSessionStore {
+    async fn load(&self, cookie: &str) -> Result<Option<SessionRecord>, SessionError> {
+        // The cookie comes from the client; only ids this store made reach the disk.
+        if !is_valid_id(cookie) {
+            return Ok(None);
+        }
+        Ok(self
+            .read(cookie)
+            .await?
+            .filter(|entry| entry.expires_at > Utc::now())
+            .map(|entry| entry.record))
+    }
+
+    async fn save(
+        &self,
+        record: &SessionRecord,
+        expires_at: DateTime<Utc>,
+    ) -> Result<String, SessionError> {
+        let entry = Entry {
+            record: record.clone(),
+            expires_at,
+        };
+        let contents =
+            serde_json::to_vec(&entry).map_err(|error| SessionError::Encode(error.to_string()))?;
+        let path = self.dir.join(format!("{}.json", record.id));
+        let partial = path.with_extension("json.tmp");
+        async {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            tokio::fs::write(&partial, contents).await?;
+            tokio::fs::rename(&partial, &path).await
+        }
+        .await
+        .map_err(|error| SessionError::Store(error.to_string()))?;
+        Ok(record.id.clone())
+    }
+
+    async fn destroy(&self, id: &str) -> Result<(), SessionError> {
+        if !is_valid_id(id) {
+            return Ok(());
+        }
+        match tokio::fs::remove_file(self.dir.join(format!("{}.json", id))).await {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                Err(SessionError::Store(error.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn gc(&self, now: DateTime<Utc>) -> Result<(), SessionError> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(error) => return Err(SessionError::Store(error.to_string())),
+        };
+        while let Some(file) = entries
+            .next_entry()
+            .await
+            .map_err(|error| SessionError::Store(error.to_string()))?
+        {
+            let path = file.path();
+            let Some(id) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".json"))
+            else {
+                continue;
+            };
+            // Unreadable files are leftovers too.
+            let expired = self
+                .read(id)
+                .await?
+                .is_none_or(|entry| entry.expires_at <= now);
+            if expired {
+                self.destroy(id).await?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,344 @@
+// Signed URLs: links that work only as handed out, optionally until a deadline and
+// only from one IP. The signature is an HMAC-SHA256 of the path and query with the
+// app key, so the links survive a change of host or a proxy in front of the app.
+//
+//     /files/report.pdf?expires=1760000000&signature=4f1c...
+use std::fmt::{self, Display, Formatter};
+use std::net::IpAddr;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{DateTime, Duration, Utc};
+use hmac::{Hmac, Mac};
+use salvo::http::StatusError;
+use salvo::{Depot, FlowCtrl, Handler, Request, Response, async_trait};
+use sha2::Sha256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    // No `signature` parameter, or not one this key made for this URL.
+    Invalid,
+    Expired,
+}
+
+impl Display for SignatureError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Invalid => write!(f, "Invalid signature."),
+            SignatureError::Expired => write!(f, "The link has expired."),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AppKeyError {
+    // Not set, or no key at all, like `APP_KEY=` or `APP_KEY=base64:`.
+    Missing,
+    // `base64:` followed by something that doesn't decode.
+    InvalidBase64,
+}
+
+impl Display for AppKeyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AppKeyError::Missing => write!(f, "APP_KEY is not set or empty."),
+            AppKeyError::InvalidBase64 => write!(f, "APP_KEY is not valid base64."),
+        }
+    }
+}
+
+impl std::error::Error for AppKeyError {}
+
+#[derive(Clone)]
+pub struct UrlSigner {
+    key: Vec<u8>,
+}
+
+// The key is never printed.
+impl fmt::Debug for UrlSigner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UrlSigner").finish_non_exhaustive()
+    }
+}
+
+impl UrlSigner {
+    pub fn new(key: impl AsRef<[u8]>) -> Self {
+        UrlSigner {
+            key: key.as_ref().to_vec(),
+        }
+    }
+
+    // The app key from `APP_KEY`, either raw or as `base64:...`.
+    pub fn from_env() -> Result<Self, AppKeyError> {
+        let key = std::env::var("APP_KEY").map_err(|_| AppKeyError::Missing)?;
+        UrlSigner::from_app_key(&key)
+    }
+
+    fn from_app_key(key: &str) -> Result<Self, AppKeyError> {
+        let key = match key.strip_prefix("base64:") {
+            Some(encoded) => STANDARD
+                .decode(encoded)
+                .map_err(|_| AppKeyError::InvalidBase64)?,
+            None => key.as_bytes().to_vec(),
+        };
+        // Anyone could sign URLs with an empty key.
+        if key.is_empty() {
+            return Err(AppKeyError::Missing);
+        }
+        Ok(UrlSigner::new(key))
+    }
+
+    // A link that never expires, e.g. for an unsubscribe link in a newsletter.
+    pub fn sign(&self, url: &str) -> String {
+        self.sign_with(url, None, None)
+    }
+
+    // A link that stops working after `ttl`.
+    pub fn temporary(&self, url: &str, ttl: Duration) -> String {
+        self.sign_with(url, Some(Utc::now() + ttl), None)
+    }
+
+    // `url`, absolute or just a path, with the signature parameters appended. A link
+    // bound to `ip` is refused from anywhere else; the IP is signed but not written
+    // in the link.
+    pub fn sign_with(
+        &self,
+        url: &str,
+        expires_at: Option<DateTime<Utc>>,
+        ip: Option<IpAddr>,
+    ) -> String {
+        // The parameters go before any `#fragment`, which the browser never sends.
+        let (url, fragment) = match url.split_once('#') {
+            Some((url, fragment)) => (url, format!("#{}", fragment)),
+            None => (url, String::new()),
+        };
+        let mut url = url.to_string();
+        let mut separator = if url.contains('?') { '&' } else { '?' };
+        if let Some(expires_at) = expires_at {
+            url.push_str(&format!("{}expires={}", separator, expires_at.timestamp()));
+            separator = '&';
+        }
+        if ip.is_some() {
+            url.push_str(&format!("{}bind=ip", separator));
+            separator = '&';
+        }
+        let signature = hex::encode(self.mac(path_and_query(&url), ip).finalize().into_bytes());
+        format!("{}{}signature={}{}", url, separator, signature, fragment)
+    }
+
+    // Checks the path and query of a request as received, e.g. `req.uri()`'s, and
+    // the IP it came from.
+    pub fn verify(&self, path_and_query: &str, ip: Option<IpAddr>) -> Result<(), SignatureError> {
+        let (path, query) = path_and_query
+            .split_once('?')
+            .ok_or(SignatureError::Invalid)?;
+        let mut signature = None;
+        let mut expires = None;
+        let mut bound = false;
+        let mut signed = Vec::new();
+        for pair in query.split('&') {
+            match pair.split_once('=') {
+                Some(("signature", value)) => signature = Some(value),
+                Some(("expires", value)) => {
+                    expires = Some(value.parse::<i64>().map_err(|_| SignatureError::Invalid)?);
+                    signed.push(pair);
+                }
+                Some(("bind", "ip")) => {
+                    bound = true;
+                    signed.push(pair);
+                }
+                _ => signed.push(pair),
+            }
+        }
+        let signature = signature
+            .and_then(|signature| hex::decode(signature).ok())
+            .ok_or(SignatureError::Invalid)?;
+
+        let mut url = path.to_string();
+        if !signed.is_empty() {
+            url = format!("{}?{}", url, signed.join("&"));
+        }
+        let ip = if bound {
+            Some(ip.ok_or(SignatureError::Invalid)?)
+        } else {
+            None
+        };
+        // Compared in constant time.
+        self.mac(&url, ip)
+            .verify_slice(&signature)
+            .map_err(|_| SignatureError::Invalid)?;
+
+        // Checked after the signature, so a forged `expires` is just invalid.
+        if expires.is_some_and(|expires| expires <= Utc::now().timestamp()) {
+            return Err(SignatureError::Expired);
+        }
+        Ok(())
+    }
+
+    fn mac(&self, path_and_query: &str, ip: Option<IpAddr>) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any size");
+        mac.update(path_and_query.as_bytes());
+        if let Some(ip) = ip {
+            mac.update(b"\n");
+            mac.update(ip.to_string().as_bytes());
+        }
+        mac
+    }
+}
+
+// `https://example.com/a?b=c` -> `/a?b=c`.
+fn path_and_query(url: &str) -> &str {
+    match url.split_once("://") {
+        Some((_, rest)) => rest.find('/').map_or("/", |start| &rest[start..]),
+        None => url,
+    }
+}
+
+// Hoop for routes reached through signed links; anything else gets a 403.
+//
+//     Router::with_path("unsubscribe/{id}")
+//         .hoop(ValidateSignature::new(signer))
+//         .get(unsubscribe)
+#[derive(Debug, Clone)]
+pub struct ValidateSignature {
+    signer: UrlSigner,
+}
+
+impl ValidateSignature {
+    pub fn new(signer: UrlSigner) -> Self {
+        ValidateSignature { signer }
+    }
+}
+
+#[async_trait]
+impl Handler for ValidateSignature {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let path_and_query = req
+            .uri()
+            .path_and_query()
+            .map_or("/", |path_and_query| path_and_query.as_str());
+        let ip = req.remote_addr().clone().into_std().map(|addr| addr.ip());
+        if let Err(error) = self.signer.verify(path_and_query, ip) {
+            res.render(StatusError::forbidden().brief(error.to_string()));
+            ctrl.skip_rest();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer() -> UrlSigner {
+        UrlSigner::new("test-key")
+    }
+
+    #[test]
+    fn verifies_a_signed_url() {
+        let url = signer().sign("https://example.com/files/report.pdf?page=2");
+        let path_and_query = path_and_query(&url);
+        assert!(path_and_query.starts_with("/files/report.pdf?page=2&signature="));
+        assert_eq!(signer().verify(path_and_query, None), Ok(()));
+    }
+
+    #[test]
+    fn keeps_the_fragment_after_the_signature() {
+        let url = signer().sign("/files/report.pdf#page=2");
+        let (url, fragment) = url.split_once('#').unwrap();
+        assert_eq!(fragment, "page=2");
+        assert_eq!(signer().verify(url, None), Ok(()));
+    }
+
+    #[test]
+    fn refuses_a_tampered_url() {
+        let url = signer().sign("/files/report.pdf?user=1");
+        let tampered = url.replace("user=1", "user=2");
+        assert_eq!(
+            signer().verify(&tampered, None),
+            Err(SignatureError::Invalid)
+        );
+
+        let added = format!("{}&user=2", url);
+        assert_eq!(signer().verify(&added, None), Err(SignatureError::Invalid));
+
+        let unsigned = url.split_once("&signature=").unwrap().0;
+        assert_eq!(
+            signer().verify(unsigned, None),
+            Err(SignatureError::Invalid)
+        );
+
+        let other_key = UrlSigner::new("other-key");
+        assert_eq!(other_key.verify(&url, None), Err(SignatureError::Invalid));
+    }
+
+    #[test]
+    fn expires_a_temporary_url() {
+        let url = signer().temporary("/files/report.pdf", Duration::minutes(5));
+        assert_eq!(signer().verify(&url, None), Ok(()));
+
+        let url = signer().temporary("/files/report.pdf", Duration::minutes(-5));
+        assert_eq!(signer().verify(&url, None), Err(SignatureError::Expired));
+    }
+
+    #[test]
+    fn refuses_a_pushed_back_deadline() {
+        let url = signer().temporary("/files/report.pdf", Duration::minutes(-5));
+        let expires = url
+            .split("expires=")
+            .nth(1)
+            .unwrap()
+            .split('&')
+            .next()
+            .unwrap();
+        let later = url.replace(&format!("expires={}", expires), "expires=99999999999");
+        assert_eq!(signer().verify(&later, None), Err(SignatureError::Invalid));
+    }
+
+    #[test]
+    fn binds_a_url_to_an_ip() {
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let url = signer().sign_with("/files/report.pdf", None, Some(ip));
+        assert!(!url.contains("203.0.113.7"));
+        assert_eq!(signer().verify(&url, Some(ip)), Ok(()));
+
+        let other: IpAddr = "203.0.113.8".parse().unwrap();
+        assert_eq!(
+            signer().verify(&url, Some(other)),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(signer().verify(&url, None), Err(SignatureError::Invalid));
+    }
+
+    #[test]
+    fn reads_the_app_key() {
+        let raw = UrlSigner::from_app_key("base64:dGVzdC1rZXk=").unwrap();
+        let url = raw.sign("/files/report.pdf");
+        assert_eq!(signer().verify(&url, None), Ok(()));
+
+        assert_eq!(
+            UrlSigner::from_app_key("base64:not base64!").unwrap_err(),
+            AppKeyError::InvalidBase64
+        );
+    }
+
+    #[test]
+    fn refuses_an_empty_app_key() {
+        assert_eq!(
+            UrlSigner::from_app_key("").unwrap_err(),
+            AppKeyError::Missing
+        );
+        assert_eq!(
+            UrlSigner::from_app_key("base64:").unwrap_err(),
+            AppKeyError::Missing
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use chrono::Duration;
+use futures_util::TryStreamExt;
+use salvo::async_trait;
+use tokio::io::AsyncWriteExt;
+
+use super::{ByteStream, Storage, StorageError, check_key, file_stream};
+use crate::signing::UrlSigner;
+
+// Files under a directory of the server, e.g. `storage/app/public`, served from
+// `base_url` by a static route. A private disk is served by `ServeDisk` behind
+// `ValidateSignature` instead, and hands out links with `temporary_url`.
+#[derive(Debug, Clone)]
+pub struct LocalDisk {
+    root: PathBuf,
+    base_url: String,
+    signer: Option<UrlSigner>,
+}
+
+impl LocalDisk {
+    pub fn new(root: impl Into<PathBuf>, base_url: impl Into<String>) -> Self {
+        LocalDisk {
+            root: root.into(),
+            base_url: base_url.into(),
+            signer: None,
+        }
+    }
+
+    // Signs the links of `temporary_url` with `signer`.
+    pub fn signed(mut self, signer: UrlSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    // Where `key` lives on disk.
+    pub fn path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        check_key(key)?;
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalDisk {
+    async fn put(
+        &self,
+        key: &str,
+        contents: Vec<u8>,
+        _content_type: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, contents).await?;
+        Ok(())
+    }
+
+    async fn put_stream(
+        &self,
+        key: &str,
+        mut contents: ByteStream<'_>,
+        _content_type: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // Written aside and renamed once complete, so readers never see half a file and
+        // a broken upload leaves the previous one in place.
+        let partial = path.with_file_name(format!(
+            ".{}.{}.tmp",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            uuid::Uuid::new_v4()
+        ));
+        let written = async {
+            let mut file = tokio::fs::File::create(&partial).await?;
+            while let Some(chunk) = contents.try_next().await? {
+                file.write_all(&chunk).await?;
+            }
+            file.flush().await?;
+            tokio::fs::rename(&partial, &path).await?;
+            Ok::<_, StorageError>(())
+        }
+        .await;
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(&partial).await;
+        }
+        written
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(contents) => Ok(contents),
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                Err(StorageError::NotFound(key.to_string()))
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn get_stream(&self, key: &str) -> Result<ByteStream<'static>, StorageError> {
+        match tokio::fs::File::open(self.path(key)?).await {
+            Ok(file) => Ok(file_stream(file)),
+            Err(error) if error.kind() == ErrorKind::NotFound => {
+                Err(StorageError::NotFound(key.to_string()))
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, StorageError> {
+        Ok(tokio::fs::try_exists(self.path(key)?).await?)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url.trim_end_matches('/'), key)
+    }
+
+    fn temporary_url(&self, key: &str, ttl: Duration) -> Result<String, StorageError> {
+        check_key(key)?;
+        let signer = self.signer.as_ref().ok_or_else(|| {
+            StorageError::Unsupported("temporary URLs without a signer".to_string())
+        })?;
+        Ok(signer.temporary(&self.url(key), ttl))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures_util::stream;
+
+    use super::*;
+
+    fn chunks(chunks: Vec<Result<&'static [u8], StorageError>>) -> ByteStream<'static> {
+        Box::pin(stream::iter(
+            chunks
+                .into_iter()
+                .map(|chunk| chunk.map(Bytes::from_static)),
+        ))
+    }
+
+    fn files_in(dir: &std::path::Path) -> Vec<String> {
+        let mut names: Vec<_> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn streams_a_file_in() {
+        let root = std::env::temp_dir().join(format!("thunder-local-{}", uuid::Uuid::new_v4()));
+        let disk = LocalDisk::new(&root, "/storage");
+
+        disk.put_stream(
+            "videos/a.txt",
+            chunks(vec![Ok(b"hello "), Ok(b"world")]),
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(disk.get("videos/a.txt").await.unwrap(), b"hello world");
+        assert_eq!(files_in(&root.join("videos")), ["a.txt"]);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_previous_file_when_a_stream_breaks() {
+        let root = std::env::temp_dir().join(format!("thunder-local-{}", uuid::Uuid::new_v4()));
+        let disk = LocalDisk::new(&root, "/storage");
+        disk.put("videos/a.txt", b"first".to_vec(), None)
+            .await
+            .unwrap();
+
+        let broken = chunks(vec![
+            Ok(b"sec"),
+            Err(StorageError::Remote("connection reset".to_string())),
+        ]);
+        assert!(matches!(
+            disk.put_stream("videos/a.txt", broken, None).await,
+            Err(StorageError::Remote(_))
+        ));
+        assert_eq!(disk.get("videos/a.txt").await.unwrap(), b"first");
+        assert_eq!(files_in(&root.join("videos")), ["a.txt"]);
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn refuses_keys_outside_the_root() {
+        let disk = LocalDisk::new(std::env::temp_dir().join("thunder-local"), "/storage");
+        for key in ["", "/etc/passwd", "../secret", "a/../../b", "a\\b"] {
+            assert!(matches!(
+                disk.put_stream(key, chunks(vec![Ok(b"x")]), None).await,
+                Err(StorageError::InvalidKey(_))
+            ));
+        }
+        assert_eq!(disk.url("videos/a.txt"), "/storage/videos/a.txt");
+    }
+}
@@ -0,0 +1,161 @@
+// Persistent storage for uploads. `UploadedFile` only points at the temporary file
+// Salvo wrote the part to, which is gone after the request; `store` copies it to a
+// `Storage` driver and returns where it ended up.
+use std::fmt::{self, Display, Formatter};
+use std::pin::Pin;
+
+use bytes::Bytes;
+use chrono::Duration;
+use futures_util::{Stream, TryStreamExt};
+use salvo::async_trait;
+use salvo::oapi::ToSchema;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+
+mod local;
+mod s3;
+mod serve;
+
+pub use local::LocalDisk;
+pub use s3::{S3Config, S3Disk};
+pub use serve::ServeDisk;
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(std::io::Error),
+    // The key is empty, absolute or climbs out of the disk with `..`.
+    InvalidKey(String),
+    NotFound(String),
+    // The remote driver answered with an error or could not be reached.
+    Remote(String),
+    // The driver cannot do this, e.g. a temporary URL without a signer.
+    Unsupported(String),
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Io(error) => write!(f, "storage I/O error: {}", error),
+            StorageError::InvalidKey(key) => write!(f, "invalid storage key `{}`", key),
+            StorageError::NotFound(key) => write!(f, "no file stored under `{}`", key),
+            StorageError::Remote(message) => write!(f, "storage driver error: {}", message),
+            StorageError::Unsupported(what) => write!(f, "the disk does not support {}", what),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<std::io::Error> for StorageError {
+    fn from(error: std::io::Error) -> Self {
+        StorageError::Io(error)
+    }
+}
+
+// What `UploadedFile::store` hands back: enough to save in a model and to link to.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct StoredFile {
+    // The stable key inside the disk, e.g. `avatars/3f2a...c9.png`.
+    pub key: String,
+    pub url: String,
+    // Hex SHA-256 of the contents.
+    pub hash: String,
+    pub size: u64,
+    pub content_type: Option<String>,
+}
+
+// The chunks of a file on its way into a disk, e.g. a multipart part as it arrives.
+pub type ByteStream<'a> = Pin<Box<dyn Stream<Item = Result<Bytes, StorageError>> + Send + 'a>>;
+
+// A place files can be written to and read back from by key. Keys are relative,
+// `/`-separated paths such as `avatars/photo.png`.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(
+        &self,
+        key: &str,
+        contents: Vec<u8>,
+        content_type: Option<&str>,
+    ) -> Result<(), StorageError>;
+
+    // Writes the chunks as they come instead of holding the whole file in memory.
+    // Drivers that cannot stream collect the chunks and `put` them.
+    async fn put_stream(
+        &self,
+        key: &str,
+        mut contents: ByteStream<'_>,
+        content_type: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let mut buffer = Vec::new();
+        while let Some(chunk) = contents.try_next().await? {
+            buffer.extend_from_slice(&chunk);
+        }
+        self.put(key, buffer, content_type).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError>;
+
+    // Reads the file in chunks instead of holding it in memory, e.g. to send it back.
+    // Drivers that cannot stream `get` the whole file as one chunk.
+    async fn get_stream(&self, key: &str) -> Result<ByteStream<'static>, StorageError> {
+        let contents = self.get(key).await?;
+        Ok(Box::pin(futures_util::stream::once(async move {
+            Ok(Bytes::from(contents))
+        })))
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool, StorageError>;
+
+    // Deleting a missing key is not an error.
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+
+    // The public URL of `key`; it does not check that the file exists.
+    fn url(&self, key: &str) -> String;
+
+    // A URL that gives access to `key` for `ttl`, for files that are not public.
+    fn temporary_url(&self, key: &str, ttl: Duration) -> Result<String, StorageError> {
+        let _ = (key, ttl);
+        Err(StorageError::Unsupported("temporary URLs".to_string()))
+    }
+}
+
+// Refuses keys that could escape the root of a disk.
+pub(crate) fn check_key(key: &str) -> Result<(), StorageError> {
+    let is_valid = !key.is_empty()
+        && !key.starts_with('/')
+        && !key.contains('\\')
+        && key
+            .split('/')
+            .all(|segment| !segment.is_empty() && segment != "." && segment != "..");
+    if is_valid {
+        Ok(())
+    } else {
+        Err(StorageError::InvalidKey(key.to_string()))
+    }
+}
+
+// Reads `file` in chunks of 64 KiB.
+pub(crate) fn file_stream(file: tokio::fs::File) -> ByteStream<'static> {
+    Box::pin(futures_util::stream::try_unfold(
+        file,
+        |mut file| async move {
+            let mut buffer = vec![0; 64 * 1024];
+            let read = file.read(&mut buffer).await?;
+            if read == 0 {
+                return Ok(None);
+            }
+            buffer.truncate(read);
+            Ok(Some((Bytes::from(buffer), file)))
+        },
+    ))
+}
+
+// `dir` and `name` joined with a single `/`; an empty `dir` stores at the root.
+pub(crate) fn join_key(dir: &str, name: &str) -> String {
+    let dir = dir.trim_matches('/');
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
@@ -0,0 +1,178 @@
+use std::sync::Arc;
+
+use futures_util::{StreamExt, TryStreamExt, stream};
+use salvo::http::header::{
+    CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE, X_CONTENT_TYPE_OPTIONS,
+};
+use salvo::http::{HeaderValue, StatusError};
+use salvo::{Depot, FlowCtrl, Handler, Request, Response, async_trait};
+
+use super::{Storage, StorageError};
+
+// Types a browser may show in the page. Anything else, HTML and SVG included, is
+// downloaded, so an uploaded file can't run script on the app's origin.
+const INLINE_TYPES: &[&str] = &[
+    "application/pdf",
+    "audio/mpeg",
+    "audio/ogg",
+    "audio/wav",
+    "image/avif",
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+    "text/plain",
+    "video/mp4",
+    "video/webm",
+];
+
+// Serves the files of a disk from a `{**key}` route, e.g. a private `LocalDisk`
+// behind `ValidateSignature`:
+//
+//     Router::with_path("private/{**key}")
+//         .hoop(ValidateSignature::new(signer))
+//         .get(ServeDisk::new(disk))
+#[derive(Clone)]
+pub struct ServeDisk {
+    disk: Arc<dyn Storage>,
+}
+
+impl ServeDisk {
+    pub fn new(disk: impl Storage + 'static) -> Self {
+        ServeDisk {
+            disk: Arc::new(disk),
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for ServeDisk {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+        _ctrl: &mut FlowCtrl,
+    ) {
+        let key = req.param::<String>("key").unwrap_or_default();
+        // The first chunk is read ahead to sniff the type; the rest follows as it is read.
+        let opened = async {
+            let mut contents = self.disk.get_stream(&key).await?;
+            let first = contents.try_next().await?.unwrap_or_default();
+            Ok::<_, StorageError>((first, contents))
+        }
+        .await;
+        match opened {
+            Ok((first, rest)) => {
+                // Sniffed from the content, or guessed from the extension for text.
+                let content_type = match infer::get(&first) {
+                    Some(kind) => kind.mime_type().to_string(),
+                    None => mime_infer::from_path(&key)
+                        .first_or_octet_stream()
+                        .to_string(),
+                };
+                let headers = res.headers_mut();
+                if let Ok(value) = HeaderValue::from_str(&content_type) {
+                    headers.insert(CONTENT_TYPE, value);
+                }
+                headers.insert(X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+                if !INLINE_TYPES.contains(&content_type.as_str()) {
+                    headers.insert(CONTENT_DISPOSITION, HeaderValue::from_static("attachment"));
+                }
+                // The links are meant for one person; shared caches keep out.
+                headers.insert(CACHE_CONTROL, HeaderValue::from_static("private"));
+                res.stream(stream::once(async { Ok(first) }).chain(rest));
+            }
+            Err(StorageError::NotFound(_) | StorageError::InvalidKey(_)) => {
+                res.render(StatusError::not_found());
+            }
+            Err(error) => {
+                res.render(StatusError::internal_server_error().brief(error.to_string()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo::http::StatusCode;
+    use salvo::test::{ResponseExt, TestClient};
+    use salvo::{Router, Service};
+
+    use super::*;
+    use crate::LocalDisk;
+
+    async fn service(root: &std::path::Path) -> Service {
+        let disk = LocalDisk::new(root, "/private");
+        // A PNG signature followed by more than one chunk of data.
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.resize(200 * 1024, 7);
+        disk.put("photos/a.png", png, None).await.unwrap();
+        disk.put("pages/a.html", b"<script>alert(1)</script>".to_vec(), None)
+            .await
+            .unwrap();
+        Service::new(Router::with_path("private/{**key}").get(ServeDisk::new(disk)))
+    }
+
+    fn header<'a>(res: &'a Response, name: &str) -> &'a str {
+        res.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn streams_the_whole_file() {
+        let root = std::env::temp_dir().join(format!("thunder-serve-{}", uuid::Uuid::new_v4()));
+        let service = service(&root).await;
+
+        let mut res = TestClient::get("http://localhost/private/photos/a.png")
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        assert_eq!(header(&res, "content-type"), "image/png");
+        assert_eq!(header(&res, "content-disposition"), "");
+        assert_eq!(header(&res, "cache-control"), "private");
+        let body = res.take_bytes(None).await.unwrap();
+        assert_eq!(body.len(), 200 * 1024);
+        assert!(body.starts_with(b"\x89PNG"));
+        assert!(body.ends_with(&[7, 7, 7]));
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn downloads_what_could_run_script() {
+        let root = std::env::temp_dir().join(format!("thunder-serve-{}", uuid::Uuid::new_v4()));
+        let service = service(&root).await;
+
+        let mut res = TestClient::get("http://localhost/private/pages/a.html")
+            .send(&service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        assert_eq!(header(&res, "content-type"), "text/html");
+        assert_eq!(header(&res, "content-disposition"), "attachment");
+        assert_eq!(header(&res, "x-content-type-options"), "nosniff");
+        assert_eq!(
+            res.take_string().await.unwrap(),
+            "<script>alert(1)</script>"
+        );
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn answers_404_to_missing_files_and_bad_keys() {
+        let root = std::env::temp_dir().join(format!("thunder-serve-{}", uuid::Uuid::new_v4()));
+        let service = service(&root).await;
+
+        for path in ["photos/missing.png", "photos%5Ca.png"] {
+            let res = TestClient::get(format!("http://localhost/private/{}", path))
+                .send(&service)
+                .await;
+            assert_eq!(res.status_code, Some(StatusCode::NOT_FOUND), "{}", path);
+        }
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,485 @@
+// Multipart bodies read part by part as they arrive, for uploads too big to buffer.
+// Nothing is written to temporary files: a file part is either read chunk by chunk
+// or piped straight into a `Storage` disk. The body is only pulled from the client
+// as fast as the parts are consumed.
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use futures_util::StreamExt;
+use multer::{Constraints, Field, Multipart, SizeLimit};
+use salvo::Request;
+use salvo::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use salvo::http::{Mime, StatusCode};
+use sha2::{Digest, Sha256};
+
+use crate::file::file_name_extension;
+use crate::storage::{Storage, StorageError, StoredFile, join_key};
+use crate::{ValidationError, ValidationErrors};
+
+// Size limits for `MultipartStream`, in bytes. Going over one answers with a 413.
+#[derive(Debug, Clone, Default)]
+pub struct MultipartLimits {
+    total: Option<u64>,
+    per_field: Option<u64>,
+    fields: HashMap<String, u64>,
+}
+
+impl MultipartLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The whole body, every part included.
+    pub fn total(mut self, limit: u64) -> Self {
+        self.total = Some(limit);
+        self
+    }
+
+    // Any single part without a limit of its own.
+    pub fn per_field(mut self, limit: u64) -> Self {
+        self.per_field = Some(limit);
+        self
+    }
+
+    // The parts named `name`, e.g. a `video` that may be larger than the rest.
+    pub fn field(mut self, name: impl Into<String>, limit: u64) -> Self {
+        self.fields.insert(name.into(), limit);
+        self
+    }
+
+    fn size_limit(&self) -> SizeLimit {
+        let mut size_limit = SizeLimit::new();
+        if let Some(total) = self.total {
+            size_limit = size_limit.whole_stream(total);
+        }
+        if let Some(per_field) = self.per_field {
+            size_limit = size_limit.per_field(per_field);
+        }
+        for (name, limit) in &self.fields {
+            size_limit = size_limit.for_field(name.clone(), *limit);
+        }
+        size_limit
+    }
+}
+
+// A `multipart/form-data` body taken out of the request and parsed lazily.
+pub struct MultipartStream {
+    multipart: Multipart<'static>,
+}
+
+impl MultipartStream {
+    // Takes the body of `req`. A body that is not multipart is a 400, and one whose
+    // `Content-Length` is already over the total limit a 413 before anything is read.
+    pub fn new(req: &mut Request, limits: MultipartLimits) -> Result<Self, ValidationErrors> {
+        let boundary = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| multer::parse_boundary(value).ok())
+            .ok_or_else(|| {
+                parse_failed("body", "Expected a multipart/form-data body.".to_string())
+            })?;
+
+        let length = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        if let (Some(length), Some(total)) = (length, limits.total)
+            && length > total
+        {
+            return Err(too_large("body", total));
+        }
+
+        let body = req
+            .take_body()
+            .map(|frame| frame.map(|frame| frame.into_data().unwrap_or_default()));
+        let constraints = Constraints::new().size_limit(limits.size_limit());
+        Ok(MultipartStream {
+            multipart: Multipart::with_constraints(body, boundary, constraints),
+        })
+    }
+
+    // The next part, once its headers have arrived. The previous part must have been
+    // consumed or dropped.
+    pub async fn next_part(&mut self) -> Result<Option<StreamPart>, ValidationErrors> {
+        match self.multipart.next_field().await {
+            Ok(field) => Ok(field.map(|field| StreamPart { field })),
+            Err(error) => Err(multipart_failed(error)),
+        }
+    }
+}
+
+// One part of a `MultipartStream`.
+pub struct StreamPart {
+    field: Field<'static>,
+}
+
+impl StreamPart {
+    pub fn name(&self) -> Option<&str> {
+        self.field.name()
+    }
+
+    // The client-side file name; only file parts have one.
+    pub fn file_name(&self) -> Option<&str> {
+        self.field.file_name()
+    }
+
+    pub fn content_type(&self) -> Option<&Mime> {
+        self.field.content_type()
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.field.file_name().is_some()
+    }
+
+    // The next chunk of the contents, `None` at the end of the part.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, ValidationErrors> {
+        self.field.chunk().await.map_err(multipart_failed)
+    }
+
+    // The whole part as text, for the small non-file fields.
+    pub async fn text(self) -> Result<String, ValidationErrors> {
+        self.field.text().await.map_err(multipart_failed)
+    }
+
+    /// Pipes the part into `dir` of `disk` under a random name that keeps the
+    /// extension of the client-side file name, if it is a plain one like `pdf`.
+    pub async fn store(
+        self,
+        dir: &str,
+        disk: &(impl Storage + ?Sized),
+    ) -> Result<StoredFile, ValidationErrors> {
+        let extension = self.file_name().and_then(file_name_extension);
+        let name = match extension {
+            Some(extension) => format!("{}.{}", uuid::Uuid::new_v4(), extension),
+            None => uuid::Uuid::new_v4().to_string(),
+        };
+        self.put(join_key(dir, &name), disk).await
+    }
+
+    /// Pipes the part into `dir` of `disk` under the given `name`.
+    pub async fn store_as(
+        self,
+        dir: &str,
+        name: &str,
+        disk: &(impl Storage + ?Sized),
+    ) -> Result<StoredFile, ValidationErrors> {
+        self.put(join_key(dir, name), disk).await
+    }
+
+    async fn put(
+        self,
+        key: String,
+        disk: &(impl Storage + ?Sized),
+    ) -> Result<StoredFile, ValidationErrors> {
+        let field_name = self.name().unwrap_or("body").to_string();
+        let content_type = self.content_type().map(Mime::to_string);
+
+        // The hash and size are worked out on the way through; a multipart error is
+        // kept aside so that a size limit still answers with a 413.
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        let mut multipart_error = None;
+        let stream = self.field.map(|chunk| match chunk {
+            Ok(bytes) => {
+                hasher.update(&bytes);
+                size += bytes.len() as u64;
+                Ok(bytes)
+            }
+            Err(error) => {
+                let message = error.to_string();
+                multipart_error = Some(error);
+                Err(StorageError::Remote(message))
+            }
+        });
+        let stored = disk
+            .put_stream(&key, Box::pin(stream), content_type.as_deref())
+            .await;
+
+        match (stored, multipart_error) {
+            (_, Some(error)) => Err(multipart_failed(error)),
+            (Err(error), None) => {
+                let mut errors = ValidationErrors::new();
+                errors.add_error(
+                    &field_name,
+                    ValidationError::new("storage", "The file could not be stored: {reason}.")
+                        .with_param("reason", error.to_string()),
+                );
+                errors.add_status_code(StatusCode::INTERNAL_SERVER_ERROR);
+                Err(errors)
+            }
+            (Ok(()), None) => Ok(StoredFile {
+                url: disk.url(&key),
+                key,
+                hash: hex::encode(hasher.finalize()),
+                size,
+                content_type,
+            }),
+        }
+    }
+}
+
+fn too_large(field_name: &str, limit: u64) -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    errors.add_error(
+        field_name,
+        ValidationError::new(
+            "too_large",
+            "The upload may not be greater than {limit} bytes.",
+        )
+        .with_param("limit", limit),
+    );
+    errors.add_status_code(StatusCode::PAYLOAD_TOO_LARGE);
+    errors
+}
+
+fn parse_failed(field_name: &str, message: String) -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    errors.add_error(field_name, ValidationError::new("parse", message));
+    errors.add_status_code(StatusCode::BAD_REQUEST);
+    errors
+}
+
+// Size limits are a 413 on the part, or on `body` for the total; anything else the
+// client sent wrong is a 400.
+fn multipart_failed(error: multer::Error) -> ValidationErrors {
+    match error {
+        multer::Error::FieldSizeExceeded { limit, field_name } => {
+            too_large(field_name.as_deref().unwrap_or("body"), limit)
+        }
+        multer::Error::StreamSizeExceeded { limit } => too_large("body", limit),
+        error => parse_failed("body", error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::task::{Context, Poll};
+
+    use salvo::BoxedError;
+    use salvo::http::ReqBody;
+    use salvo::http::body::{Body, Frame};
+    use salvo::test::TestClient;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::LocalDisk;
+
+    const BOUNDARY: &str = "X-THUNDER-BOUNDARY";
+
+    fn part(name: &str, file_name: Option<&str>, contents: &str) -> String {
+        let file_name = file_name
+            .map(|file_name| format!("; filename=\"{}\"", file_name))
+            .unwrap_or_default();
+        format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"{}\r\n\r\n{}\r\n",
+            BOUNDARY, name, file_name, contents
+        )
+    }
+
+    fn request(body: impl Into<ReqBody>) -> Request {
+        TestClient::post("http://127.0.0.1/upload")
+            .add_header(
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+                true,
+            )
+            .body(body)
+            .build()
+    }
+
+    fn body(parts: &[String]) -> String {
+        format!("{}--{}--\r\n", parts.concat(), BOUNDARY)
+    }
+
+    // A body fed through a channel, so the client can only send as fast as the
+    // server reads.
+    struct Channel(mpsc::Receiver<Bytes>);
+
+    impl Body for Channel {
+        type Data = Bytes;
+        type Error = BoxedError;
+
+        fn poll_frame(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Frame<Bytes>, BoxedError>>> {
+            self.0
+                .poll_recv(cx)
+                .map(|chunk| chunk.map(|chunk| Ok(Frame::data(chunk))))
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_the_parts_in_order() {
+        let mut req = request(body(&[
+            part("title", None, "Holidays"),
+            part("video", Some("beach.mp4"), "not really a video"),
+        ]));
+        let mut stream = MultipartStream::new(&mut req, MultipartLimits::new()).unwrap();
+
+        let title = stream.next_part().await.unwrap().unwrap();
+        assert_eq!(title.name(), Some("title"));
+        assert!(!title.is_file());
+        assert_eq!(title.text().await.unwrap(), "Holidays");
+
+        let mut video = stream.next_part().await.unwrap().unwrap();
+        assert_eq!(video.file_name(), Some("beach.mp4"));
+        assert_eq!(
+            video.chunk().await.unwrap().unwrap(),
+            Bytes::from_static(b"not really a video")
+        );
+        drop(video);
+        assert!(stream.next_part().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn refuses_a_body_announced_over_the_total() {
+        let mut req = request(body(&[part("title", None, "Holidays")]));
+        req.headers_mut()
+            .insert(CONTENT_LENGTH, "1000".parse().unwrap());
+        let errors = MultipartStream::new(&mut req, MultipartLimits::new().total(100))
+            .err()
+            .unwrap();
+        assert_eq!(errors.status_code(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+        assert_eq!(errors.first("body").unwrap().code, "too_large");
+        assert_eq!(errors.first("body").unwrap().params["limit"], 100);
+    }
+
+    #[tokio::test]
+    async fn answers_413_on_the_part_over_its_limit() {
+        let limits = MultipartLimits::new().per_field(8).field("video", 32);
+        let mut req = request(body(&[
+            part("video", Some("beach.mp4"), &"v".repeat(20)),
+            part("title", None, "A long holiday title"),
+        ]));
+        let mut stream = MultipartStream::new(&mut req, limits).unwrap();
+
+        // Its own limit lets the video through, the default one stops the title.
+        let video = stream.next_part().await.unwrap().unwrap();
+        assert_eq!(video.text().await.unwrap().len(), 20);
+        let title = stream.next_part().await.unwrap().unwrap();
+        let errors = title.text().await.unwrap_err();
+        assert_eq!(errors.status_code(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+        assert_eq!(errors.first("title").unwrap().code, "too_large");
+        assert_eq!(errors.first("title").unwrap().params["limit"], 8);
+    }
+
+    #[tokio::test]
+    async fn answers_413_over_the_total_while_reading() {
+        let mut req = request(body(&[part("video", Some("beach.mp4"), &"v".repeat(200))]));
+        let mut stream = MultipartStream::new(&mut req, MultipartLimits::new().total(100)).unwrap();
+
+        let errors = match stream.next_part().await {
+            Ok(Some(video)) => video.text().await.unwrap_err(),
+            Ok(None) => panic!("the video part is missing"),
+            Err(errors) => errors,
+        };
+        assert_eq!(errors.status_code(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+        assert_eq!(errors.first("body").unwrap().code, "too_large");
+    }
+
+    #[tokio::test]
+    async fn refuses_a_body_that_is_not_multipart() {
+        let mut req = TestClient::post("http://127.0.0.1/upload")
+            .json(&serde_json::json!({ "title": "Holidays" }))
+            .build();
+        let errors = MultipartStream::new(&mut req, MultipartLimits::new())
+            .err()
+            .unwrap();
+        assert_eq!(errors.status_code(), Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn pulls_the_body_only_as_the_parts_are_read() {
+        let (sender, receiver) = mpsc::channel(1);
+        let sent = Arc::new(AtomicUsize::new(0));
+        let client = tokio::spawn({
+            let sent = sent.clone();
+            async move {
+                let mut chunks = vec![
+                    part("title", None, "Holidays"),
+                    format!(
+                        "--{}\r\nContent-Disposition: form-data; name=\"video\"; \
+                         filename=\"beach.mp4\"\r\n\r\n",
+                        BOUNDARY
+                    ),
+                ];
+                chunks.extend((0..20).map(|_| "v".repeat(1024)));
+                chunks.push(format!("\r\n--{}--\r\n", BOUNDARY));
+                for chunk in chunks {
+                    sender.send(Bytes::from(chunk)).await.unwrap();
+                    sent.fetch_add(1, Ordering::SeqCst);
+                }
+            }
+        });
+        let mut req = request(ReqBody::Boxed {
+            inner: Box::pin(Channel(receiver)),
+            fusewire: None,
+        });
+        let mut stream = MultipartStream::new(&mut req, MultipartLimits::new()).unwrap();
+
+        let title = stream.next_part().await.unwrap().unwrap();
+        assert_eq!(title.text().await.unwrap(), "Holidays");
+        let mut video = stream.next_part().await.unwrap().unwrap();
+        let mut size = video.chunk().await.unwrap().unwrap().len();
+
+        // However long the client waits, it can't get far ahead of the reads.
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(sent.load(Ordering::SeqCst) < 8);
+
+        while let Some(chunk) = video.chunk().await.unwrap() {
+            size += chunk.len();
+        }
+        assert_eq!(size, 20 * 1024);
+        client.await.unwrap();
+        assert_eq!(sent.load(Ordering::SeqCst), 23);
+    }
+
+    #[tokio::test]
+    async fn stores_a_part_under_a_plain_extension() {
+        let root = std::env::temp_dir().join(format!("thunder-stream-{}", uuid::Uuid::new_v4()));
+        let disk = LocalDisk::new(&root, "/storage");
+        let mut req = request(body(&[
+            part("video", Some("beach.MP4"), "frames"),
+            part("script", Some("run.php%00.sh;x"), "echo"),
+        ]));
+        let mut stream = MultipartStream::new(&mut req, MultipartLimits::new()).unwrap();
+
+        let video = stream.next_part().await.unwrap().unwrap();
+        let stored = video.store("videos", &disk).await.unwrap();
+        assert!(stored.key.starts_with("videos/") && stored.key.ends_with(".mp4"));
+        assert_eq!(stored.url, format!("/storage/{}", stored.key));
+        assert_eq!(stored.size, 6);
+        assert_eq!(disk.get(&stored.key).await.unwrap(), b"frames");
+
+        let script = stream.next_part().await.unwrap().unwrap();
+        let stored = script.store("scripts", &disk).await.unwrap();
+        assert!(!stored.key.contains('.'));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn answers_413_when_a_stored_part_is_over_its_limit() {
+        let root = std::env::temp_dir().join(format!("thunder-stream-{}", uuid::Uuid::new_v4()));
+        let disk = LocalDisk::new(&root, "/storage");
+        let mut req = request(body(&[part("video", Some("beach.mp4"), &"v".repeat(64))]));
+        let mut stream =
+            MultipartStream::new(&mut req, MultipartLimits::new().field("video", 16)).unwrap();
+
+        let video = stream.next_part().await.unwrap().unwrap();
+        let errors = video
+            .store_as("videos", "beach.mp4", &disk)
+            .await
+            .unwrap_err();
+        assert_eq!(errors.status_code(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+        assert_eq!(errors.first("video").unwrap().code, "too_large");
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,227 @@
+// A `TusStore` on the application database, for apps that keep their state there
+// rather than in JSON files. The table comes from the `create_tus_uploads` migration.
+// The partial files stay in the data directory of the process and `TusServer` only
+// locks uploads in memory, so every request for an upload must reach the same instance.
+use chrono::{DateTime, Utc};
+use salvo::async_trait;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set};
+
+use super::store::{TusStore, TusUpload};
+use crate::storage::StorageError;
+
+pub mod tus_upload {
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "tus_uploads")]
+    pub struct Model {
+        #[sea_orm(primary_key, auto_increment = false)]
+        pub id: String,
+        pub length: i64,
+        pub upload_offset: i64,
+        // The metadata as a JSON object.
+        #[sea_orm(column_type = "Text")]
+        pub metadata: String,
+        pub expires_at: DateTimeUtc,
+        // The `StoredFile` as JSON, once the upload is complete.
+        #[sea_orm(column_type = "Text", nullable)]
+        pub stored: Option<String>,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+#[derive(Debug, Clone)]
+pub struct SeaOrmTusStore {
+    db: DatabaseConnection,
+}
+
+impl SeaOrmTusStore {
+    pub fn new(db: DatabaseConnection) -> Self {
+        SeaOrmTusStore { db }
+    }
+}
+
+fn database_failed(error: DbErr) -> StorageError {
+    StorageError::Remote(error.to_string())
+}
+
+fn json_failed(error: serde_json::Error) -> StorageError {
+    StorageError::Remote(format!("invalid tus upload record: {}", error))
+}
+
+fn to_upload(model: tus_upload::Model) -> Result<TusUpload, StorageError> {
+    Ok(TusUpload {
+        id: model.id,
+        length: model.length as u64,
+        offset: model.upload_offset as u64,
+        metadata: serde_json::from_str(&model.metadata).map_err(json_failed)?,
+        expires_at: model.expires_at,
+        stored: model
+            .stored
+            .map(|stored| serde_json::from_str(&stored))
+            .transpose()
+            .map_err(json_failed)?,
+    })
+}
+
+#[async_trait]
+impl TusStore for SeaOrmTusStore {
+    async fn save(&self, upload: &TusUpload) -> Result<(), StorageError> {
+        let model = tus_upload::ActiveModel {
+            id: Set(upload.id.clone()),
+            length: Set(upload.length as i64),
+            upload_offset: Set(upload.offset as i64),
+            metadata: Set(serde_json::to_string(&upload.metadata).map_err(json_failed)?),
+            expires_at: Set(upload.expires_at),
+            stored: Set(upload
+                .stored
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()
+                .map_err(json_failed)?),
+        };
+        tus_upload::Entity::insert(model)
+            .on_conflict(
+                OnConflict::column(tus_upload::Column::Id)
+                    .update_columns([
+                        tus_upload::Column::Length,
+                        tus_upload::Column::UploadOffset,
+                        tus_upload::Column::Metadata,
+                        tus_upload::Column::ExpiresAt,
+                        tus_upload::Column::Stored,
+                    ])
+                    .to_owned(),
+            )
+            .exec(&self.db)
+            .await
+            .map_err(database_failed)?;
+        Ok(())
+    }
+
+    async fn load(&self, id: &str) -> Result<Option<TusUpload>, StorageError> {
+        tus_upload::Entity::find_by_id(id.to_string())
+            .one(&self.db)
+            .await
+            .map_err(database_failed)?
+            .map(to_upload)
+            .transpose()
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), StorageError> {
+        tus_upload::Entity::delete_by_id(id.to_string())
+            .exec(&self.db)
+            .await
+            .map_err(database_failed)?;
+        Ok(())
+    }
+
+    async fn expired(&self, now: DateTime<Utc>) -> Result<Vec<TusUpload>, StorageError> {
+        tus_upload::Entity::find()
+            .filter(tus_upload::Column::ExpiresAt.lte(now))
+            .all(&self.db)
+            .await
+            .map_err(database_failed)?
+            .into_iter()
+            .map(to_upload)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::Duration;
+    use sea_orm::{ConnectionTrait, Database};
+
+    use super::*;
+    use crate::storage::StoredFile;
+
+    async fn store() -> SeaOrmTusStore {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        db.execute_unprepared(
+            "CREATE TABLE tus_uploads (
+                id TEXT PRIMARY KEY,
+                length BIGINT NOT NULL,
+                upload_offset BIGINT NOT NULL,
+                metadata TEXT NOT NULL,
+                expires_at TIMESTAMP NOT NULL,
+                stored TEXT
+            )",
+        )
+        .await
+        .unwrap();
+        SeaOrmTusStore::new(db)
+    }
+
+    fn upload(id: &str, expires_at: DateTime<Utc>) -> TusUpload {
+        TusUpload {
+            id: id.to_string(),
+            length: 11,
+            offset: 0,
+            metadata: HashMap::from([("filename".to_string(), "clip.txt".to_string())]),
+            expires_at,
+            stored: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn saves_loads_and_removes_uploads() {
+        let store = store().await;
+        let now = Utc::now();
+
+        assert!(store.load("missing").await.unwrap().is_none());
+        let mut saved = upload("a1", now);
+        store.save(&saved).await.unwrap();
+        // Saved again once complete, over the first record.
+        saved.offset = 11;
+        saved.stored = Some(StoredFile {
+            key: "videos/a1.txt".to_string(),
+            url: "/files/videos/a1.txt".to_string(),
+            hash: "ab".to_string(),
+            size: 11,
+            content_type: None,
+        });
+        store.save(&saved).await.unwrap();
+
+        let loaded = store.load("a1").await.unwrap().unwrap();
+        assert!(loaded.is_complete());
+        assert_eq!(loaded.metadata["filename"], "clip.txt");
+        assert_eq!(loaded.expires_at, now);
+        assert_eq!(loaded.stored.unwrap().key, "videos/a1.txt");
+
+        store.remove("a1").await.unwrap();
+        assert!(store.load("a1").await.unwrap().is_none());
+        store.remove("a1").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn lists_the_expired_uploads() {
+        let store = store().await;
+        let now = Utc::now();
+        store
+            .save(&upload("old", now - Duration::hours(1)))
+            .await
+            .unwrap();
+        store.save(&upload("due", now)).await.unwrap();
+        store
+            .save(&upload("new", now + Duration::hours(1)))
+            .await
+            .unwrap();
+
+        let mut ids: Vec<_> = store
+            .expired(now)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|upload| upload.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, ["due", "old"]);
+    }
+}
@@ -0,0 +1,629 @@
+// A tus 1.0 server (https://tus.io/protocols/resumable-upload), for uploads that
+// must survive a dropped connection. The client creates an upload, sends it in as
+// many `PATCH`es as it needs and asks with `HEAD` where to resume. The bytes are
+// appended to a file in the data directory; once the last one arrives the file is
+// handed to a `Storage` disk like any other `UploadedFile`.
+//
+// Supported extensions: creation, termination and expiration.
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{DateTime, Duration, Utc};
+use futures_util::StreamExt;
+use salvo::http::header::{CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, LOCATION};
+use salvo::http::{HeaderName, HeaderValue, Method, StatusError};
+use salvo::{Depot, FlowCtrl, Handler, Request, Response, Router, async_trait};
+use tokio::io::AsyncWriteExt;
+
+use crate::UploadedFile;
+use crate::storage::{Storage, StorageError};
+
+mod database;
+mod store;
+
+pub use database::{SeaOrmTusStore, tus_upload};
+pub use store::{FileTusStore, TusStore, TusUpload};
+
+const VERSION: &str = "1.0.0";
+const EXTENSIONS: &str = "creation,termination,expiration";
+
+const TUS_RESUMABLE: HeaderName = HeaderName::from_static("tus-resumable");
+const TUS_VERSION: HeaderName = HeaderName::from_static("tus-version");
+const TUS_EXTENSION: HeaderName = HeaderName::from_static("tus-extension");
+const TUS_MAX_SIZE: HeaderName = HeaderName::from_static("tus-max-size");
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_METADATA: HeaderName = HeaderName::from_static("upload-metadata");
+const UPLOAD_EXPIRES: HeaderName = HeaderName::from_static("upload-expires");
+const METHOD_OVERRIDE: HeaderName = HeaderName::from_static("x-http-method-override");
+
+// Ids are generated by the server; anything else in the path is refused before it
+// reaches the filesystem.
+pub(crate) fn is_valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+}
+
+// Mounted on a path with `router`, which also routes `<path>/<id>`.
+#[derive(Clone)]
+pub struct TusServer {
+    store: Arc<dyn TusStore>,
+    disk: Arc<dyn Storage>,
+    data_dir: PathBuf,
+    dir: String,
+    max_size: Option<u64>,
+    expiration: Duration,
+    // Uploads with a request in flight; a second one gets a 423.
+    busy: Arc<Mutex<HashSet<String>>>,
+}
+
+impl TusServer {
+    // Keeps the state in `store`, the partial files in `data_dir` and the finished
+    // ones in `disk`.
+    pub fn new(
+        store: impl TusStore + 'static,
+        disk: impl Storage + 'static,
+        data_dir: impl Into<PathBuf>,
+    ) -> Self {
+        TusServer {
+            store: Arc::new(store),
+            disk: Arc::new(disk),
+            data_dir: data_dir.into(),
+            dir: "uploads".to_string(),
+            max_size: None,
+            expiration: Duration::hours(24),
+            busy: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    // The directory of the disk finished uploads go to, `uploads` by default.
+    pub fn dir(mut self, dir: impl Into<String>) -> Self {
+        self.dir = dir.into();
+        self
+    }
+
+    // The largest `Upload-Length` accepted, in bytes.
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    // How long an upload is kept after its last `PATCH`, 24 hours by default.
+    pub fn expiration(mut self, expiration: Duration) -> Self {
+        self.expiration = expiration;
+        self
+    }
+
+    // `path` for creating uploads and `path/{id}` for the rest.
+    pub fn router(self, path: &str) -> Router {
+        Router::with_path(path)
+            .goal(self.clone())
+            .push(Router::with_path("{id}").goal(self))
+    }
+
+    // The state of an upload, e.g. to find its `stored` file from the id the client
+    // got in `Location`.
+    pub async fn upload(&self, id: &str) -> Result<Option<TusUpload>, StorageError> {
+        if !is_valid_id(id) {
+            return Ok(None);
+        }
+        self.store.load(id).await
+    }
+
+    // Drops the expired uploads and their partial files; meant to run periodically.
+    // Files already moved to the disk are left alone. Returns how many were removed.
+    pub async fn remove_expired(&self) -> Result<usize, StorageError> {
+        let expired = self.store.expired(Utc::now()).await?;
+        for upload in &expired {
+            self.discard(&upload.id).await?;
+        }
+        Ok(expired.len())
+    }
+
+    async fn discard(&self, id: &str) -> Result<(), StorageError> {
+        match tokio::fs::remove_file(self.data_dir.join(id)).await {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => {
+                return Err(error.into());
+            }
+            _ => {}
+        }
+        self.store.remove(id).await
+    }
+
+    async fn respond(&self, req: &mut Request, res: &mut Response) -> Result<(), StatusError> {
+        // For clients that can only send `GET` and `POST`.
+        let method = match header(req, &METHOD_OVERRIDE) {
+            Some(method) => method
+                .parse::<Method>()
+                .map_err(|_| StatusError::bad_request().brief("Invalid X-HTTP-Method-Override."))?,
+            None => req.method().clone(),
+        };
+        if method == Method::OPTIONS {
+            self.options(res);
+            return Ok(());
+        }
+        if header(req, &TUS_RESUMABLE) != Some(VERSION) {
+            res.headers_mut()
+                .insert(TUS_VERSION, HeaderValue::from_static(VERSION));
+            return Err(
+                StatusError::precondition_failed().brief("Unsupported Tus-Resumable version.")
+            );
+        }
+
+        let id = req.param::<String>("id");
+        match (method, id) {
+            (Method::POST, None) => self.create(req, res).await,
+            (Method::HEAD, Some(id)) => self.head(&id, res).await,
+            (Method::PATCH, Some(id)) => self.patch(&id, req, res).await,
+            (Method::DELETE, Some(id)) => self.terminate(&id, res).await,
+            _ => Err(StatusError::method_not_allowed()),
+        }
+    }
+
+    fn options(&self, res: &mut Response) {
+        let headers = res.headers_mut();
+        headers.insert(TUS_VERSION, HeaderValue::from_static(VERSION));
+        headers.insert(TUS_EXTENSION, HeaderValue::from_static(EXTENSIONS));
+        if let Some(max_size) = self.max_size {
+            headers.insert(TUS_MAX_SIZE, HeaderValue::from(max_size));
+        }
+        res.status_code(salvo::http::StatusCode::NO_CONTENT);
+    }
+
+    async fn create(&self, req: &mut Request, res: &mut Response) -> Result<(), StatusError> {
+        let length = header(req, &UPLOAD_LENGTH)
+            .and_then(|length| length.parse::<u64>().ok())
+            .ok_or_else(|| StatusError::bad_request().brief("Missing or invalid Upload-Length."))?;
+        if let Some(max_size) = self.max_size
+            && length > max_size
+        {
+            return Err(StatusError::payload_too_large().brief(format!(
+                "The upload may not be greater than {} bytes.",
+                max_size
+            )));
+        }
+        let metadata = match header(req, &UPLOAD_METADATA) {
+            Some(metadata) => parse_metadata(metadata)
+                .ok_or_else(|| StatusError::bad_request().brief("Invalid Upload-Metadata."))?,
+            None => HashMap::new(),
+        };
+
+        let mut upload = TusUpload {
+            id: uuid::Uuid::new_v4().simple().to_string(),
+            length,
+            offset: 0,
+            metadata,
+            expires_at: Utc::now() + self.expiration,
+            stored: None,
+        };
+        tokio::fs::create_dir_all(&self.data_dir)
+            .await
+            .map_err(storage_failed)?;
+        tokio::fs::File::create(self.data_dir.join(&upload.id))
+            .await
+            .map_err(storage_failed)?;
+        self.store.save(&upload).await.map_err(storage_failed)?;
+        if upload.is_complete() {
+            self.finish(&mut upload).await?;
+        }
+
+        let location = format!("{}/{}", req.uri().path().trim_end_matches('/'), upload.id);
+        let headers = res.headers_mut();
+        headers.insert(LOCATION, header_value(&location));
+        headers.insert(UPLOAD_EXPIRES, header_value(&http_date(upload.expires_at)));
+        res.status_code(salvo::http::StatusCode::CREATED);
+        Ok(())
+    }
+
+    async fn head(&self, id: &str, res: &mut Response) -> Result<(), StatusError> {
+        let upload = self.find(id).await?;
+        let headers = res.headers_mut();
+        headers.insert(UPLOAD_OFFSET, HeaderValue::from(upload.offset));
+        headers.insert(UPLOAD_LENGTH, HeaderValue::from(upload.length));
+        if !upload.metadata.is_empty() {
+            headers.insert(
+                UPLOAD_METADATA,
+                header_value(&encode_metadata(&upload.metadata)),
+            );
+        }
+        if !upload.is_complete() {
+            headers.insert(UPLOAD_EXPIRES, header_value(&http_date(upload.expires_at)));
+        }
+        headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        res.status_code(salvo::http::StatusCode::OK);
+        Ok(())
+    }
+
+    async fn patch(
+        &self,
+        id: &str,
+        req: &mut Request,
+        res: &mut Response,
+    ) -> Result<(), StatusError> {
+        if header(req, &CONTENT_TYPE) != Some("application/offset+octet-stream") {
+            return Err(StatusError::unsupported_media_type()
+                .brief("Expected Content-Type: application/offset+octet-stream."));
+        }
+        let offset = header(req, &UPLOAD_OFFSET)
+            .and_then(|offset| offset.parse::<u64>().ok())
+            .ok_or_else(|| StatusError::bad_request().brief("Missing or invalid Upload-Offset."))?;
+
+        let _busy = self.lock(id)?;
+        let mut upload = self.find(id).await?;
+        if offset != upload.offset {
+            return Err(StatusError::conflict()
+                .brief(format!("The upload is at offset {}.", upload.offset)));
+        }
+        // A retried last `PATCH` whose answer got lost: the file is already on the disk.
+        if upload.stored.is_some() {
+            patched(&upload, res);
+            return Ok(());
+        }
+        let announced = header(req, &CONTENT_LENGTH).and_then(|length| length.parse::<u64>().ok());
+        if announced.is_some_and(|announced| offset + announced > upload.length) {
+            return Err(
+                StatusError::payload_too_large().brief("The chunk goes past Upload-Length.")
+            );
+        }
+
+        let path = self.data_dir.join(id);
+        let mut file = tokio::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .await
+            .map_err(storage_failed)?;
+        // Whatever arrived before the client went away is kept, so it can resume.
+        let mut outcome = Ok(());
+        let mut body = req.take_body();
+        while let Some(frame) = body.next().await {
+            let chunk = match frame {
+                Ok(frame) => frame.into_data().unwrap_or_default(),
+                Err(error) => {
+                    outcome = Err(StatusError::bad_request().brief(error.to_string()));
+                    break;
+                }
+            };
+            if upload.offset + chunk.len() as u64 > upload.length {
+                outcome =
+                    Err(StatusError::payload_too_large()
+                        .brief("The chunk goes past Upload-Length."));
+                break;
+            }
+            if let Err(error) = file.write_all(&chunk).await {
+                // A partial write would leave the file out of step with the offset.
+                let _ = file.set_len(upload.offset).await;
+                outcome = Err(storage_failed(error));
+                break;
+            }
+            upload.offset += chunk.len() as u64;
+        }
+        file.flush().await.map_err(storage_failed)?;
+        drop(file);
+
+        // Saved before `finish`, so the offset matches the data file even if moving it
+        // to the disk fails; the next `PATCH` retries the move.
+        upload.expires_at = Utc::now() + self.expiration;
+        self.store.save(&upload).await.map_err(storage_failed)?;
+        outcome?;
+        if upload.is_complete() {
+            self.finish(&mut upload).await?;
+        }
+
+        patched(&upload, res);
+        Ok(())
+    }
+
+    async fn terminate(&self, id: &str, res: &mut Response) -> Result<(), StatusError> {
+        let _busy = self.lock(id)?;
+        self.find(id).await?;
+        self.discard(id).await.map_err(storage_failed)?;
+        res.status_code(salvo::http::StatusCode::NO_CONTENT);
+        Ok(())
+    }
+
+    // Moves the assembled file to the disk, named after the upload id with the
+    // extension of the `filename` metadata.
+    async fn finish(&self, upload: &mut TusUpload) -> Result<(), StatusError> {
+        let path = self.data_dir.join(&upload.id);
+        let file = UploadedFile {
+            path: path.clone(),
+            file_name: upload.metadata.get("filename").cloned(),
+            content_type: upload
+                .metadata
+                .get("filetype")
+                .and_then(|filetype| filetype.parse().ok()),
+        };
+        let name = match file.extension() {
+            Some(extension) => format!("{}.{}", upload.id, extension),
+            None => upload.id.clone(),
+        };
+        let stored = file
+            .store_as(&self.dir, &name, &*self.disk)
+            .await
+            .map_err(storage_failed)?;
+        upload.stored = Some(stored);
+        self.store.save(upload).await.map_err(storage_failed)?;
+        tokio::fs::remove_file(&path)
+            .await
+            .map_err(storage_failed)?;
+        Ok(())
+    }
+
+    // The upload, as long as it has not expired. An expired one is cleaned up on
+    // the spot.
+    async fn find(&self, id: &str) -> Result<TusUpload, StatusError> {
+        if !is_valid_id(id) {
+            return Err(StatusError::not_found());
+        }
+        let upload = self
+            .store
+            .load(id)
+            .await
+            .map_err(storage_failed)?
+            .ok_or_else(StatusError::not_found)?;
+        if upload.is_expired(Utc::now()) {
+            self.discard(id).await.map_err(storage_failed)?;
+            return Err(StatusError::gone().brief("The upload has expired."));
+        }
+        Ok(upload)
+    }
+
+    fn lock(&self, id: &str) -> Result<Busy, StatusError> {
+        let mut busy = self.busy.lock().unwrap_or_else(|error| error.into_inner());
+        if !busy.insert(id.to_string()) {
+            return Err(StatusError::locked().brief("The upload is being written to."));
+        }
+        Ok(Busy {
+            ids: self.busy.clone(),
+            id: id.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl Handler for TusServer {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+        _ctrl: &mut FlowCtrl,
+    ) {
+        res.headers_mut()
+            .insert(TUS_RESUMABLE, HeaderValue::from_static(VERSION));
+        if let Err(error) = self.respond(req, res).await {
+            res.render(error);
+        }
+    }
+}
+
+// Releases the upload when the request is done with it.
+struct Busy {
+    ids: Arc<Mutex<HashSet<String>>>,
+    id: String,
+}
+
+impl Drop for Busy {
+    fn drop(&mut self) {
+        let mut ids = self.ids.lock().unwrap_or_else(|error| error.into_inner());
+        ids.remove(&self.id);
+    }
+}
+
+// The answer to a `PATCH`: where the upload stands now.
+fn patched(upload: &TusUpload, res: &mut Response) {
+    let headers = res.headers_mut();
+    headers.insert(UPLOAD_OFFSET, HeaderValue::from(upload.offset));
+    if !upload.is_complete() {
+        headers.insert(UPLOAD_EXPIRES, header_value(&http_date(upload.expires_at)));
+    }
+    res.status_code(salvo::http::StatusCode::NO_CONTENT);
+}
+
+fn header<'a>(req: &'a Request, name: &HeaderName) -> Option<&'a str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+}
+
+fn header_value(value: &str) -> HeaderValue {
+    HeaderValue::from_str(value).unwrap_or_else(|_| HeaderValue::from_static(""))
+}
+
+// The details go to the log: they may name paths or hosts of the server.
+fn storage_failed(error: impl std::fmt::Display) -> StatusError {
+    tracing::error!("tus upload could not be stored: {}", error);
+    StatusError::internal_server_error().brief("The upload could not be stored.")
+}
+
+// `filename d29ybGQ=,is_confidential`: comma-separated keys, each with an optional
+// base64 value.
+fn parse_metadata(header: &str) -> Option<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+    for pair in header
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => {
+                let value = STANDARD.decode(value.trim()).ok()?;
+                (key, String::from_utf8(value).ok()?)
+            }
+            None => (pair, String::new()),
+        };
+        if key.is_empty() || metadata.insert(key.to_string(), value).is_some() {
+            return None;
+        }
+    }
+    Some(metadata)
+}
+
+fn encode_metadata(metadata: &HashMap<String, String>) -> String {
+    let mut pairs: Vec<_> = metadata
+        .iter()
+        .map(|(key, value)| {
+            if value.is_empty() {
+                key.clone()
+            } else {
+                format!("{} {}", key, STANDARD.encode(value))
+            }
+        })
+        .collect();
+    pairs.sort();
+    pairs.join(",")
+}
+
+fn http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use salvo::http::StatusCode;
+    use salvo::test::TestClient;
+    use salvo::{Router, Service};
+
+    use super::*;
+    use crate::storage::LocalDisk;
+
+    struct Fixture {
+        root: PathBuf,
+        service: Service,
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn fixture() -> Fixture {
+        let root = std::env::temp_dir().join(format!("thunder-tus-{}", uuid::Uuid::new_v4()));
+        let server = TusServer::new(
+            FileTusStore::new(root.join("state")),
+            LocalDisk::new(root.join("disk"), "/files"),
+            root.join("partial"),
+        )
+        .dir("videos");
+        Fixture {
+            service: Service::new(Router::new().push(server.router("uploads"))),
+            root,
+        }
+    }
+
+    fn header(res: &Response, name: &str) -> String {
+        res.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    async fn create(fixture: &Fixture, length: u64) -> String {
+        let res = TestClient::post("http://localhost/uploads")
+            .add_header("tus-resumable", VERSION, true)
+            .add_header("upload-length", length, true)
+            // `filename` is `clip.txt`.
+            .add_header("upload-metadata", "filename Y2xpcC50eHQ=", true)
+            .send(&fixture.service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::CREATED));
+        header(&res, "location")
+    }
+
+    async fn patch(fixture: &Fixture, location: &str, offset: u64, chunk: &[u8]) -> Response {
+        TestClient::patch(format!("http://localhost{}", location))
+            .add_header("tus-resumable", VERSION, true)
+            .add_header("upload-offset", offset, true)
+            .add_header("content-type", "application/offset+octet-stream", true)
+            .bytes(chunk.to_vec())
+            .send(&fixture.service)
+            .await
+    }
+
+    async fn head(fixture: &Fixture, location: &str) -> Response {
+        TestClient::head(format!("http://localhost{}", location))
+            .add_header("tus-resumable", VERSION, true)
+            .send(&fixture.service)
+            .await
+    }
+
+    #[tokio::test]
+    async fn uploads_in_chunks_and_stores_the_file() {
+        let fixture = fixture();
+        let location = create(&fixture, 11).await;
+        assert!(location.starts_with("/uploads/"));
+        let id = location.trim_start_matches("/uploads/");
+
+        let res = patch(&fixture, &location, 0, b"hello ").await;
+        assert_eq!(res.status_code, Some(StatusCode::NO_CONTENT));
+        assert_eq!(header(&res, "upload-offset"), "6");
+
+        let res = head(&fixture, &location).await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        assert_eq!(header(&res, "upload-offset"), "6");
+        assert_eq!(header(&res, "upload-length"), "11");
+        assert_eq!(header(&res, "cache-control"), "no-store");
+
+        let res = patch(&fixture, &location, 6, b"world").await;
+        assert_eq!(res.status_code, Some(StatusCode::NO_CONTENT));
+        assert_eq!(header(&res, "upload-offset"), "11");
+
+        let stored = std::fs::read(fixture.root.join(format!("disk/videos/{}.txt", id))).unwrap();
+        assert_eq!(stored, b"hello world");
+        assert!(!fixture.root.join("partial").join(id).exists());
+
+        // A client that lost the last answer resumes at the offset `HEAD` gives it.
+        let res = patch(&fixture, &location, 11, b"").await;
+        assert_eq!(res.status_code, Some(StatusCode::NO_CONTENT));
+        assert_eq!(header(&res, "upload-offset"), "11");
+    }
+
+    #[tokio::test]
+    async fn refuses_a_chunk_at_the_wrong_offset() {
+        let fixture = fixture();
+        let location = create(&fixture, 11).await;
+
+        let res = patch(&fixture, &location, 3, b"hello").await;
+        assert_eq!(res.status_code, Some(StatusCode::CONFLICT));
+
+        let res = patch(&fixture, &location, 0, b"hello world!").await;
+        assert_eq!(res.status_code, Some(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    #[tokio::test]
+    async fn terminates_an_upload() {
+        let fixture = fixture();
+        let location = create(&fixture, 11).await;
+        patch(&fixture, &location, 0, b"hello ").await;
+
+        let res = TestClient::delete(format!("http://localhost{}", location))
+            .add_header("tus-resumable", VERSION, true)
+            .send(&fixture.service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::NO_CONTENT));
+
+        let res = head(&fixture, &location).await;
+        assert_eq!(res.status_code, Some(StatusCode::NOT_FOUND));
+        let id = location.trim_start_matches("/uploads/");
+        assert!(!fixture.root.join("partial").join(id).exists());
+    }
+
+    #[tokio::test]
+    async fn requires_the_protocol_version() {
+        let fixture = fixture();
+        let res = TestClient::post("http://localhost/uploads")
+            .add_header("upload-length", 11, true)
+            .send(&fixture.service)
+            .await;
+        assert_eq!(res.status_code, Some(StatusCode::PRECONDITION_FAILED));
+        assert_eq!(header(&res, "tus-version"), VERSION);
+    }
+}
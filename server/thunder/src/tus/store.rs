@@ -0,0 +1,211 @@
+// Where the `TusServer` keeps the state of each upload between requests. The bytes
+// themselves always go to the server's data directory; a store only holds the small
+// `TusUpload` record, so it can live next to them or in the application database.
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use chrono::{DateTime, Utc};
+use salvo::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::storage::{StorageError, StoredFile};
+
+// One upload, from its creation until it is terminated or expires.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TusUpload {
+    pub id: String,
+    // The total size announced with `Upload-Length`.
+    pub length: u64,
+    // How many bytes have been received so far.
+    pub offset: u64,
+    // The decoded `Upload-Metadata`, e.g. `filename` and `filetype`.
+    pub metadata: HashMap<String, String>,
+    pub expires_at: DateTime<Utc>,
+    // Where the file ended up once every byte arrived.
+    pub stored: Option<StoredFile>,
+}
+
+impl TusUpload {
+    pub fn is_complete(&self) -> bool {
+        self.offset == self.length
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at <= now
+    }
+}
+
+#[async_trait]
+pub trait TusStore: Send + Sync {
+    // Creates or replaces the record of `upload.id`.
+    async fn save(&self, upload: &TusUpload) -> Result<(), StorageError>;
+
+    async fn load(&self, id: &str) -> Result<Option<TusUpload>, StorageError>;
+
+    // Removing a missing upload is not an error.
+    async fn remove(&self, id: &str) -> Result<(), StorageError>;
+
+    // The uploads whose `expires_at` is at or before `now`.
+    async fn expired(&self, now: DateTime<Utc>) -> Result<Vec<TusUpload>, StorageError>;
+}
+
+// Keeps every upload as a `<id>.json` file in a directory.
+#[derive(Debug, Clone)]
+pub struct FileTusStore {
+    dir: PathBuf,
+}
+
+impl FileTusStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileTusStore { dir: dir.into() }
+    }
+
+    fn path(&self, id: &str) -> Result<PathBuf, StorageError> {
+        if !super::is_valid_id(id) {
+            return Err(StorageError::InvalidKey(id.to_string()));
+        }
+        Ok(self.dir.join(format!("{}.json", id)))
+    }
+}
+
+#[async_trait]
+impl TusStore for FileTusStore {
+    async fn save(&self, upload: &TusUpload) -> Result<(), StorageError> {
+        let path = self.path(&upload.id)?;
+        let contents = serde_json::to_vec(upload).map_err(std::io::Error::other)?;
+        tokio::fs::create_dir_all(&self.dir).await?;
+        // Written aside and renamed, so a crash never leaves half a record.
+        let partial = path.with_extension("json.tmp");
+        tokio::fs::write(&partial, contents).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        Ok(())
+    }
+
+    async fn load(&self, id: &str) -> Result<Option<TusUpload>, StorageError> {
+        match tokio::fs::read(self.path(id)?).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map(Some)
+                .map_err(|error| StorageError::Io(std::io::Error::other(error))),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), StorageError> {
+        match tokio::fs::remove_file(self.path(id)?).await {
+            Err(error) if error.kind() != std::io::ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn expired(&self, now: DateTime<Utc>) -> Result<Vec<TusUpload>, StorageError> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error.into()),
+        };
+        let mut expired = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if let Some(upload) = self.load(id).await?
+                && upload.is_expired(now)
+            {
+                expired.push(upload);
+            }
+        }
+        Ok(expired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    fn upload(id: &str, expires_at: DateTime<Utc>) -> TusUpload {
+        TusUpload {
+            id: id.to_string(),
+            length: 11,
+            offset: 0,
+            metadata: HashMap::from([("filename".to_string(), "clip.txt".to_string())]),
+            expires_at,
+            stored: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn saves_loads_and_removes_uploads() {
+        let dir = std::env::temp_dir().join(format!("thunder-tus-store-{}", uuid::Uuid::new_v4()));
+        let store = FileTusStore::new(&dir);
+        let now = Utc::now();
+
+        assert!(store.load("missing").await.unwrap().is_none());
+        let mut saved = upload("a1", now);
+        store.save(&saved).await.unwrap();
+        saved.offset = 6;
+        store.save(&saved).await.unwrap();
+
+        let loaded = store.load("a1").await.unwrap().unwrap();
+        assert_eq!(loaded.offset, 6);
+        assert_eq!(loaded.metadata["filename"], "clip.txt");
+        assert_eq!(loaded.expires_at, now);
+        // Nothing is left aside once renamed.
+        assert!(!dir.join("a1.json.tmp").exists());
+
+        store.remove("a1").await.unwrap();
+        assert!(store.load("a1").await.unwrap().is_none());
+        store.remove("a1").await.unwrap();
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn refuses_ids_that_leave_the_directory() {
+        let store = FileTusStore::new(std::env::temp_dir().join("thunder-tus-store"));
+        for id in ["", "../a1", "a1/b2", "a1.json"] {
+            assert!(matches!(
+                store.load(id).await,
+                Err(StorageError::InvalidKey(_))
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn lists_the_expired_uploads() {
+        let dir = std::env::temp_dir().join(format!("thunder-tus-store-{}", uuid::Uuid::new_v4()));
+        let store = FileTusStore::new(&dir);
+        let now = Utc::now();
+        // A directory that doesn't exist yet holds nothing.
+        assert!(store.expired(now).await.unwrap().is_empty());
+
+        store
+            .save(&upload("old", now - Duration::hours(1)))
+            .await
+            .unwrap();
+        store.save(&upload("due", now)).await.unwrap();
+        store
+            .save(&upload("new", now + Duration::hours(1)))
+            .await
+            .unwrap();
+        std::fs::write(dir.join("notes.txt"), "not an upload").unwrap();
+
+        let mut ids: Vec<_> = store
+            .expired(now)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|upload| upload.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, ["due", "old"]);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,373 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+
+use salvo::http::{ParseError, StatusCode};
+use salvo::oapi::{
+    Components, Content, EndpointArgRegister, Object, Operation, Parameter, ParameterIn, RefOr,
+    RequestBody, Schema, ToRequestBody, ToSchema,
+};
+use salvo_core::extract::{Extractible, Metadata};
+use salvo_core::{Depot, FlowCtrl, Handler, Request, Response, Writer, async_trait};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    AsyncValidate, Validate, ValidationError, ValidationErrors, apply_constraints,
+    document_constraints,
+};
+
+// The newtype plumbing shared by every validated extractor.
+macro_rules! validated_extractor {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        ///
+        /// `G` picks the validation group, see [`validation_group!`](crate::validation_group).
+        pub struct $name<T, G = NoGroup>(pub T, PhantomData<G>);
+
+        impl<T, G> $name<T, G> {
+            pub fn new(value: T) -> Self {
+                Self(value, PhantomData)
+            }
+
+            /// Consumes self and returns the validated value.
+            pub fn into_inner(self) -> T {
+                self.0
+            }
+        }
+
+        impl<T, G> Deref for $name<T, G> {
+            type Target = T;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        impl<T, G> DerefMut for $name<T, G> {
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                &mut self.0
+            }
+        }
+
+        impl<T: Debug, G> Debug for $name<T, G> {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        impl<T: Display, G> Display for $name<T, G> {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+    };
+}
+
+// The validation group of an extractor: rules of that group run together with the
+// ones that belong to no group.
+pub trait ValidationGroup: Send + Sync + 'static {
+    const NAME: Option<&'static str>;
+}
+
+/// Runs only the rules that belong to no group.
+pub struct NoGroup;
+
+impl ValidationGroup for NoGroup {
+    const NAME: Option<&'static str> = None;
+}
+
+/// Declares a type that selects a validation group on the validated extractors:
+///
+/// ```ignore
+/// thunder::validation_group!(pub OnCreate = "create");
+///
+/// async fn create(user: JsonValidated<UserForm, OnCreate>) { ... }
+/// ```
+#[macro_export]
+macro_rules! validation_group {
+    ($(#[$meta:meta])* $vis:vis $name:ident = $group:literal) => {
+        $(#[$meta])*
+        $vis struct $name;
+
+        impl $crate::ValidationGroup for $name {
+            const NAME: Option<&'static str> = Some($group);
+        }
+    };
+}
+
+validated_extractor!(
+    /// A JSON body that has been deserialized and passed `Validate`.
+    JsonValidated
+);
+validated_extractor!(
+    /// Query parameters that have been deserialized and passed `Validate`.
+    QueryValidated
+);
+validated_extractor!(
+    /// An url-encoded or multipart form that has been deserialized and passed `Validate`.
+    FormValidated
+);
+validated_extractor!(
+    /// Path parameters that have been deserialized and passed `Validate`.
+    PathValidated
+);
+
+// Input that cannot be deserialized is a 400; the field of the error says where it came from.
+fn parse_failed(source: &str, error: ParseError) -> ValidationErrors {
+    let mut errors = ValidationErrors::new();
+    errors.add_error(source, ValidationError::new("parse", error.to_string()));
+    errors.add_status_code(StatusCode::BAD_REQUEST);
+    errors
+}
+
+type Injector = Arc<dyn Fn(&mut Depot) + Send + Sync>;
+
+// Hoop that injects values into the depot, e.g. the `DatabaseConnection` of the
+// `unique` and `exists` rules. Salvo hands extractors no depot, so the validated
+// extractors run the async rules against one holding only these values:
+//
+//     Router::new().hoop(ValidationDepot::new().inject(db))
+#[derive(Clone, Default)]
+pub struct ValidationDepot {
+    injectors: Vec<Injector>,
+}
+
+impl Debug for ValidationDepot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ValidationDepot")
+            .field("values", &self.injectors.len())
+            .finish()
+    }
+}
+
+impl ValidationDepot {
+    pub fn new() -> Self {
+        ValidationDepot {
+            injectors: Vec::new(),
+        }
+    }
+
+    // Adds `value`, found with `depot.obtain::<V>()` like any injected value.
+    pub fn inject<V: Clone + Send + Sync + 'static>(mut self, value: V) -> Self {
+        self.injectors.push(Arc::new(move |depot: &mut Depot| {
+            depot.inject(value.clone());
+        }));
+        self
+    }
+
+    fn fill(&self, depot: &mut Depot) {
+        for inject in &self.injectors {
+            inject(depot);
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for ValidationDepot {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        _res: &mut Response,
+        _ctrl: &mut FlowCtrl,
+    ) {
+        self.fill(depot);
+        req.extensions_mut().insert(self.clone());
+    }
+}
+
+// Sanitizes a freshly parsed value and runs every rule on it for `group`, the async
+// ones included. Failing rules answer with 422 unless a rule picked another status,
+// e.g. a database error.
+pub(crate) async fn validated<T: AsyncValidate>(
+    mut value: T,
+    group: Option<&str>,
+    req: &Request,
+) -> Result<T, ValidationErrors> {
+    value.sanitize();
+    let mut depot = Depot::new();
+    if let Some(values) = req.extensions().get::<ValidationDepot>() {
+        values.fill(&mut depot);
+    }
+    let outcome = match group {
+        Some(group) => value.validate_group_async(&depot, group).await,
+        None => value.validate_async(&depot).await,
+    };
+    match outcome {
+        Ok(()) => Ok(value),
+        Err(mut errors) => {
+            if errors.status_code.is_none() {
+                errors.add_status_code(StatusCode::UNPROCESSABLE_ENTITY);
+            }
+            Err(errors)
+        }
+    }
+}
+
+impl<'ex, T, G> Extractible<'ex> for JsonValidated<T, G>
+where
+    T: DeserializeOwned + AsyncValidate + Send,
+    G: ValidationGroup,
+{
+    fn metadata() -> &'static Metadata {
+        static METADATA: Metadata = Metadata::new("");
+        &METADATA
+    }
+    async fn extract(
+        req: &'ex mut Request,
+    ) -> Result<Self, impl Writer + Send + fmt::Debug + 'static> {
+        let value = req
+            .parse_json::<T>()
+            .await
+            .map_err(|e| parse_failed("body", e))?;
+        validated(value, G::NAME, req).await.map(JsonValidated::new)
+    }
+}
+
+impl<'ex, T, G> Extractible<'ex> for FormValidated<T, G>
+where
+    T: DeserializeOwned + AsyncValidate + Send,
+    G: ValidationGroup,
+{
+    fn metadata() -> &'static Metadata {
+        static METADATA: Metadata = Metadata::new("");
+        &METADATA
+    }
+    async fn extract(
+        req: &'ex mut Request,
+    ) -> Result<Self, impl Writer + Send + fmt::Debug + 'static> {
+        let value = req
+            .parse_form::<T>()
+            .await
+            .map_err(|e| parse_failed("body", e))?;
+        validated(value, G::NAME, req).await.map(FormValidated::new)
+    }
+}
+
+impl<'ex, T, G> Extractible<'ex> for QueryValidated<T, G>
+where
+    T: DeserializeOwned + AsyncValidate + Send,
+    G: ValidationGroup,
+{
+    fn metadata() -> &'static Metadata {
+        static METADATA: Metadata = Metadata::new("");
+        &METADATA
+    }
+    async fn extract(
+        req: &'ex mut Request,
+    ) -> Result<Self, impl Writer + Send + fmt::Debug + 'static> {
+        let value = req
+            .parse_queries::<T>()
+            .map_err(|e| parse_failed("query", e))?;
+        validated(value, G::NAME, req)
+            .await
+            .map(QueryValidated::new)
+    }
+}
+
+impl<'ex, T, G> Extractible<'ex> for PathValidated<T, G>
+where
+    T: DeserializeOwned + AsyncValidate + Send,
+    G: ValidationGroup,
+{
+    fn metadata() -> &'static Metadata {
+        static METADATA: Metadata = Metadata::new("");
+        &METADATA
+    }
+    async fn extract(
+        req: &'ex mut Request,
+    ) -> Result<Self, impl Writer + Send + fmt::Debug + 'static> {
+        let value = req
+            .parse_params::<T>()
+            .map_err(|e| parse_failed("path", e))?;
+        validated(value, G::NAME, req).await.map(PathValidated::new)
+    }
+}
+
+impl<T: ToSchema + Validate, G> ToRequestBody for JsonValidated<T, G> {
+    fn to_request_body(components: &mut Components) -> RequestBody {
+        let schema = T::to_schema(components);
+        let schema = document_constraints::<T>(components, schema);
+        RequestBody::new()
+            .description("Extract json format data from request.")
+            .add_content("application/json", Content::new(schema))
+    }
+}
+
+impl<T: ToSchema + Validate, G> ToRequestBody for FormValidated<T, G> {
+    fn to_request_body(components: &mut Components) -> RequestBody {
+        let schema = T::to_schema(components);
+        let schema = document_constraints::<T>(components, schema);
+        RequestBody::new()
+            .description("Extract form format data from request.")
+            .add_content(
+                "application/x-www-form-urlencoded",
+                Content::new(schema.clone()),
+            )
+            .add_content("multipart/form-data", Content::new(schema))
+    }
+}
+
+impl<T: ToSchema + Validate, G> EndpointArgRegister for JsonValidated<T, G> {
+    fn register(components: &mut Components, operation: &mut Operation, _arg: &str) {
+        operation.request_body = Some(Self::to_request_body(components));
+    }
+}
+
+impl<T: ToSchema + Validate, G> EndpointArgRegister for FormValidated<T, G> {
+    fn register(components: &mut Components, operation: &mut Operation, _arg: &str) {
+        operation.request_body = Some(Self::to_request_body(components));
+    }
+}
+
+impl<T: ToSchema + Validate, G> EndpointArgRegister for QueryValidated<T, G> {
+    fn register(components: &mut Components, operation: &mut Operation, _arg: &str) {
+        register_parameters::<T>(components, operation, ParameterIn::Query);
+    }
+}
+
+impl<T: ToSchema + Validate, G> EndpointArgRegister for PathValidated<T, G> {
+    fn register(components: &mut Components, operation: &mut Operation, _arg: &str) {
+        register_parameters::<T>(components, operation, ParameterIn::Path);
+    }
+}
+
+// Query and path values are flat, so every property of the schema of `T` becomes
+// one parameter of the operation.
+fn register_parameters<T: ToSchema + Validate>(
+    components: &mut Components,
+    operation: &mut Operation,
+    parameter_in: ParameterIn,
+) {
+    let schema = T::to_schema(components);
+    let Some(mut object) = resolve_object(components, &schema) else {
+        return;
+    };
+    apply_constraints(&mut object, &T::schema_constraints());
+
+    for (name, property) in &object.properties {
+        let required = parameter_in == ParameterIn::Path || object.required.contains(name);
+        operation.parameters.insert(
+            Parameter::new(name)
+                .parameter_in(parameter_in)
+                .required(required)
+                .schema(property.clone()),
+        );
+    }
+}
+
+// Follows `#/components/schemas/...` references down to the object they point at.
+pub(crate) fn resolve_object(components: &Components, schema: &RefOr<Schema>) -> Option<Object> {
+    match schema {
+        RefOr::Type(Schema::Object(object)) => Some((**object).clone()),
+        RefOr::Ref(reference) => {
+            let name = reference
+                .ref_location
+                .strip_prefix("#/components/schemas/")?;
+            let schema = components.schemas.0.get(name)?.clone();
+            resolve_object(components, &schema)
+        }
+        _ => None,
+    }
+}
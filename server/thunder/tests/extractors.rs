@@ -0,0 +1,305 @@
+// The validated extractors in handlers: the statuses and formats of their answers,
+// the depot of the async rules and what they add to the OpenAPI document.
+use salvo::oapi::{OpenApi, ToSchema, endpoint};
+use salvo::prelude::*;
+use salvo::test::{ResponseExt, TestClient};
+use serde::Deserialize;
+use serde_json::Value;
+use thunder::{
+    FormValidated, JsonValidated, MessageCatalogue, PathValidated, QueryValidated, Validate,
+    ValidationDepot,
+};
+
+mod user {
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "users")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub email: String,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+struct Signup {
+    #[validate(email, unique(entity = user::Entity, column = "email"))]
+    email: String,
+    #[validate(length(min = 3, max = 20))]
+    name: String,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+struct Search {
+    #[validate(length(min = 2))]
+    q: String,
+    #[validate(min = 1, max = 100)]
+    per_page: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+struct UserPath {
+    #[validate(min = 1)]
+    id: i64,
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+struct Profile {
+    #[validate(email)]
+    email: String,
+    #[validate(length(min = 3, max = 40))]
+    name: String,
+    #[validate(regex = "^[a-z0-9_]+$")]
+    handle: String,
+    #[validate(min = 13, max = 120)]
+    age: u8,
+}
+
+#[endpoint]
+async fn update_profile(profile: JsonValidated<Profile>) -> String {
+    profile.into_inner().handle
+}
+
+#[endpoint]
+async fn signup_json(signup: JsonValidated<Signup>) -> String {
+    signup.into_inner().email
+}
+
+#[endpoint]
+async fn signup_form(signup: FormValidated<Signup>) -> String {
+    signup.into_inner().name
+}
+
+#[endpoint]
+async fn search(query: QueryValidated<Search>) -> String {
+    query.into_inner().q
+}
+
+#[endpoint]
+async fn show_user(path: PathValidated<UserPath>) -> String {
+    path.into_inner().id.to_string()
+}
+
+fn router() -> Router {
+    Router::new()
+        .push(Router::with_path("signup").post(signup_json))
+        .push(Router::with_path("signup-form").post(signup_form))
+        .push(Router::with_path("search").get(search))
+        .push(Router::with_path("users/{id}").get(show_user))
+        .push(Router::with_path("profile").put(update_profile))
+}
+
+async fn users() -> sea_orm::DatabaseConnection {
+    use sea_orm::{ConnectionTrait, Database};
+
+    let db = Database::connect("sqlite::memory:").await.unwrap();
+    db.execute_unprepared(
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT NOT NULL);
+         INSERT INTO users (id, email) VALUES (1, 'ada@example.com');",
+    )
+    .await
+    .unwrap();
+    db
+}
+
+async fn service() -> Service {
+    Service::new(
+        Router::new()
+            .hoop(ValidationDepot::new().inject(users().await))
+            .push(router()),
+    )
+}
+
+async fn error_body(res: &mut salvo::Response) -> Value {
+    res.take_json::<Value>().await.unwrap()["errors"].take()
+}
+
+#[tokio::test]
+async fn answers_422_with_the_errors_of_every_extractor() {
+    let service = service().await;
+
+    let mut res = TestClient::post("http://localhost/signup")
+        .json(&serde_json::json!({ "email": "nope", "name": "Al" }))
+        .send(&service)
+        .await;
+    assert_eq!(res.status_code, Some(StatusCode::UNPROCESSABLE_ENTITY));
+    let errors = error_body(&mut res).await;
+    assert_eq!(errors["email"][0]["code"], "email");
+    assert_eq!(errors["name"][0]["code"], "length_min");
+    assert_eq!(errors["name"][0]["message"], "Length must be at least 3.");
+
+    let mut res = TestClient::post("http://localhost/signup-form")
+        .raw_form("email=grace%40example.com&name=G")
+        .send(&service)
+        .await;
+    assert_eq!(res.status_code, Some(StatusCode::UNPROCESSABLE_ENTITY));
+    assert_eq!(error_body(&mut res).await["name"][0]["code"], "length_min");
+
+    let mut res = TestClient::get("http://localhost/search?q=a&per_page=500")
+        .send(&service)
+        .await;
+    assert_eq!(res.status_code, Some(StatusCode::UNPROCESSABLE_ENTITY));
+    let errors = error_body(&mut res).await;
+    assert_eq!(errors["q"][0]["code"], "length_min");
+    assert_eq!(errors["per_page"][0]["code"], "max");
+
+    let res = TestClient::get("http://localhost/users/0")
+        .send(&service)
+        .await;
+    assert_eq!(res.status_code, Some(StatusCode::UNPROCESSABLE_ENTITY));
+}
+
+#[tokio::test]
+async fn hands_valid_values_to_the_handler() {
+    let service = service().await;
+
+    let mut res = TestClient::post("http://localhost/signup")
+        .json(&serde_json::json!({ "email": "grace@example.com", "name": "Grace" }))
+        .send(&service)
+        .await;
+    assert_eq!(res.status_code, Some(StatusCode::OK));
+    assert_eq!(res.take_string().await.unwrap(), "grace@example.com");
+
+    let mut res = TestClient::get("http://localhost/users/7")
+        .send(&service)
+        .await;
+    assert_eq!(res.take_string().await.unwrap(), "7");
+}
+
+#[tokio::test]
+async fn answers_400_to_what_does_not_parse() {
+    let service = service().await;
+
+    let mut res = TestClient::post("http://localhost/signup")
+        .raw_json("{\"email\": ")
+        .send(&service)
+        .await;
+    assert_eq!(res.status_code, Some(StatusCode::BAD_REQUEST));
+    assert_eq!(error_body(&mut res).await["body"][0]["code"], "parse");
+
+    let mut res = TestClient::get("http://localhost/users/ada")
+        .send(&service)
+        .await;
+    assert_eq!(res.status_code, Some(StatusCode::BAD_REQUEST));
+    assert_eq!(error_body(&mut res).await["path"][0]["code"], "parse");
+}
+
+#[tokio::test]
+async fn flattens_the_errors_for_inertia() {
+    let service = service().await;
+
+    let mut res = TestClient::post("http://localhost/signup")
+        .add_header("X-Inertia", "true", true)
+        .json(&serde_json::json!({ "email": "nope", "name": "Grace" }))
+        .send(&service)
+        .await;
+    assert_eq!(res.status_code, Some(StatusCode::UNPROCESSABLE_ENTITY));
+    assert_eq!(
+        error_body(&mut res).await,
+        serde_json::json!({ "email": "Must be a valid email address." })
+    );
+}
+
+#[tokio::test]
+async fn translates_to_the_accepted_language() {
+    MessageCatalogue::init(concat!(env!("CARGO_MANIFEST_DIR"), "/../locales")).unwrap();
+    let service = service().await;
+
+    let mut res = TestClient::post("http://localhost/signup")
+        .add_header("accept-language", "es-CL,es;q=0.9,en;q=0.8", true)
+        .add_header("X-Inertia", "true", true)
+        .json(&serde_json::json!({ "email": "nope", "name": "Al" }))
+        .send(&service)
+        .await;
+    let errors = error_body(&mut res).await;
+    assert_eq!(errors["email"], "Debe ser un correo electrónico válido.");
+    assert_eq!(errors["name"], "Debe tener al menos 3 caracteres.");
+
+    // Languages without a catalogue keep the English messages.
+    let mut res = TestClient::post("http://localhost/signup")
+        .add_header("accept-language", "fr", true)
+        .add_header("X-Inertia", "true", true)
+        .json(&serde_json::json!({ "email": "nope", "name": "Grace" }))
+        .send(&service)
+        .await;
+    assert_eq!(
+        error_body(&mut res).await["email"],
+        "Must be a valid email address."
+    );
+}
+
+#[tokio::test]
+async fn runs_the_async_rules_against_the_validation_depot() {
+    let service = service().await;
+
+    let mut res = TestClient::post("http://localhost/signup")
+        .json(&serde_json::json!({ "email": "ada@example.com", "name": "Ada" }))
+        .send(&service)
+        .await;
+    assert_eq!(res.status_code, Some(StatusCode::UNPROCESSABLE_ENTITY));
+    assert_eq!(error_body(&mut res).await["email"][0]["code"], "unique");
+
+    // Without the hoop there is no database to ask.
+    let service = Service::new(router());
+    let mut res = TestClient::post("http://localhost/signup")
+        .json(&serde_json::json!({ "email": "ada@example.com", "name": "Ada" }))
+        .send(&service)
+        .await;
+    assert_eq!(res.status_code, Some(StatusCode::INTERNAL_SERVER_ERROR));
+    assert_eq!(error_body(&mut res).await["email"][0]["code"], "database");
+}
+
+#[test]
+fn registers_query_and_path_parameters() {
+    let document = OpenApi::new("test", "0.0.1").merge_router(&router());
+    let document = serde_json::to_value(&document).unwrap();
+
+    let parameters = &document["paths"]["/search"]["get"]["parameters"];
+    let q = parameters
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|parameter| parameter["name"] == "q")
+        .unwrap();
+    assert_eq!(q["in"], "query");
+    assert_eq!(q["required"], true);
+    assert_eq!(q["schema"]["minLength"], 2);
+    let per_page = parameters
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|parameter| parameter["name"] == "per_page")
+        .unwrap();
+    assert_eq!(per_page["required"], false);
+    assert_eq!(per_page["schema"]["maximum"], 100.0);
+
+    let id = &document["paths"]["/users/{id}"]["get"]["parameters"][0];
+    assert_eq!(id["name"], "id");
+    assert_eq!(id["in"], "path");
+    assert_eq!(id["required"], true);
+    assert_eq!(id["schema"]["minimum"], 1.0);
+}
+
+#[test]
+fn documents_the_rules_in_the_body_schema() {
+    let document = OpenApi::new("test", "0.0.1").merge_router(&router());
+    let document = serde_json::to_value(&document).unwrap();
+
+    let body = &document["paths"]["/profile"]["put"]["requestBody"]["content"]["application/json"];
+    let reference = body["schema"]["$ref"].as_str().unwrap();
+    let name = reference.strip_prefix("#/components/schemas/").unwrap();
+    let properties = &document["components"]["schemas"][name]["properties"];
+
+    assert_eq!(properties["email"]["format"], "email");
+    assert_eq!(properties["name"]["minLength"], 3);
+    assert_eq!(properties["name"]["maxLength"], 40);
+    assert_eq!(properties["handle"]["pattern"], "^[a-z0-9_]+$");
+    assert_eq!(properties["age"]["minimum"], 13.0);
+    assert_eq!(properties["age"]["maximum"], 120.0);
+}
@@ -0,0 +1,173 @@
+// `#[derive(FromMultipart)]`: how the parts end up in the fields, and the errors of
+// the ones that are missing or don't parse.
+use salvo::prelude::*;
+use salvo::test::{ResponseExt, TestClient};
+use serde_json::Value;
+use thunder::{FromMultipart, MultipartValidated, UploadedFile, Validate, ValidationErrors};
+
+const BOUNDARY: &str = "X-THUNDER-BOUNDARY";
+
+// A text part, or a file part when `file_name` is given. Salvo tells them apart
+// by the `Content-Type` that browsers send with files.
+fn part(name: &str, file_name: Option<&str>, contents: &str) -> String {
+    let file_name = file_name
+        .map(|file_name| {
+            format!(
+                "; filename=\"{}\"\r\nContent-Type: application/octet-stream",
+                file_name
+            )
+        })
+        .unwrap_or_default();
+    format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"{}\"{}\r\n\r\n{}\r\n",
+        BOUNDARY, name, file_name, contents
+    )
+}
+
+fn form(parts: &[String]) -> salvo::test::RequestBuilder {
+    TestClient::post("http://localhost/posts")
+        .add_header(
+            "content-type",
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+            true,
+        )
+        .bytes(format!("{}--{}--\r\n", parts.concat(), BOUNDARY).into_bytes())
+}
+
+fn codes(errors: &ValidationErrors, field: &str) -> Vec<String> {
+    errors
+        .get(field)
+        .unwrap_or_default()
+        .iter()
+        .map(|error| error.code.clone())
+        .collect()
+}
+
+#[derive(Debug, FromMultipart, Validate)]
+struct Post {
+    #[validate(length(min = 3))]
+    title: String,
+    #[multipart(rename = "page_count")]
+    #[validate(min = 1)]
+    pages: u32,
+    subtitle: Option<String>,
+    tags: Vec<String>,
+    #[validate(file(extensions = ["png"]))]
+    cover: Option<UploadedFile>,
+    attachments: Vec<UploadedFile>,
+}
+
+#[derive(Debug, FromMultipart)]
+struct Contract {
+    document: UploadedFile,
+}
+
+#[tokio::test]
+async fn reads_every_kind_of_field() {
+    let mut req = form(&[
+        part("title", None, "Holidays"),
+        part("page_count", None, "12"),
+        part("subtitle", None, "By the sea"),
+        part("tags", None, "beach"),
+        part("tags", None, "sun"),
+        part("cover", Some("cover.png"), "png"),
+        part("attachments", Some("a.txt"), "a"),
+        part("attachments", Some("b.txt"), "b"),
+    ])
+    .build();
+    let post = Post::parse_from_multipart(&mut req).await.unwrap();
+
+    assert_eq!(post.title, "Holidays");
+    assert_eq!(post.pages, 12);
+    assert_eq!(post.subtitle.as_deref(), Some("By the sea"));
+    assert_eq!(post.tags, ["beach", "sun"]);
+    assert_eq!(post.cover.unwrap().file_name.as_deref(), Some("cover.png"));
+    let names: Vec<_> = post
+        .attachments
+        .iter()
+        .map(|file| file.file_name.clone().unwrap())
+        .collect();
+    assert_eq!(names, ["a.txt", "b.txt"]);
+}
+
+#[tokio::test]
+async fn leaves_the_optional_fields_empty() {
+    let mut req = form(&[
+        part("title", None, "Holidays"),
+        part("page_count", None, "12"),
+        // An empty optional text is no value.
+        part("subtitle", None, ""),
+    ])
+    .build();
+    let post = Post::parse_from_multipart(&mut req).await.unwrap();
+
+    assert_eq!(post.subtitle, None);
+    assert!(post.tags.is_empty());
+    assert!(post.cover.is_none());
+    assert!(post.attachments.is_empty());
+}
+
+#[tokio::test]
+async fn collects_the_missing_and_invalid_fields() {
+    let mut req = form(&[
+        // Under its form name, not `pages`.
+        part("pages", None, "12"),
+        part("page_count", None, "many"),
+    ])
+    .build();
+    let errors = Post::parse_from_multipart(&mut req).await.unwrap_err();
+
+    assert_eq!(errors.status_code(), Some(StatusCode::UNPROCESSABLE_ENTITY));
+    assert_eq!(codes(&errors, "title"), ["required"]);
+    assert_eq!(codes(&errors, "page_count"), ["invalid"]);
+    assert_eq!(errors.len(), 2);
+
+    let mut req = form(&[part("document", None, "not a file")]).build();
+    let errors = Contract::parse_from_multipart(&mut req).await.unwrap_err();
+    assert_eq!(codes(&errors, "document"), ["required"]);
+
+    let mut req = form(&[part("document", Some("contract.pdf"), "%PDF")]).build();
+    let contract = Contract::parse_from_multipart(&mut req).await.unwrap();
+    assert_eq!(contract.document.file_name.as_deref(), Some("contract.pdf"));
+}
+
+#[tokio::test]
+async fn answers_400_to_a_body_that_is_not_a_form() {
+    let mut req = TestClient::post("http://localhost/posts")
+        .raw_json("{}")
+        .build();
+    let errors = Post::parse_from_multipart(&mut req).await.unwrap_err();
+    assert_eq!(errors.status_code(), Some(StatusCode::BAD_REQUEST));
+    assert_eq!(codes(&errors, "body"), ["parse"]);
+}
+
+#[handler]
+async fn create(post: MultipartValidated<Post>) -> String {
+    post.into_inner().title
+}
+
+#[tokio::test]
+async fn validates_what_it_parsed() {
+    let service = Service::new(Router::with_path("posts").post(create));
+
+    let mut res = form(&[
+        part("title", None, "Holidays"),
+        part("page_count", None, "3"),
+    ])
+    .send(&service)
+    .await;
+    assert_eq!(res.status_code, Some(StatusCode::OK));
+    assert_eq!(res.take_string().await.unwrap(), "Holidays");
+
+    let mut res = form(&[
+        part("title", None, "Hi"),
+        part("page_count", None, "3"),
+        part("cover", Some("cover.gif"), "GIF89a"),
+    ])
+    .send(&service)
+    .await;
+    assert_eq!(res.status_code, Some(StatusCode::UNPROCESSABLE_ENTITY));
+    let errors = res.take_json::<Value>().await.unwrap()["errors"].take();
+    assert_eq!(errors["title"][0]["code"], "length_min");
+    assert_eq!(errors["cover"][0]["code"], "file_extension");
+}
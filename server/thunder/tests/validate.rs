@@ -0,0 +1,911 @@
+// The rules of `#[derive(Validate)]`, one family per section, checked through the
+// errors they report.
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use thunder::{AsyncValidate, Depot, Validate, ValidationError, ValidationErrors};
+
+// The codes of the errors of `field`, in the order the rules ran.
+fn codes(errors: &ValidationErrors, field: &str) -> Vec<String> {
+    errors
+        .get(field)
+        .unwrap_or_default()
+        .iter()
+        .map(|error| error.code.clone())
+        .collect()
+}
+
+// Field rules and `custom` / `custom_async`.
+
+#[derive(Debug, Validate)]
+struct Signup {
+    #[validate(email, length(min = 6, max = 40))]
+    email: String,
+    #[validate(min = 3, max = 20, regex = "^[a-z]+$")]
+    username: String,
+    #[validate(min = 13, max = 120)]
+    age: u8,
+    #[validate(custom = not_reserved)]
+    nickname: String,
+}
+
+fn not_reserved(value: &str) -> Result<(), ValidationError> {
+    if value == "admin" {
+        return Err(ValidationError::new("reserved", "This name is reserved."));
+    }
+    Ok(())
+}
+
+fn signup() -> Signup {
+    Signup {
+        email: "ada@example.com".to_string(),
+        username: "ada".to_string(),
+        age: 36,
+        nickname: "countess".to_string(),
+    }
+}
+
+#[test]
+fn accepts_valid_fields() {
+    assert!(signup().validate().is_ok());
+}
+
+#[test]
+fn reports_every_failing_field_rule() {
+    let errors = Signup {
+        email: "ada".to_string(),
+        username: "Ada Lovelace of the Analytical Engine".to_string(),
+        age: 7,
+        nickname: "admin".to_string(),
+    }
+    .validate()
+    .unwrap_err();
+
+    assert_eq!(codes(&errors, "email"), ["email", "length_min"]);
+    assert_eq!(codes(&errors, "username"), ["length_max", "regex"]);
+    assert_eq!(codes(&errors, "age"), ["min"]);
+    assert_eq!(codes(&errors, "nickname"), ["reserved"]);
+    assert_eq!(errors.len(), 6);
+}
+
+#[test]
+fn fills_the_message_params() {
+    let errors = Signup { age: 7, ..signup() }.validate().unwrap_err();
+    assert_eq!(errors.first("age").unwrap().message, "Must be at least 13.");
+}
+
+#[derive(Debug, Validate)]
+struct Comment {
+    #[validate(custom_async = not_banned)]
+    author: String,
+}
+
+struct Banned(Vec<String>);
+
+async fn not_banned(value: &str, depot: &Depot) -> Result<(), ValidationError> {
+    let banned = depot.obtain::<Banned>().expect("Banned is injected");
+    if banned.0.iter().any(|name| name == value) {
+        return Err(ValidationError::new("banned", "This author is banned."));
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn runs_custom_async_rules_with_the_depot() {
+    let mut depot = Depot::new();
+    depot.inject(Banned(vec!["mallory".to_string()]));
+
+    let comment = Comment {
+        author: "mallory".to_string(),
+    };
+    // The synchronous rules know nothing about it.
+    assert!(comment.validate().is_ok());
+    let errors = comment.validate_async(&depot).await.unwrap_err();
+    assert_eq!(codes(&errors, "author"), ["banned"]);
+
+    let comment = Comment {
+        author: "alice".to_string(),
+    };
+    assert!(comment.validate_async(&depot).await.is_ok());
+}
+
+// Generic fields that are validated get the bound they need, and nothing more.
+#[derive(Debug, Validate)]
+struct Page<T> {
+    #[validate(nested)]
+    items: Vec<T>,
+    #[validate(min = 1)]
+    number: u32,
+}
+
+#[tokio::test]
+async fn validates_generic_nested_fields() {
+    let page = Page {
+        items: vec![signup(), Signup { age: 7, ..signup() }],
+        number: 0,
+    };
+    let errors = page.validate().unwrap_err();
+    assert_eq!(codes(&errors, "items.1.age"), ["min"]);
+    assert_eq!(codes(&errors, "number"), ["min"]);
+    assert!(page.validate_async(&Depot::new()).await.is_err());
+}
+
+// Not `Sync`, so only `Validate` applies.
+#[derive(Debug, Validate)]
+struct Draft {
+    #[validate(length(max = 5))]
+    title: String,
+    _cache: std::cell::Cell<u32>,
+}
+
+#[test]
+fn derives_for_types_that_are_not_sync() {
+    let draft = Draft {
+        title: "A long title".to_string(),
+        _cache: std::cell::Cell::new(0),
+    };
+    assert_eq!(
+        codes(&draft.validate().unwrap_err(), "title"),
+        ["length_max"]
+    );
+}
+
+// `unique` and `exists`, against the `DatabaseConnection` in the depot.
+
+mod user {
+    use sea_orm::entity::prelude::*;
+
+    #[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel)]
+    #[sea_orm(table_name = "users")]
+    pub struct Model {
+        #[sea_orm(primary_key)]
+        pub id: i32,
+        pub email: String,
+    }
+
+    #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+    pub enum Relation {}
+
+    impl ActiveModelBehavior for ActiveModel {}
+}
+
+#[derive(Debug, Validate)]
+struct UpdateEmail {
+    id: i32,
+    #[validate(unique(entity = user::Entity, ignore = "id"))]
+    email: String,
+}
+
+#[derive(Debug, Validate)]
+struct Invite {
+    #[validate(exists(entity = user::Entity, column = "id"))]
+    invited_by: i32,
+}
+
+async fn users() -> Depot {
+    use sea_orm::{ConnectionTrait, Database};
+
+    let db = Database::connect("sqlite::memory:").await.unwrap();
+    db.execute_unprepared(
+        "CREATE TABLE users (id INTEGER PRIMARY KEY, email TEXT NOT NULL);
+         INSERT INTO users (id, email) VALUES (1, 'ada@example.com');",
+    )
+    .await
+    .unwrap();
+    let mut depot = Depot::new();
+    depot.inject(db);
+    depot
+}
+
+#[tokio::test]
+async fn checks_unique_values_in_the_database() {
+    let depot = users().await;
+
+    let taken = UpdateEmail {
+        id: 2,
+        email: "ada@example.com".to_string(),
+    };
+    let errors = taken.validate_async(&depot).await.unwrap_err();
+    assert_eq!(codes(&errors, "email"), ["unique"]);
+
+    // The record being edited doesn't count.
+    let own = UpdateEmail {
+        id: 1,
+        email: "ada@example.com".to_string(),
+    };
+    assert!(own.validate_async(&depot).await.is_ok());
+
+    let free = UpdateEmail {
+        id: 2,
+        email: "grace@example.com".to_string(),
+    };
+    assert!(free.validate_async(&depot).await.is_ok());
+}
+
+#[tokio::test]
+async fn checks_existing_values_in_the_database() {
+    let depot = users().await;
+
+    assert!(
+        Invite { invited_by: 1 }
+            .validate_async(&depot)
+            .await
+            .is_ok()
+    );
+    let errors = Invite { invited_by: 2 }
+        .validate_async(&depot)
+        .await
+        .unwrap_err();
+    assert_eq!(codes(&errors, "invited_by"), ["exists"]);
+}
+
+#[tokio::test]
+async fn answers_500_without_a_database() {
+    let errors = Invite { invited_by: 1 }
+        .validate_async(&Depot::new())
+        .await
+        .unwrap_err();
+    assert_eq!(codes(&errors, "invited_by"), ["database"]);
+    assert_eq!(
+        errors.first("invited_by").unwrap().message,
+        "The value could not be checked."
+    );
+    assert_eq!(
+        errors.status_code(),
+        Some(thunder::StatusCode::INTERNAL_SERVER_ERROR)
+    );
+}
+
+// `nested`, with the errors under dotted paths.
+
+#[derive(Debug, Validate)]
+struct Address {
+    #[validate(length(min = 4))]
+    zip: String,
+}
+
+#[derive(Debug, Validate)]
+struct Order {
+    #[validate(nested)]
+    shipping: Address,
+    #[validate(nested)]
+    billing: Option<Address>,
+    #[validate(nested)]
+    stops: Vec<Address>,
+    #[validate(nested)]
+    labels: std::collections::HashMap<String, Address>,
+}
+
+fn address(zip: &str) -> Address {
+    Address {
+        zip: zip.to_string(),
+    }
+}
+
+#[test]
+fn reports_nested_errors_under_dotted_paths() {
+    let order = Order {
+        shipping: address("1"),
+        billing: Some(address("2")),
+        stops: vec![address("10115"), address("3")],
+        labels: [("home".to_string(), address("4"))].into(),
+    };
+    let errors = order.validate().unwrap_err();
+
+    assert_eq!(codes(&errors, "shipping.zip"), ["length_min"]);
+    assert_eq!(codes(&errors, "billing.zip"), ["length_min"]);
+    assert_eq!(codes(&errors, "stops.1.zip"), ["length_min"]);
+    assert_eq!(codes(&errors, "labels.home.zip"), ["length_min"]);
+    assert!(!errors.has("stops.0.zip"));
+    assert_eq!(errors.len(), 4);
+}
+
+#[test]
+fn accepts_valid_nested_values() {
+    let order = Order {
+        shipping: address("10115"),
+        billing: None,
+        stops: Vec::new(),
+        labels: Default::default(),
+    };
+    assert!(order.validate().is_ok());
+}
+
+// Cross-field rules: `must_match`, `different`, `required_if` and `required_with`.
+
+#[derive(Debug, Validate)]
+#[validate(different(field = "password", other = "username"))]
+struct Register {
+    username: String,
+    password: String,
+    #[validate(must_match = "password")]
+    password_confirmation: String,
+    ship: bool,
+    #[validate(required_if(field = "ship", value = true))]
+    address: Option<String>,
+    #[validate(required_with = "address")]
+    zip: Option<String>,
+}
+
+fn register() -> Register {
+    Register {
+        username: "ada".to_string(),
+        password: "analytical".to_string(),
+        password_confirmation: "analytical".to_string(),
+        ship: true,
+        address: Some("12 St James's Square".to_string()),
+        zip: Some("SW1Y 4JH".to_string()),
+    }
+}
+
+#[test]
+fn accepts_consistent_fields() {
+    assert!(register().validate().is_ok());
+    let pickup = Register {
+        ship: false,
+        address: None,
+        zip: None,
+        ..register()
+    };
+    assert!(pickup.validate().is_ok());
+}
+
+#[test]
+fn reports_cross_field_errors_on_the_field_to_fix() {
+    let errors = Register {
+        password: "ada".to_string(),
+        password_confirmation: "engine".to_string(),
+        address: None,
+        ..register()
+    }
+    .validate()
+    .unwrap_err();
+    assert_eq!(codes(&errors, "password"), ["different"]);
+    assert_eq!(codes(&errors, "password_confirmation"), ["must_match"]);
+    assert_eq!(
+        errors.first("password_confirmation").unwrap().message,
+        "Must match password."
+    );
+    assert_eq!(codes(&errors, "address"), ["required_if"]);
+
+    let errors = Register {
+        zip: None,
+        ..register()
+    }
+    .validate()
+    .unwrap_err();
+    assert_eq!(codes(&errors, "zip"), ["required_with"]);
+}
+
+// `message` and `code` overrides, message params and the locale catalogues.
+
+#[derive(Debug, Validate)]
+struct Profile {
+    #[validate(min(value = 3, message = "Pick a longer name."))]
+    username: String,
+    #[validate(length(max = 10), message = "Keep it short.", code = "too_long")]
+    bio: String,
+    #[validate(custom(function = not_reserved, code = "taken"))]
+    handle: String,
+}
+
+#[test]
+fn applies_message_and_code_overrides() {
+    let errors = Profile {
+        username: "al".to_string(),
+        bio: "An enchantress of numbers".to_string(),
+        handle: "admin".to_string(),
+    }
+    .validate()
+    .unwrap_err();
+
+    let username = errors.first("username").unwrap();
+    assert_eq!(username.code, "length_min");
+    assert_eq!(username.message, "Pick a longer name.");
+    let bio = errors.first("bio").unwrap();
+    assert_eq!(bio.code, "too_long");
+    assert_eq!(bio.message, "Keep it short.");
+    let handle = errors.first("handle").unwrap();
+    assert_eq!(handle.code, "taken");
+    assert_eq!(handle.message, "This name is reserved.");
+}
+
+#[test]
+fn keeps_the_params_for_the_catalogues() {
+    let errors = Signup {
+        email: "a@b.c".to_string(),
+        ..signup()
+    }
+    .validate()
+    .unwrap_err();
+    let error = errors.first("email").unwrap();
+    assert_eq!(error.message, "Length must be at least 6.");
+    assert_eq!(error.params["min"], 6);
+
+    let catalogue =
+        thunder::MessageCatalogue::load(concat!(env!("CARGO_MANIFEST_DIR"), "/../locales"))
+            .unwrap();
+    let template = catalogue.message("ES", &error.code).unwrap();
+    assert_eq!(
+        error.interpolate(template),
+        "Debe tener al menos 6 caracteres."
+    );
+    assert_eq!(catalogue.negotiate("es-CL,es;q=0.9,en;q=0.8"), Some("es"));
+}
+
+// `file` and `image`, on files written to the temp directory.
+
+// A PNG header of the given size; enough for sniffing and for the dimensions.
+fn png(width: u32, height: u32) -> Vec<u8> {
+    let mut bytes = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+    bytes.extend_from_slice(&width.to_be_bytes());
+    bytes.extend_from_slice(&height.to_be_bytes());
+    bytes.extend_from_slice(&[8, 2, 0, 0, 0, 0, 0, 0, 0]);
+    bytes
+}
+
+fn upload(file_name: &str, contents: &[u8]) -> thunder::UploadedFile {
+    let path = std::env::temp_dir().join(format!("thunder-upload-{}", uuid::Uuid::new_v4()));
+    std::fs::write(&path, contents).unwrap();
+    thunder::UploadedFile {
+        path,
+        file_name: Some(file_name.to_string()),
+        // Whatever the client claims is never trusted.
+        content_type: Some("image/png".parse().unwrap()),
+    }
+}
+
+#[derive(Debug, Validate)]
+struct Avatar {
+    #[validate(
+        file(max_size = "1KB", mime = ["image/png"], extensions = ["png"]),
+        image(min_width = 64, max_width = 512, ratio = "1/1")
+    )]
+    picture: thunder::UploadedFile,
+    #[validate(file(extensions = ["pdf", "txt"]))]
+    attachments: Vec<thunder::UploadedFile>,
+}
+
+impl Drop for Avatar {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.picture.path);
+        for attachment in &self.attachments {
+            let _ = std::fs::remove_file(&attachment.path);
+        }
+    }
+}
+
+#[tokio::test]
+async fn accepts_files_that_fit_the_rules() {
+    let avatar = Avatar {
+        picture: upload("me.PNG", &png(128, 128)),
+        attachments: vec![upload("cv.pdf", b"%PDF-1.7")],
+    };
+    assert!(avatar.validate_async(&Depot::new()).await.is_ok());
+}
+
+#[tokio::test]
+async fn checks_the_content_rather_than_the_claims() {
+    let avatar = Avatar {
+        picture: upload("me.png", &[b'<'; 2000]),
+        attachments: vec![upload("cv.pdf", b"%PDF-1.7"), upload("run.exe", b"MZ")],
+    };
+    let errors = avatar.validate_async(&Depot::new()).await.unwrap_err();
+    assert_eq!(
+        codes(&errors, "picture"),
+        ["file_max_size", "file_mime", "image"]
+    );
+    assert_eq!(codes(&errors, "attachments"), ["file_extension"]);
+}
+
+#[tokio::test]
+async fn checks_image_dimensions() {
+    let avatar = Avatar {
+        picture: upload("me.png", &png(640, 480)),
+        attachments: Vec::new(),
+    };
+    let errors = avatar.validate_async(&Depot::new()).await.unwrap_err();
+    assert_eq!(codes(&errors, "picture"), ["image_dimensions"]);
+    assert_eq!(errors.first("picture").unwrap().params["width"], 640);
+}
+
+// Enums, tuple structs and newtypes.
+
+#[derive(Debug, Validate)]
+struct EmailAddress(#[validate(email)] String);
+
+#[derive(Debug, Validate)]
+struct Range(#[validate(min = 0)] i32, #[validate(max = 100)] i32);
+
+#[derive(Debug, Validate)]
+enum Contact {
+    Email(#[validate(nested)] EmailAddress),
+    Phone {
+        #[validate(length(min = 7, max = 15))]
+        number: String,
+    },
+    Anonymous,
+}
+
+#[derive(Debug, Validate)]
+struct Subscribe {
+    #[validate(nested)]
+    email: EmailAddress,
+    #[validate(nested)]
+    contacts: Vec<Contact>,
+}
+
+#[test]
+fn reports_newtype_errors_on_the_field_itself() {
+    let errors = EmailAddress("ada".to_string()).validate().unwrap_err();
+    assert_eq!(codes(&errors, ""), ["email"]);
+
+    let errors = Range(-1, 101).validate().unwrap_err();
+    assert_eq!(codes(&errors, "0"), ["min"]);
+    assert_eq!(codes(&errors, "1"), ["max"]);
+}
+
+#[test]
+fn validates_the_fields_of_the_variant() {
+    let subscribe = Subscribe {
+        email: EmailAddress("ada".to_string()),
+        contacts: vec![
+            Contact::Anonymous,
+            Contact::Email(EmailAddress("grace".to_string())),
+            Contact::Phone {
+                number: "123".to_string(),
+            },
+            Contact::Phone {
+                number: "+44 20 7946 0000".to_string(),
+            },
+        ],
+    };
+    let errors = subscribe.validate().unwrap_err();
+    assert_eq!(codes(&errors, "email"), ["email"]);
+    assert_eq!(codes(&errors, "contacts.1"), ["email"]);
+    assert_eq!(codes(&errors, "contacts.2.number"), ["length_min"]);
+    assert_eq!(codes(&errors, "contacts.3.number"), ["length_max"]);
+    assert_eq!(errors.len(), 4);
+}
+
+// `sanitize(...)`, applied in order before the rules.
+
+#[derive(Debug, Validate)]
+struct Post {
+    #[validate(sanitize(strip_tags, collapse_whitespace, trim), length(min = 3))]
+    title: String,
+    #[validate(sanitize(trim, lowercase), email)]
+    author: Option<String>,
+    #[validate(sanitize(trim))]
+    tags: Vec<String>,
+    #[validate(nested)]
+    contact: EmailAddress,
+}
+
+#[derive(Debug, Validate)]
+struct Trimmed(#[validate(sanitize(trim, lowercase))] String);
+
+#[test]
+fn sanitizes_fields_in_order() {
+    let mut post = Post {
+        title: "  <b>Notes</b>\n on   the <i>Engine</i> ".to_string(),
+        author: Some(" Ada@Example.COM ".to_string()),
+        tags: vec![" math ".to_string(), "history".to_string()],
+        contact: EmailAddress("ada@example.com".to_string()),
+    };
+    post.sanitize();
+    assert_eq!(post.title, "Notes on the Engine");
+    assert_eq!(post.author.as_deref(), Some("ada@example.com"));
+    assert_eq!(post.tags, ["math", "history"]);
+    assert!(post.validate().is_ok());
+
+    let mut trimmed = Trimmed(" ABC ".to_string());
+    trimmed.sanitize();
+    assert_eq!(trimmed.0, "abc");
+}
+
+#[test]
+fn validates_what_is_left_after_sanitizing() {
+    let mut post = Post {
+        title: "<p> </p>".to_string(),
+        author: None,
+        tags: Vec::new(),
+        contact: EmailAddress("ada@example.com".to_string()),
+    };
+    post.sanitize();
+    let errors = post.validate().unwrap_err();
+    assert_eq!(codes(&errors, "title"), ["length_min"]);
+}
+
+// The error collection: lookup, merging and the two serialized formats.
+
+#[test]
+fn collects_errors_per_field() {
+    let mut errors = ValidationErrors::new();
+    errors.add("email", "email", "Must be a valid email address.");
+    errors.add("email", "length_min", "Length must be at least 6.");
+    let mut address = ValidationErrors::new();
+    address.add("zip", "required", "This field is required.");
+    errors.merge_nested("address", address);
+    let mut other = ValidationErrors::new();
+    other.add("name", "required", "This field is required.");
+    errors.merge(other);
+
+    assert_eq!(errors.len(), 4);
+    assert_eq!(codes(&errors, "email"), ["email", "length_min"]);
+    assert_eq!(errors.first("address.zip").unwrap().code, "required");
+    assert!(errors.has("name"));
+    assert!(errors.get("zip").is_none());
+    assert_eq!(errors.iter().count(), 3);
+}
+
+#[test]
+fn serializes_in_the_full_and_flat_formats() {
+    let mut errors = ValidationErrors::new();
+    errors.add("email", "email", "Must be a valid email address.");
+    errors.add("email", "length_min", "Length must be at least 6.");
+
+    assert_eq!(
+        serde_json::to_value(&errors).unwrap(),
+        serde_json::json!({"errors": {"email": [
+            {"code": "email", "message": "Must be a valid email address."},
+            {"code": "length_min", "message": "Length must be at least 6."},
+        ]}})
+    );
+    let errors = errors.with_format(thunder::ErrorFormat::Flat);
+    assert_eq!(
+        serde_json::to_value(&errors).unwrap(),
+        serde_json::json!({"errors": {"email": "Must be a valid email address."}})
+    );
+}
+
+// Validation groups.
+
+#[derive(Debug, Validate)]
+struct Account {
+    #[validate(length(min = 3))]
+    #[validate(required, groups = ["create"])]
+    name: Option<String>,
+    #[validate(length(min = 8), groups = ["create", "password"])]
+    password: String,
+    #[validate(nested)]
+    address: Address,
+}
+
+#[derive(Debug, Validate)]
+struct Checkout {
+    #[validate(nested)]
+    account: Account,
+}
+
+fn account() -> Account {
+    Account {
+        name: None,
+        password: "short".to_string(),
+        address: address("1"),
+    }
+}
+
+#[test]
+fn runs_group_rules_only_when_the_group_is_selected() {
+    let errors = account().validate().unwrap_err();
+    assert!(!errors.has("name"));
+    assert!(!errors.has("password"));
+    // Rules without a group always run.
+    assert_eq!(codes(&errors, "address.zip"), ["length_min"]);
+
+    let errors = account().validate_group("create").unwrap_err();
+    assert_eq!(codes(&errors, "name"), ["required"]);
+    assert_eq!(codes(&errors, "password"), ["length_min"]);
+    assert_eq!(codes(&errors, "address.zip"), ["length_min"]);
+
+    let errors = account().validate_group("password").unwrap_err();
+    assert!(!errors.has("name"));
+    assert_eq!(codes(&errors, "password"), ["length_min"]);
+}
+
+#[test]
+fn passes_the_group_to_nested_values() {
+    let checkout = Checkout { account: account() };
+    assert!(!checkout.validate().unwrap_err().has("account.password"));
+    let errors = checkout.validate_group("create").unwrap_err();
+    assert_eq!(codes(&errors, "account.name"), ["required"]);
+    assert_eq!(codes(&errors, "account.password"), ["length_min"]);
+}
+
+// `required` and `nullable`, and the other rules on missing values.
+
+#[derive(Debug, Validate)]
+struct Listing {
+    #[validate(required, length(min = 5))]
+    title: String,
+    #[validate(required)]
+    photos: Vec<String>,
+    #[validate(length(max = 10))]
+    subtitle: Option<String>,
+    #[validate(nullable, email)]
+    contact: Option<String>,
+    #[validate(min = 1)]
+    price: Option<u32>,
+}
+
+fn listing() -> Listing {
+    Listing {
+        title: "Bicycle".to_string(),
+        photos: vec!["bike.jpg".to_string()],
+        subtitle: None,
+        contact: None,
+        price: None,
+    }
+}
+
+#[test]
+fn reports_only_required_for_a_missing_value() {
+    let errors = Listing {
+        title: "   ".to_string(),
+        photos: Vec::new(),
+        ..listing()
+    }
+    .validate()
+    .unwrap_err();
+    assert_eq!(codes(&errors, "title"), ["required"]);
+    assert_eq!(codes(&errors, "photos"), ["required"]);
+
+    let errors = Listing {
+        title: "Bike".to_string(),
+        ..listing()
+    }
+    .validate()
+    .unwrap_err();
+    assert_eq!(codes(&errors, "title"), ["length_min"]);
+}
+
+#[test]
+fn skips_the_rules_of_missing_optional_values() {
+    assert!(listing().validate().is_ok());
+    // Blank is only missing with `nullable`.
+    let blank = Listing {
+        subtitle: Some(String::new()),
+        contact: Some(" ".to_string()),
+        ..listing()
+    };
+    assert!(blank.validate().is_ok());
+
+    let errors = Listing {
+        subtitle: Some("A very fine bicycle".to_string()),
+        contact: Some("nobody".to_string()),
+        price: Some(0),
+        ..listing()
+    }
+    .validate()
+    .unwrap_err();
+    assert_eq!(codes(&errors, "subtitle"), ["length_max"]);
+    assert_eq!(codes(&errors, "contact"), ["email"]);
+    assert_eq!(codes(&errors, "price"), ["min"]);
+}
+
+// `after`, `before`, `after_or_equal`, `before_or_equal`, `future` and `past`.
+
+#[derive(Debug, Validate)]
+struct Booking {
+    #[validate(future)]
+    start: DateTime<Utc>,
+    #[validate(after = "start", before = "2100-01-01")]
+    end: Option<DateTime<Utc>>,
+    #[validate(past, after_or_equal = "1900-01-01")]
+    born_on: NaiveDate,
+    #[validate(before_or_equal = "today")]
+    signed_on: Option<NaiveDate>,
+    #[validate(after = "now")]
+    remind_at: Option<NaiveDateTime>,
+}
+
+fn booking() -> Booking {
+    let start = Utc::now() + Duration::days(1);
+    Booking {
+        start,
+        end: Some(start + Duration::days(2)),
+        born_on: NaiveDate::from_ymd_opt(1900, 1, 1).unwrap(),
+        signed_on: Some(Utc::now().date_naive()),
+        remind_at: Some(start.naive_utc()),
+    }
+}
+
+#[test]
+fn accepts_dates_within_their_limits() {
+    assert!(booking().validate().is_ok());
+    let open = Booking {
+        end: None,
+        signed_on: None,
+        remind_at: None,
+        ..booking()
+    };
+    assert!(open.validate().is_ok());
+}
+
+#[test]
+fn reports_dates_outside_their_limits() {
+    let start = Utc::now() - Duration::days(1);
+    let errors = Booking {
+        start,
+        end: Some(start - Duration::hours(1)),
+        born_on: NaiveDate::from_ymd_opt(1899, 12, 31).unwrap(),
+        signed_on: Some(Utc::now().date_naive() + Duration::days(1)),
+        remind_at: Some(start.naive_utc()),
+    }
+    .validate()
+    .unwrap_err();
+    assert_eq!(codes(&errors, "start"), ["future"]);
+    assert_eq!(codes(&errors, "end"), ["after"]);
+    assert_eq!(codes(&errors, "born_on"), ["after_or_equal"]);
+    assert_eq!(codes(&errors, "signed_on"), ["before_or_equal"]);
+    assert_eq!(codes(&errors, "remind_at"), ["after"]);
+
+    let errors = Booking {
+        end: "2100-01-01T00:00:00Z".parse().ok(),
+        ..booking()
+    }
+    .validate()
+    .unwrap_err();
+    assert_eq!(codes(&errors, "end"), ["before"]);
+    assert_eq!(
+        errors.first("end").unwrap().message,
+        "Must be a date before 2100-01-01."
+    );
+}
+
+// `password`.
+
+#[derive(Debug, Validate)]
+struct ChangePassword {
+    #[validate(password(min_len = 12, mixed_case, numbers, symbols, not_compromised_local_list))]
+    password: String,
+    #[validate(password)]
+    pin: String,
+}
+
+#[test]
+fn checks_password_strength() {
+    let strong = ChangePassword {
+        password: "Correct-Horse-7-Battery".to_string(),
+        pin: "12345678x".to_string(),
+    };
+    assert!(strong.validate().is_ok());
+
+    let errors = ChangePassword {
+        password: "horse".to_string(),
+        pin: "1234".to_string(),
+    }
+    .validate()
+    .unwrap_err();
+    assert_eq!(
+        codes(&errors, "password"),
+        [
+            "password_min_len",
+            "password_mixed_case",
+            "password_numbers",
+            "password_symbols",
+        ]
+    );
+    // Only the length is checked by default.
+    assert_eq!(codes(&errors, "pin"), ["password_min_len"]);
+}
+
+#[derive(Debug, Validate)]
+struct Passphrase {
+    #[validate(password(min_len = 12, not_compromised_local_list))]
+    passphrase: String,
+}
+
+#[test]
+fn refuses_compromised_passwords_whatever_their_case() {
+    let errors = Passphrase {
+        passphrase: "Password1234".to_string(),
+    }
+    .validate()
+    .unwrap_err();
+    assert_eq!(codes(&errors, "passphrase"), ["password_compromised"]);
+
+    let fresh = Passphrase {
+        passphrase: "paraffin lamp overture".to_string(),
+    };
+    assert!(fresh.validate().is_ok());
+}